@@ -47,12 +47,13 @@ impl Bucket {
         maps: &'a Maps<S>,
         exp_tx: &'a Sender<ExpCMD>,
     ) -> impl Future<Output = Result<CacheEntry>> + Send + use<'a, RT, S> {
+        let ttl = self.ttl;
         let rotation_future = match self.needs_rotate(now) {
             Ok(path) => Either::Left(async move { // this needs to be a future so the new partition creation can be awaited.
                 // ensures the guard will be released if the future is dropped or function returns early.
                 let drop_guard = defer(|| maps.buckets.pin().get(&bucket_id).map(Bucket::rel_rotate));                
 
-                let partition = PendingPartition::new::<RT>(now, path).await?;
+                let partition = PendingPartition::new::<RT>(now, ttl, path).await?;
                 let new_key = partition.insert_into(&maps.partitions)?;
                 
                 let bucket_guard = maps.buckets.guard();
@@ -111,7 +112,7 @@ impl Bucket {
         let partition = RT::spawn_blocking(move || {
             fs::create_dir_all(&create_path)?;
             let part_path = create_path.join(now.to_string());
-            PendingPartition::new_sync(now, part_path)
+            PendingPartition::new_sync(now, ttl, part_path)
         }).await??;
         
         let par_key = partition.insert_into(partition_map)?;
@@ -125,6 +126,11 @@ impl Bucket {
         })
     }
 
+    /// Key of the partition currently taking writes.
+    pub(crate) fn live_partition_key(&self) -> usize {
+        self.live_partition.load(Ordering::Relaxed).0
+    }
+
     /// Acquires the rotation guard, returning `true` if the guard was successfully acquired.
     #[inline]
     pub(crate) fn acq_rotate(&self) -> bool {
@@ -1,24 +1,35 @@
-use std::{fs, hash::{BuildHasher, RandomState}, marker::PhantomData, path::{Path, PathBuf}, sync::Arc, time::Duration};
+use std::{collections::HashSet, fs, hash::{BuildHasher, RandomState}, marker::PhantomData, path::{Path, PathBuf}, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
 
 use bytes::Bytes;
 use flume::Sender;
 use futures_util::{StreamExt, stream::FuturesUnordered};
 use papaya::{HashMap, Operation};
 use sharded_slab::Slab;
+use simple_defer::defer;
 
-use crate::{Result, bucket::{ActivePartition, Bucket}, error::Error, expiration_queue::{ExpCMD, run_expiration_task}, partition::{Partition, PartitionEntry}, runtime::Runtime, sized_bytes::SizedBytes, unix_secs};
+use crate::{Result, bucket::{ActivePartition, Bucket}, error::Error, expiration_queue::{ExpCMD, run_expiration_task}, metrics::{Metrics, Observer}, partition::{Partition, PartitionEntry}, runtime::Runtime, sized_bytes::SizedBytes, unix_secs};
 
 pub(crate) trait ViableHasher: BuildHasher + Default + Send + Sync + 'static {}
 impl<T: BuildHasher + Default + Send + Sync + 'static> ViableHasher for T {}
 
 pub(crate) struct Entry {
     pub key: SizedBytes,
-    pub value: Bytes,
+    /// `None` for a tombstone, recording the key's removal.
+    pub value: Option<Bytes>,
 }
 
 impl Entry {
     pub fn new(key: impl Into<SizedBytes>, value: impl Into<Bytes>) -> Self {
-        Self { key: key.into(), value: value.into() }
+        Self { key: key.into(), value: Some(value.into()) }
+    }
+
+    pub fn tombstone(key: SizedBytes) -> Self {
+        Self { key, value: None }
+    }
+
+    /// Length of the entry once written, including its length prefixes.
+    pub fn stored_len(&self) -> u64 {
+        (2 * size_of::<u64>() + self.key.len() + self.value.as_ref().map_or(0, Bytes::len)) as u64
     }
 }
 
@@ -41,6 +52,33 @@ impl CacheEntry {
     }
 }
 
+/// Where and when an entry was stored, as reported by [`Database::inspect`].
+#[derive(Clone, Copy, Debug)]
+pub struct EntryInfo {
+    /// ttl of the bucket holding the entry.
+    pub bucket: Duration,
+    /// unix time in seconds the entry's partition was created.
+    /// entries are written within a minute of their partition's creation.
+    pub partition: u64,
+    /// unix time in seconds the entry's partition is scheduled to be purged.
+    pub expires_at: u64,
+    /// length in bytes of the stored value.
+    pub len: usize,
+}
+
+/// What [`Database::close`] did.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CloseSummary {
+    /// inserts in progress when the database was closed that finished before the deadline.
+    pub writes_finished: usize,
+    /// inserts still in progress at the deadline, their values may be partially written.
+    pub writes_abandoned: usize,
+    /// partition files synced to disk.
+    pub partitions_synced: usize,
+    /// purges the expiration task had started, and finished before stopping.
+    pub purges_finished: usize,
+}
+
 /// Lifetime managed key-value store.
 /// Async down to file io (handled by input runtime)
 /// Expirations are delegated to a background expiration task and batched by a 1 minute window
@@ -59,6 +97,9 @@ pub(crate) struct Maps<S: ViableHasher = RandomState> {
     pub partitions: Slab<Partition>,
     pub entries: HashMap<SizedBytes, CacheEntry, S>,
     pub buckets: HashMap<u64, Bucket, S>,
+    pub metrics: Metrics,
+    /// set by `Database::close`, inserts are rejected once it is.
+    pub closed: AtomicBool,
 }
 
 impl<S: ViableHasher> Maps<S> {
@@ -67,6 +108,8 @@ impl<S: ViableHasher> Maps<S> {
             partitions: Slab::new(),
             entries: HashMap::with_hasher(S::default()),
             buckets: HashMap::with_hasher(S::default()),
+            metrics: Metrics::new(),
+            closed: AtomicBool::new(false),
         }
     }
 }
@@ -118,13 +161,14 @@ impl<RT: Runtime, S: BuildHasher + Default + Send + Sync + 'static> Database<RT,
                     let Some(insert_time) = entry.file_name().into_string().ok().and_then(|n| n.parse::<u64>().ok()) else { continue };
 
                     partition_futures.push(async move {
-                        let partition_res = RT::spawn_blocking(move || Partition::from_file(insert_time, entry.path())).await.flatten();
+                        let partition_res = RT::spawn_blocking(move || Partition::from_file(insert_time, bucket_ttl, entry.path())).await.flatten();
                         (insert_time, partition_res)
                     });
                 }
                 
                 while let Some((insert_time, partition_res)) = partition_futures.next().await {
                     let (keys, partition) = partition_res?;
+                    inner_ref.metrics.disk_bytes.fetch_add(partition.len(), Ordering::Relaxed);
 
                     let par_key = partition.insert_into(&inner_ref.partitions)?;
                     queue_tx_ref.send(ExpCMD::Schedule { time: insert_time + bucket_id, par_key }).map_err(Error::queue)?;
@@ -158,6 +202,8 @@ impl<RT: Runtime, S: BuildHasher + Default + Send + Sync + 'static> Database<RT,
         while let Some(res) = bucket_futures.next().await {
             res?;
         }
+        // tombstones took part in picking each key's newest entry, and only hide the values they replaced from here on.
+        maps.entries.pin().retain(|_, entry| !entry.position.is_tombstone());
 
         drop(bucket_futures); // this drops the references to the inner and queue_tx
         RT::spawn(run_expiration_task::<RT, S>(maps.clone(), rx));
@@ -179,14 +225,23 @@ impl<RT: Runtime, S: BuildHasher + Default + Send + Sync + 'static> Database<RT,
     /// Old values will remain on disk until their original ttl has expired.
     /// 
     /// # Errors
-    /// Returns an error if any io operations failed or a spawned task returns an error.
+    /// Returns an error if any io operations failed, a spawned task returns an error, or the database was closed.
     #[allow(clippy::used_underscore_items)]
     pub async fn insert(&self, key: impl Into<SizedBytes>, value: impl Into<Bytes>, ttl: Duration) -> Result<()> {
+        // counted before checking `closed`, so `close` either sees this write or this write sees it closed.
+        self.maps.metrics.writes.fetch_add(1, Ordering::SeqCst);
+        let _finished = defer(|| self.maps.metrics.writes.fetch_sub(1, Ordering::SeqCst));
+        if self.maps.closed.load(Ordering::SeqCst) {
+            return Err(Error::CLOSED)
+        }
+
+        let start = Instant::now();
         let now = unix_secs();
         let cache_id = ttl.as_secs();
 
         let entry = Entry::new(key, value);
         let entry_key = entry.key.clone();
+        let len = entry.stored_len();
         
         let new_bucket = if self.maps.buckets.pin().contains_key(&cache_id) { None } else {
             let path = self.path.join(ttl.as_millis().to_string());
@@ -206,6 +261,7 @@ impl<RT: Runtime, S: BuildHasher + Default + Send + Sync + 'static> Database<RT,
         
         let cache_entry = insert_future.await?;
         self.maps.entries.pin().insert(entry_key, cache_entry);
+        self.maps.metrics.write(start.elapsed(), len);
         Ok(())
     }
     
@@ -216,24 +272,253 @@ impl<RT: Runtime, S: BuildHasher + Default + Send + Sync + 'static> Database<RT,
     /// Returns an error if any io operations failed or a spawned task returns an error.
     #[allow(clippy::used_underscore_items)]
     pub async fn read(&self, key: impl Into<SizedBytes>) -> Result<Option<Bytes>> {
+        let start = Instant::now();
         let entry_key = key.into();
 
-        let Some(CacheEntry { partition_key, position }) = self.maps.entries.pin().get(&entry_key).copied() else {
-            return Ok(None)
-        };
-
-        let Some(read_future) = self.maps.partitions.get(partition_key).map(|p| p.read::<RT>(position)) else {
+        let read_future = self.maps.entries.pin().get(&entry_key).copied()
+            .and_then(|CacheEntry { partition_key, position }| self.maps.partitions.get(partition_key).map(|p| p.read::<RT>(position)));
+        
+        let Some(read_future) = read_future else {
+            self.maps.metrics.read(start.elapsed(), false);
             return Ok(None) // we can treat missing partitions like a cache miss
         };
 
         let read = read_future.await?;
+        self.maps.metrics.read(start.elapsed(), true);
         Ok(Some(read))
     }
+
+    /// Removes an entry from the database, returning `true` if it existed.
+    /// 
+    /// The removal is written as a tombstone to the entry's bucket, so the value stays removed once the database is loaded again.
+    /// The value will remain on disk until its partition expires, but can no longer be read.
+    /// 
+    /// # Errors
+    /// Returns an error if the tombstone couldn't be written, the entry is still removed until the database is loaded again,
+    /// or if the database was closed.
+    pub async fn remove(&self, key: impl Into<SizedBytes>) -> Result<bool> {
+        self.maps.metrics.writes.fetch_add(1, Ordering::SeqCst);
+        let _finished = defer(|| self.maps.metrics.writes.fetch_sub(1, Ordering::SeqCst));
+        if self.maps.closed.load(Ordering::SeqCst) {
+            return Err(Error::CLOSED)
+        }
+
+        let start = Instant::now();
+        let key = key.into();
+        let Some(removed) = self.maps.entries.pin().remove(&key).copied() else { return Ok(false) };
+        // a purged partition took the value with it, so there's nothing left to hide.
+        let Some(bucket_id) = self.maps.partitions.get(removed.partition_key).map(|partition| partition.ttl.as_secs()) else { return Ok(true) };
+
+        // written to the value's bucket so the tombstone lives at least as long as the value it hides.
+        let entry = Entry::tombstone(key);
+        let len = entry.stored_len();
+        let insert_future = {
+            let bucket_map = self.maps.buckets.pin();
+            let bucket = bucket_map.get(&bucket_id).ok_or(Error::BUCKET_NOT_FOUND)?;
+            bucket.insert::<RT, S>(unix_secs(), bucket_id, entry, &self.maps, &self.queue_tx)
+        };
+        insert_future.await?;
+        self.maps.metrics.write(start.elapsed(), len);
+        Ok(true)
+    }
+}
+
+impl<RT: Runtime, S: BuildHasher + Default + Send + Sync + 'static> Database<RT, S> {
+    /// Sets the observer told about each read and write.
+    /// Returns `false` if the database already had one, which is kept.
+    pub fn observe(&self, observer: impl Observer) -> bool {
+        self.maps.metrics.observer.set(Box::new(observer)).is_ok()
+    }
+
+    /// Bytes held by partition files. Replaced and removed values count until their partition is purged.
+    pub fn disk_bytes(&self) -> u64 {
+        self.maps.metrics.disk_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Number of partitions in the expiration queue, each is purged once it expires.
+    pub fn expiration_queue_len(&self) -> usize {
+        self.maps.metrics.queued.load(Ordering::Relaxed) + self.queue_tx.len()
+    }
+
+    /// Returns `false` if the expiration task has stopped, such as after a panic. Expired partitions aren't purged without it.
+    pub fn expiration_task_alive(&self) -> bool {
+        !self.queue_tx.is_disconnected()
+    }
+
+    /// Checks new partitions could be created, by writing and removing a probe file in the database's directory.
+    ///
+    /// # Errors
+    /// Returns an error if the directory can't be written to.
+    pub async fn check_writable(&self) -> Result<()> {
+        let probe = self.path.join(".write_probe");
+        RT::spawn_blocking(move || {
+            fs::write(&probe, [])?;
+            fs::remove_file(&probe)
+        }).await??;
+        Ok(())
+    }
+
+    /// Closes the database: new inserts are rejected, inserts in progress get until `timeout` to finish,
+    /// every partition still holding entries is synced to disk and the expiration task is stopped
+    /// once the purges it started finish. Reads keep working.
+    ///
+    /// # Errors
+    /// Returns an error if a partition couldn't be synced.
+    pub async fn close(&self, timeout: Duration) -> Result<CloseSummary> {
+        let deadline = Instant::now() + timeout;
+        self.maps.closed.store(true, Ordering::SeqCst);
+        let in_progress = self.maps.metrics.writes.load(Ordering::SeqCst);
+        while self.maps.metrics.writes.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            RT::sleep(Duration::from_millis(10)).await;
+        }
+        let writes_abandoned = self.maps.metrics.writes.load(Ordering::SeqCst);
+
+        // partitions holding entries, and each bucket's live partition in case its entries were all replaced.
+        let mut par_keys: HashSet<usize> = self.maps.entries.pin().values().map(|entry| entry.partition_key).collect();
+        par_keys.extend(self.maps.buckets.pin().values().map(|bucket| bucket.live_partition_key()));
+        let mut syncs: FuturesUnordered<_> = par_keys.into_iter()
+            .filter_map(|key| self.maps.partitions.get(key).map(|partition| partition.file.sync::<RT>()))
+            .collect();
+        let mut partitions_synced = 0;
+        while let Some(res) = syncs.next().await {
+            res?;
+            partitions_synced += 1;
+        }
+
+        let (done, stopped) = flume::bounded(1);
+        let purges_finished = match self.queue_tx.send(ExpCMD::Stop { done }) {
+            Ok(()) => stopped.recv_async().await.unwrap_or(0),
+            Err(_) => 0, // the task already stopped.
+        };
+
+        Ok(CloseSummary { writes_finished: in_progress.saturating_sub(writes_abandoned), writes_abandoned, partitions_synced, purges_finished })
+    }
+
+    /// Gets where an entry is stored without reading its value.
+    /// Returns None if the entry isn't in the database.
+    pub fn inspect(&self, key: impl Into<SizedBytes>) -> Option<EntryInfo> {
+        let CacheEntry { partition_key, position } = self.maps.entries.pin().get(&key.into()).copied()?;
+        let partition = self.maps.partitions.get(partition_key)?;
+
+        Some(EntryInfo {
+            bucket: partition.ttl,
+            partition: partition.insertion_time,
+            expires_at: partition.insertion_time + partition.ttl.as_secs(),
+            len: position.value_len(),
+        })
+    }
 }
 
 /// Asserts at compile-time that the database's read and insert methods are send safe.
 fn _assert_send<RT: Runtime, S: ViableHasher>(db: &Database<RT, S>, key: SizedBytes, value: Bytes) {
     fn assert_send<T: Send>(_: T) { }
     assert_send(db.insert(key.clone(), value, Duration::from_secs(20)));
-    assert_send(db.read(key));
-}
\ No newline at end of file
+    assert_send(db.read(key.clone()));
+    assert_send(db.remove(key));
+}
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::{Path, PathBuf}, process, time::Duration};
+
+    use crate::{Database, Error, ResultExt, Runtime, partition::TOMBSTONE, unix_secs};
+
+    struct TokioRT;
+    impl Runtime for TokioRT {
+        fn spawn<F>(task: F)
+        where
+            F: Future + Send + 'static,
+            F::Output: Send + 'static,
+        {
+            tokio::spawn(task);
+        }
+
+        fn spawn_blocking<T, R>(task: T) -> impl Future<Output = Result<R, Error>> + Send
+        where
+            T: FnOnce() -> R + Send + 'static,
+            R: Send + 'static,
+        {
+            tokio::task::spawn_blocking(task).task_err()
+        }
+
+        fn sleep(duration: Duration) -> impl Future<Output = ()> + Send {
+            tokio::time::sleep(duration)
+        }
+    }
+
+    const TTL: Duration = Duration::from_hours(1);
+
+    /// An empty directory for one test's database.
+    fn dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ltmdb_{test}_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Writes a partition file created at `created` holding `entries`, `None` values being tombstones.
+    fn write_partition(db: &Path, created: u64, entries: &[(&str, Option<&str>)]) {
+        let bucket = db.join(TTL.as_millis().to_string());
+        fs::create_dir_all(&bucket).unwrap();
+        let mut file = Vec::new();
+        for (key, value) in entries {
+            file.extend((key.len() as u64).to_be_bytes());
+            file.extend(key.as_bytes());
+            file.extend(value.map_or(TOMBSTONE, |value| value.len() as u64).to_be_bytes());
+            file.extend(value.unwrap_or_default().as_bytes());
+        }
+        fs::write(bucket.join(created.to_string()), file).unwrap();
+    }
+
+    async fn read(db: &Database<TokioRT>, key: &str) -> Option<String> {
+        db.read(key).await.unwrap().map(|value| String::from_utf8(value.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn tombstones_mask_older_values_in_other_partitions() {
+        let path = dir("tombstone_mask");
+        let now = unix_secs();
+        write_partition(&path, now - 120, &[("removed", Some("old")), ("reinserted", Some("old"))]);
+        write_partition(&path, now - 60, &[("removed", None), ("reinserted", None)]);
+        write_partition(&path, now, &[("reinserted", Some("new"))]);
+
+        let db = Database::<TokioRT>::load(path.clone()).await.unwrap();
+        assert_eq!(read(&db, "removed").await, None);
+        assert_eq!(read(&db, "reinserted").await.as_deref(), Some("new"));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn tombstones_expire_with_their_bucket() {
+        let path = dir("tombstone_expiry");
+        let db = Database::<TokioRT>::load(path.clone()).await.unwrap();
+        let ttl = Duration::from_secs(1);
+        db.insert("key", "value", ttl).await.unwrap();
+        assert!(db.remove("key").await.unwrap());
+
+        // the tombstone was written next to the value, so nothing is left once their bucket's partitions are purged.
+        let bucket = path.join(ttl.as_millis().to_string());
+        for _ in 0..50 {
+            if fs::read_dir(&bucket).unwrap().next().is_none() { break }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(fs::read_dir(&bucket).unwrap().count(), 0);
+        assert_eq!(db.disk_bytes(), 0);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn removed_keys_can_be_inserted_again() {
+        let path = dir("tombstone_reinsert");
+        let db = Database::<TokioRT>::load(path.clone()).await.unwrap();
+        db.insert("key", "old", TTL).await.unwrap();
+        assert!(db.remove("key").await.unwrap());
+        assert!(!db.remove("key").await.unwrap());
+        db.insert("key", "new", TTL).await.unwrap();
+        assert_eq!(read(&db, "key").await.as_deref(), Some("new"));
+
+        // the value and tombstone share a partition here, so the order they were written in decides.
+        db.close(Duration::from_secs(1)).await.unwrap();
+        let db = Database::<TokioRT>::load(path.clone()).await.unwrap();
+        assert_eq!(read(&db, "key").await.as_deref(), Some("new"));
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -24,6 +24,8 @@ pub enum ErrorKind {
     PartitionNotFound,
     Bucket,
     Queue,
+    /// the database was closed, see [`Database::close`](crate::Database::close).
+    Closed,
     Other(&'static str)
 }
 
@@ -40,6 +42,7 @@ impl Error {
     pub const BUCKET_NOT_FOUND: Self = Self { kind: ErrorKind::Bucket, error: ErrorContent::Simple("Bucket Not Found!") };
     pub const PARTITION_NOT_FOUND: Self = Self::partition_not_found("Removed before access!");
     pub const PARTITION_FAILED_INSERTION: Self = Self::simple(ErrorKind::Partition, "Failed to insert partition!");
+    pub const CLOSED: Self = Self::simple(ErrorKind::Closed, "Database was closed!");
     
     pub fn err(kind: ErrorKind, err: impl StdError + Send + Sync + 'static) -> Self {
         Self {
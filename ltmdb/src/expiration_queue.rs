@@ -1,8 +1,8 @@
 #![allow(clippy::items_after_statements)]
 
-use std::{cmp::Reverse, collections::BinaryHeap, future::poll_fn, sync::Arc, time::Duration};
+use std::{cmp::Reverse, collections::BinaryHeap, future::poll_fn, sync::{Arc, atomic::Ordering}, time::Duration};
 
-use flume::{Receiver, TryRecvError};
+use flume::{Receiver, Sender, TryRecvError};
 use futures_util::{FutureExt, StreamExt, TryFutureExt, future::{Either, err}, select, stream::FuturesUnordered};
 
 use crate::{Error, Result, db::{Maps, ViableHasher}, runtime::Runtime, unix_secs};
@@ -11,7 +11,11 @@ pub(crate) enum ExpCMD {
     Schedule {
         time: u64,
         par_key: usize,
-    }
+    },
+    /// Stops the task once the purges already started finish, sending how many there were.
+    Stop {
+        done: Sender<usize>,
+    },
 }
 
 #[derive(PartialEq, Eq)]
@@ -37,19 +41,21 @@ impl PartialOrd for QueueEntry {
 pub(crate) async fn run_expiration_task<RT: Runtime, S: ViableHasher>(db_maps: Arc<Maps<S>>, rx: Receiver<ExpCMD>) {    
     let mut heap: BinaryHeap<Reverse<QueueEntry>> = BinaryHeap::new();
     let mut pending_deletions = FuturesUnordered::new();
+    let mut stopped = None;
     
     'outer: loop {
+        db_maps.metrics.queued.store(heap.len() + pending_deletions.len(), Ordering::Relaxed);
         loop {
             match rx.try_recv() {
-                Ok(msg) => handle_message(msg, &mut heap),
+                Ok(msg) => if let Some(done) = handle_message(msg, &mut heap) { stopped = Some(done); break 'outer },
                 Err(TryRecvError::Empty) => break,
                 Err(TryRecvError::Disconnected) => break 'outer,
             }
         }
 
-        if heap.is_empty() {
+        if heap.is_empty() && pending_deletions.is_empty() {
             match rx.recv_async().await {
-                Ok(msg) => handle_message(msg, &mut heap),
+                Ok(msg) => if let Some(done) = handle_message(msg, &mut heap) { stopped = Some(done); break 'outer },
                 Err(_) => break 'outer,
             }
             continue 'outer;
@@ -67,7 +73,7 @@ pub(crate) async fn run_expiration_task<RT: Runtime, S: ViableHasher>(db_maps: A
         select! {
             res = next_completion.fuse() => {
                 let Some(Err((entry, err))) = res else { continue 'outer }; // if its an error, it was successfully deleted and we don't care about it anymore.
-                eprintln!("Failed to delete partition: {err}");
+                db_maps.metrics.purge_failed(entry.par_key, &err);
 
                 const INITIAL_BACKOFF: u64 = 5;     // 5 seconds
                 const MAX_BACKOFF: u64 = 60 * 60;   // 1 hour
@@ -81,7 +87,7 @@ pub(crate) async fn run_expiration_task<RT: Runtime, S: ViableHasher>(db_maps: A
 
             res = rx.recv_async().fuse() => {
                 match res {
-                    Ok(msg) => handle_message(msg, &mut heap),
+                    Ok(msg) => if let Some(done) = handle_message(msg, &mut heap) { stopped = Some(done); break 'outer },
                     Err(_) => break 'outer,
                 }
             }
@@ -89,14 +95,26 @@ pub(crate) async fn run_expiration_task<RT: Runtime, S: ViableHasher>(db_maps: A
             () = sleep_until_next::<RT>(&mut heap, now).fuse() => {}, // we loop back, which will shortly purge the woken entry.
         };
     }
-    // database closed due to it being dropped. 
+    // database was closed or dropped. purges already started are finished rather than leaving their keys half removed.
+    let finished = pending_deletions.len();
+    while let Some(res) = pending_deletions.next().await {
+        if let Err((entry, err)) = res {
+            db_maps.metrics.purge_failed(entry.par_key, &err);
+        }
+    }
+    db_maps.metrics.queued.store(heap.len(), Ordering::Relaxed);
+    if let Some(done) = stopped {
+        let _ = done.send(finished);
+    }
 }
 
-#[allow(clippy::needless_pass_by_value)]
-fn handle_message(msg: ExpCMD, queue: &mut BinaryHeap<Reverse<QueueEntry>>) {
+/// Returns where to report back to if the task should stop.
+fn handle_message(msg: ExpCMD, queue: &mut BinaryHeap<Reverse<QueueEntry>>) -> Option<Sender<usize>> {
     match msg {
         ExpCMD::Schedule { time, par_key } => queue.push(Reverse(QueueEntry { time, par_key, retries: 0 })),
+        ExpCMD::Stop { done } => return Some(done),
     }
+    None
 }
 
 fn get_next(heap: &mut BinaryHeap<Reverse<QueueEntry>>, now: u64) -> Option<QueueEntry> {
@@ -118,6 +136,7 @@ fn purge_partition<RT: Runtime, S: ViableHasher>(maps: &Maps<S>, key: usize) ->
         return Either::Left(err(Error::PARTITION_NOT_FOUND)); // either because i want to return errors on the future itself
     };
 
+    maps.metrics.disk_bytes.fetch_sub(partition.file.len(), Ordering::Relaxed);
     maps.partitions.remove(key); // sharded_slab has no problem letting us keep a reference while marking it to be deleted.
     Either::Right(partition.purge::<RT, S>(&maps.entries))
 }
\ No newline at end of file
@@ -53,6 +53,11 @@ impl FileHandle {
         })
     }
 
+    /// Length of the file, including space reserved by writes still in progress.
+    pub fn len(&self) -> u64 {
+        self.inner.offset.load(Ordering::Relaxed)
+    }
+
     pub fn append_from<RT: Runtime, B: Buf + Send + Sync + 'static>(&self, buf: B) ->  impl Future<Output = Result<u64>> + use<RT, B> {
         let inner = self.inner.clone();
         let len = buf.remaining() as u64;
@@ -71,6 +76,12 @@ impl FileHandle {
         }).flatten()
     }
 
+    /// Waits for the os to write the file's data to disk.
+    pub fn sync<RT: Runtime>(&self) -> impl Future<Output = Result<()>> + use<RT> {
+        let inner = self.inner.clone();
+        RT::spawn_blocking(move || inner.file.sync_data().map_err(Into::into)).flatten()
+    }
+
     pub fn delete<RT: Runtime>(&self) -> impl Future<Output = Result<()>> + use<RT> {
         let inner = self.inner.clone();
         RT::spawn_blocking(move || fs::remove_file(&inner.path).map_err(Into::into)).flatten()
@@ -16,14 +16,17 @@ use std::{result::Result as StdResult, time::{Duration, SystemTime, UNIX_EPOCH}}
 mod error;
 mod expiration_queue;
 mod file_handle;
+mod metrics;
 mod partition;
 mod bucket;
 mod db;
 mod sized_bytes;
 mod runtime;
+pub mod offline;
 
 pub use error::{Error, ErrorKind, ResultExt};
-pub use db::Database;
+pub use db::{CloseSummary, Database, EntryInfo};
+pub use metrics::Observer;
 pub use runtime::Runtime;
 pub use sized_bytes::SizedBytes;
 
@@ -0,0 +1,56 @@
+use std::{sync::{OnceLock, atomic::{AtomicU64, AtomicUsize, Ordering}}, time::Duration};
+
+use crate::Error;
+
+/// Receives timings of database operations as they finish, and failures that happen in the background,
+/// see [`Database::observe`](crate::Database::observe).
+/// 
+/// Called inline on the reading or writing task, so implementations should be cheap.
+pub trait Observer: Send + Sync + 'static {
+    /// A read finished. `hit` is false if the key wasn't stored.
+    fn read(&self, elapsed: Duration, hit: bool);
+
+    /// A write of `len` bytes, including entry metadata, finished.
+    fn write(&self, elapsed: Duration, len: u64);
+
+    /// The expiration task failed to delete an expired partition, it's retried with a backoff.
+    fn purge_failed(&self, partition: usize, err: &Error);
+}
+
+/// Counters shared by the database and its expiration task.
+pub(crate) struct Metrics {
+    pub observer: OnceLock<Box<dyn Observer>>,
+    /// bytes held by partition files, including values that were replaced or removed but not yet purged.
+    pub disk_bytes: AtomicU64,
+    /// partitions the expiration task has received and not yet purged.
+    pub queued: AtomicUsize,
+    /// inserts that have started and not yet finished.
+    pub writes: AtomicUsize,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self { observer: OnceLock::new(), disk_bytes: AtomicU64::new(0), queued: AtomicUsize::new(0), writes: AtomicUsize::new(0) }
+    }
+
+    pub fn read(&self, elapsed: Duration, hit: bool) {
+        if let Some(observer) = self.observer.get() {
+            observer.read(elapsed, hit);
+        }
+    }
+
+    /// Printed to stderr if nothing observes the database.
+    pub fn purge_failed(&self, partition: usize, err: &Error) {
+        match self.observer.get() {
+            Some(observer) => observer.purge_failed(partition, err),
+            None => eprintln!("Failed to delete partition {partition}: {err}"),
+        }
+    }
+
+    pub fn write(&self, elapsed: Duration, len: u64) {
+        self.disk_bytes.fetch_add(len, Ordering::Relaxed);
+        if let Some(observer) = self.observer.get() {
+            observer.write(elapsed, len);
+        }
+    }
+}
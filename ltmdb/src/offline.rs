@@ -0,0 +1,268 @@
+//! Reading and rewriting a database directory while no [`Database`](crate::Database) has it open, for admin tooling.
+//!
+//! Nothing here coordinates with a loaded database, rewriting or deleting partitions under one loses its entries.
+
+use std::{collections::HashMap, fs::{self, File}, io::{self, BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}, time::Duration};
+
+use bytes::Bytes;
+
+use crate::{Error, ErrorKind, Result, partition::TOMBSTONE};
+
+/// A partition file found by [`scan`].
+#[derive(Clone, Debug)]
+pub struct PartitionFile {
+    /// ttl of the bucket holding the partition.
+    pub bucket: Duration,
+    /// unix time in seconds the partition was created, which is also its file name.
+    pub created: u64,
+    pub path: PathBuf,
+    /// length of the file in bytes.
+    pub len: u64,
+}
+
+impl PartitionFile {
+    /// Unix time in seconds the partition is scheduled to be purged.
+    pub fn expires_at(&self) -> u64 {
+        self.created + self.bucket.as_secs()
+    }
+
+    /// Reads the partition's entries in the order they were written.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be opened.
+    pub fn entries(&self) -> Result<Entries> {
+        let file = File::open(&self.path)?;
+        Ok(Entries { reader: BufReader::new(file), path: self.path.clone(), offset: 0, len: self.len })
+    }
+
+    /// Rewrites the partition with only the entries `keep` returns `true` for, deleting it if there are none.
+    /// Returns the bytes freed, the file is left as it was if every entry is kept.
+    ///
+    /// # Errors
+    /// Returns an error if the partition doesn't parse or the new file couldn't be written.
+    /// The original file is only replaced once the new one is complete.
+    pub fn rewrite(&self, mut keep: impl FnMut(&StoredEntry) -> bool) -> Result<u64> {
+        let mut kept = Vec::new();
+        let mut dropped = false;
+        for entry in self.entries()? {
+            let entry = entry?;
+            if keep(&entry) { kept.push(entry) } else { dropped = true }
+        }
+
+        if !dropped {
+            return Ok(0)
+        }
+        if kept.is_empty() {
+            fs::remove_file(&self.path)?;
+            return Ok(self.len)
+        }
+
+        // file names that aren't a number are skipped on load, so a left over temporary file is never read as a partition.
+        let tmp = self.path.with_extension("compacting");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        let mut written = 0;
+        for entry in &kept {
+            out.write_all(&(entry.key.len() as u64).to_be_bytes())?;
+            out.write_all(&entry.key)?;
+            out.write_all(&entry.value.as_ref().map_or(TOMBSTONE, |value| value.len() as u64).to_be_bytes())?;
+            out.write_all(entry.value.as_deref().unwrap_or_default())?;
+            written += entry.stored_len();
+        }
+        out.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(self.len - written)
+    }
+}
+
+/// An entry read from a partition file.
+#[derive(Clone, Debug)]
+pub struct StoredEntry {
+    pub key: Bytes,
+    /// `None` if the entry is a tombstone, recording the key's removal.
+    pub value: Option<Bytes>,
+    /// where the entry starts in its partition file.
+    pub offset: u64,
+}
+
+impl StoredEntry {
+    /// Length of the entry in its file, including its length prefixes.
+    pub fn stored_len(&self) -> u64 {
+        (2 * size_of::<u64>() + self.key.len() + self.value.as_ref().map_or(0, Bytes::len)) as u64
+    }
+}
+
+/// Entries of a partition file, see [`PartitionFile::entries`].
+///
+/// Unlike loading a database, an entry running past the end of the file is an error rather than being read as far as it goes.
+/// Nothing is read after the first error.
+pub struct Entries {
+    reader: BufReader<File>,
+    path: PathBuf,
+    offset: u64,
+    len: u64,
+}
+
+impl Iterator for Entries {
+    type Item = Result<StoredEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.len {
+            return None
+        }
+        let entry = self.read_entry();
+        if entry.is_err() {
+            self.offset = self.len;
+        }
+        Some(entry)
+    }
+}
+
+impl Entries {
+    fn read_entry(&mut self) -> Result<StoredEntry> {
+        let offset = self.offset;
+        let key = self.read_field(offset, "key")?;
+        let value = self.read_field(offset, "value")?;
+        let Some(key) = key else { return Err(self.truncated(offset, "key")) };
+        Ok(StoredEntry { key, value, offset })
+    }
+
+    /// Reads a length prefixed field of the entry starting at `entry`, `None` if its length marks a tombstone.
+    #[allow(clippy::cast_possible_truncation)]
+    fn read_field(&mut self, entry: u64, field: &str) -> Result<Option<Bytes>> {
+        let mut len = [0; size_of::<u64>()];
+        self.read_exact(&mut len, entry, field)?;
+        let len = u64::from_be_bytes(len);
+        if len == TOMBSTONE {
+            return Ok(None)
+        }
+        if len > self.len - self.offset {
+            return Err(self.truncated(entry, field))
+        }
+        let mut buf = vec![0; len as usize];
+        self.read_exact(&mut buf, entry, field)?;
+        Ok(Some(buf.into()))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8], entry: u64, field: &str) -> Result<()> {
+        if buf.len() as u64 > self.len - self.offset {
+            return Err(self.truncated(entry, field))
+        }
+        self.reader.read_exact(buf)?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    fn truncated(&self, entry: u64, field: &str) -> Error {
+        let message = format!("{}: the {field} of the entry at byte {entry} runs past the end of the file ({} bytes)", self.path.display(), self.len);
+        Error::err(ErrorKind::Partition, io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+}
+
+/// Where the value a key resolves to is stored, see [`latest`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Location {
+    /// index of the partition in the slice given to [`latest`].
+    pub partition: usize,
+    pub offset: u64,
+}
+
+/// Finds the partition files of a database directory, ordered by bucket then creation time.
+///
+/// # Errors
+/// Returns an error if the directory can't be read.
+pub fn scan(path: impl AsRef<Path>) -> Result<Vec<PartitionFile>> {
+    let mut partitions = Vec::new();
+    for bucket in fs::read_dir(path)? {
+        let bucket = bucket?;
+        if !bucket.file_type()?.is_dir() { continue }
+        let Some(bucket_millis) = bucket.file_name().into_string().ok().and_then(|n| n.parse::<u64>().ok()) else { continue };
+
+        for partition in fs::read_dir(bucket.path())? {
+            let partition = partition?;
+            let metadata = partition.metadata()?;
+            if !metadata.is_file() { continue }
+            let Some(created) = partition.file_name().into_string().ok().and_then(|n| n.parse::<u64>().ok()) else { continue };
+            partitions.push(PartitionFile { bucket: Duration::from_millis(bucket_millis), created, path: partition.path(), len: metadata.len() });
+        }
+    }
+    partitions.sort_by_key(|partition| (partition.bucket, partition.created));
+    Ok(partitions)
+}
+
+/// Resolves each key to its newest entry the way loading the database does: the newest partition wins,
+/// and within a partition the last entry written. Keys whose newest entry is a tombstone resolve to it.
+///
+/// # Errors
+/// Returns an error if any partition doesn't parse.
+pub fn latest(partitions: &[PartitionFile]) -> Result<HashMap<Bytes, Location>> {
+    let mut latest: HashMap<Bytes, (u64, Location)> = HashMap::new();
+    for (index, partition) in partitions.iter().enumerate() {
+        for entry in partition.entries()? {
+            let entry = entry?;
+            let location = Location { partition: index, offset: entry.offset };
+            match latest.get_mut(&entry.key) {
+                Some((created, _)) if *created > partition.created => {}
+                Some(newest) => *newest = (partition.created, location),
+                None => { latest.insert(entry.key, (partition.created, location)); }
+            }
+        }
+    }
+    Ok(latest.into_iter().map(|(key, (_, location))| (key, location)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::{PartitionFile, latest, scan};
+    use crate::partition::TOMBSTONE;
+
+    /// A database directory holding one partition file with `bytes` as its contents.
+    fn partition(test: &str, bytes: &[u8]) -> (PathBuf, PartitionFile) {
+        let dir = env::temp_dir().join(format!("ltmdb_offline_{test}_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("60000")).unwrap();
+        fs::write(dir.join("60000").join("100"), bytes).unwrap();
+        let partition = scan(&dir).unwrap().remove(0);
+        (dir, partition)
+    }
+
+    fn entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+        [&(key.len() as u64).to_be_bytes(), key, &(value.len() as u64).to_be_bytes(), value].concat()
+    }
+
+    #[test]
+    fn truncated_entries_are_errors() {
+        let mut bytes = entry(b"whole", b"value");
+        let second = bytes.len();
+        bytes.extend(entry(b"cut", b"value"));
+        bytes.truncate(bytes.len() - 2);
+        let (dir, partition) = partition("truncated", &bytes);
+
+        let mut entries = partition.entries().unwrap();
+        let whole = entries.next().unwrap().unwrap();
+        assert_eq!((&whole.key[..], whole.value.as_deref()), (&b"whole"[..], Some(&b"value"[..])));
+        let err = entries.next().unwrap().unwrap_err().to_string();
+        assert!(err.contains(&format!("the value of the entry at byte {second} runs past the end of the file")), "{err}");
+        assert!(entries.next().is_none());
+        assert!(latest(&[partition]).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_lengths_are_errors() {
+        // a key can't be a tombstone, and no length can run past the end of the file.
+        let tombstone_key = [&TOMBSTONE.to_be_bytes()[..], &5u64.to_be_bytes(), b"value"].concat();
+        let oversized_key = [&u64::MAX.wrapping_sub(1).to_be_bytes()[..], b"key"].concat();
+        let oversized_value = [&3u64.to_be_bytes()[..], b"key", &1000u64.to_be_bytes(), b"value"].concat();
+
+        for (test, bytes, field) in [("tombstone_key", tombstone_key, "key"), ("oversized_key", oversized_key, "key"), ("oversized_value", oversized_value, "value")] {
+            let (dir, partition) = partition(test, &bytes);
+            let err = partition.entries().unwrap().next().unwrap().unwrap_err().to_string();
+            assert!(err.contains(&format!("the {field} of the entry at byte 0 runs past the end of the file")), "{test}: {err}");
+            assert!(partition.rewrite(|_| true).is_err(), "{test}");
+            assert_eq!(fs::read(&partition.path).unwrap(), bytes, "{test} shouldn't be rewritten");
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
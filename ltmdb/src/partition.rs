@@ -1,4 +1,4 @@
-use std::{io::{self, Read, Seek}, path::PathBuf};
+use std::{io::{self, Read, Seek}, path::PathBuf, time::Duration};
 
 use bytes::{Buf, Bytes, BytesMut};
 use crossbeam_queue::SegQueue;
@@ -10,6 +10,8 @@ use crate::{Error, Result, db::{CacheEntry, Entry, ViableHasher}, file_handle::{
 
 const KEY_LEN_SIZE: usize = size_of::<u64>();
 const VALUE_LEN_SIZE: usize = size_of::<u64>();
+/// Value length written in place of a value to record its key's removal. No value bytes follow it.
+pub(crate) const TOMBSTONE: u64 = u64::MAX;
 
 #[derive(Clone, Copy)]
 pub(crate) struct PartitionEntry {
@@ -17,27 +19,41 @@ pub(crate) struct PartitionEntry {
     value_len: usize
 }
 
+impl PartitionEntry {
+    pub fn value_len(self) -> usize {
+        self.value_len
+    }
+
+    /// Returns `true` if the entry records a removal rather than a value.
+    pub fn is_tombstone(self) -> bool {
+        self.value_len == usize::MAX
+    }
+}
+
 /// A partition that doesn't hold its own key yet.
 /// 
 /// Used to prevent `FileHandle` creation while holding a reference to a partition slab entry
 pub(crate) struct PendingPartition {
     insertion_time: u64,
+    ttl: Duration,
     file: FileHandle,
     keys: SegQueue<SizedBytes>
 }
 
 impl PendingPartition {
-    pub async fn new<RT: Runtime>(now: u64, path: PathBuf) -> Result<Self> {
+    pub async fn new<RT: Runtime>(now: u64, ttl: Duration, path: PathBuf) -> Result<Self> {
         Ok(Self {
             insertion_time: now,
+            ttl,
             file: FileHandle::new::<RT>(path).await?,
             keys: SegQueue::new(),
         })
     }
     
-    pub fn new_sync(now: u64, path: PathBuf) -> Result<Self> {
+    pub fn new_sync(now: u64, ttl: Duration, path: PathBuf) -> Result<Self> {
         Ok(Self {
             insertion_time: now,
+            ttl,
             file: FileHandle::new_sync(path)?,
             keys: SegQueue::new(),
         })
@@ -52,10 +68,16 @@ impl PendingPartition {
         vacent.insert(self.construct(key));
         Ok(key)
     }
+
+    /// Length of the partition's file.
+    pub fn len(&self) -> u64 {
+        self.file.len()
+    }
     
     fn construct(self, key: usize) -> Partition {
         Partition {
             insertion_time: self.insertion_time,
+            ttl: self.ttl,
             key,
             file: self.file,
             keys: self.keys,
@@ -65,6 +87,8 @@ impl PendingPartition {
 
 pub(crate) struct Partition {
     pub insertion_time: u64,
+    /// ttl of the bucket this partition belongs to.
+    pub ttl: Duration,
     pub key: usize,
     pub file: FileHandle,
     pub keys: SegQueue<SizedBytes>
@@ -77,7 +101,8 @@ impl Partition {
     #[must_use = "This future has side effects before being polled!"]
     pub fn insert<RT: Runtime>(&self, entry: Entry) -> impl Future<Output = Result<PartitionEntry>> + Send + use<RT> {
         let key_len = entry.key.len() as u64;
-        let value_len = entry.value.len() as u64;
+        let value_len = entry.value.as_ref().map_or(TOMBSTONE, |value| value.len() as u64);
+        let value = entry.value.unwrap_or_default();
 
         let entry_key = entry.key.clone();
         
@@ -90,18 +115,18 @@ impl Partition {
             // Chaining here avoids the allocation/move of a large key and/or value required to put them in one buffer, but this only helps when we have pwritev support.
             buf = Buf::chain(key_len_buf, entry.key)
                 .chain(value_len_buf)
-                .chain(entry.value);
+                .chain(value);
         }
         
         #[cfg(not(unix))]
         {
             use bytes::BufMut;
 
-            let mut buffer = BytesMut::with_capacity(KEY_LEN_SIZE + entry.key.len() + VALUE_LEN_SIZE + entry.value.len());
+            let mut buffer = BytesMut::with_capacity(KEY_LEN_SIZE + entry.key.len() + VALUE_LEN_SIZE + value.len());
             buffer.put_u64(key_len);
             buffer.put_slice(&entry.key);
             buffer.put_u64(value_len);
-            buffer.put(entry.value);
+            buffer.put(value);
 
             // writing a whole vector at once reduces syscalls; a chain would require each chunk to be written individually.
             buf = buffer.freeze();
@@ -138,7 +163,7 @@ impl Partition {
     
     /// creates a partition file by reading an existing file. Returns a partition pending key insertion.
     #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
-    pub fn from_file(now: u64, path: PathBuf) -> Result<(Vec<(SizedBytes, PartitionEntry)>, PendingPartition)> {
+    pub fn from_file(now: u64, ttl: Duration, path: PathBuf) -> Result<(Vec<(SizedBytes, PartitionEntry)>, PendingPartition)> {
         const BUFFER_SIZE: usize = 8 * 1024 * 1024; // 8mb
         
         let mut file = open_file(&path)?;
@@ -147,9 +172,14 @@ impl Partition {
         let keys: SegQueue<SizedBytes> = SegQueue::new();
         let mut position: usize = 0;
 
+        // a single read can hold many entries, so the buffer is only refilled once it runs short of the next one.
+        let mut needs_fill = true;
         loop {
-            let read = fill(&mut file, &mut buffer)?; 
-            if read == 0 { break; } // EOF
+            if needs_fill {
+                let read = fill(&mut file, &mut buffer)?; 
+                if read == 0 { break; } // EOF
+            }
+            needs_fill = true;
 
             // Attempt to refill the buffer if it isnt long enough to read all needed metadata
             if buffer.len() < KEY_LEN_SIZE { continue }
@@ -159,6 +189,7 @@ impl Partition {
             // Reserve space if the entry metadata is longer than `BUFFER_SIZE`
             if buffer.capacity() < entry_metadata_len { buffer.reserve(entry_metadata_len - buffer.len()); }
             if buffer.len() < entry_metadata_len { continue }
+            needs_fill = false;
 
             position += entry_metadata_len;
             
@@ -167,7 +198,13 @@ impl Partition {
             buffer.advance(key_len);
             keys.push(key.clone());
             
-            let value_len = buffer.get_u64() as usize;
+            let value_len = buffer.get_u64();
+            if value_len == TOMBSTONE {
+                entries.push((key, PartitionEntry { position: position as u64, value_len: usize::MAX }));
+                continue
+            }
+
+            let value_len = value_len as usize;
             if buffer.remaining() >= value_len {
                 buffer.advance(value_len);
             } else {
@@ -182,6 +219,7 @@ impl Partition {
 
         let inner = PendingPartition {
             insertion_time: now,
+            ttl,
             file: FileHandle::from_file(file, path)?,
             keys,
         };
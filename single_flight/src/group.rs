@@ -50,6 +50,17 @@ where
         }
     }
     
+    /// Returns `true` if a flight is currently in progress for the given key.
+    /// 
+    /// This is only a snapshot, the flight may have finished by the time this returns.
+    pub fn is_in_flight<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Eq + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.map.pin().contains_key(key)
+    }
+
     /// Executes the given function while ensuring only one is "in flight" at any given time.
     /// 
     /// Duplicate calls wait for the original call to complete and return the same value by 
@@ -84,6 +84,32 @@ mod tests {
         join_all(handlers).await;
     }
 
+    #[tokio::test]
+    async fn test_in_flight() {
+        let group = Arc::new(DefaultGroup::new());
+        assert!(!group.is_in_flight("key"));
+
+        let (ready_tx, ready_rx) = oneshot::channel::<()>();
+        let (finish_tx, finish_rx) = oneshot::channel::<()>();
+
+        let leader_group = group.clone();
+        let leader = tokio::spawn(async move {
+            leader_group.work("key", async move {
+                let _ = ready_tx.send(());
+                let _ = finish_rx.await;
+                Ok::<usize, ()>(7)
+            }).await
+        });
+
+        let _ = ready_rx.await;
+        assert!(group.is_in_flight("key"));
+        assert!(!group.is_in_flight("other"));
+
+        let _ = finish_tx.send(());
+        assert_eq!(leader.await.unwrap(), Ok(7));
+        assert!(!group.is_in_flight("key"));
+    }
+
     #[tokio::test]
     async fn test_drop_leader() {
         let group = Arc::new(DefaultGroup::new());
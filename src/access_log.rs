@@ -0,0 +1,195 @@
+use std::{cell::Cell, io::{self, Write}, path::PathBuf, pin::Pin, sync::{OnceLock, atomic::{AtomicU64, Ordering}, mpsc::{SyncSender, TrySendError, sync_channel}}, task::{Context, Poll}, thread, time::Instant};
+
+use actix_web::{body::{BodySize, MessageBody}, cookie::time::UtcDateTime, dev::{ServiceRequest, ServiceResponse}, http::header::{REFERER, USER_AGENT}, middleware::Next, web::Bytes};
+
+use tokio::{sync::oneshot, task::spawn_blocking};
+
+use crate::{cache::cache_router::Source, config::config, key_extractor::real_ip, logging::RotatingFile, request_id};
+
+/// Taken by the writer thread in order.
+enum Queued {
+    Line(String),
+    /// answered once every line queued before it is written.
+    Flush(oneshot::Sender<()>),
+}
+
+/// Set by `init` if the access log is enabled.
+static WRITER: OnceLock<SyncSender<Queued>> = OnceLock::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    /// Status of the last upstream response while handling the current request, see `upstream_status`.
+    static UPSTREAM_STATUS: Cell<Option<u16>>;
+}
+
+/// Records the status hypixel answered with, for the access line of the request being handled.
+pub fn upstream_status(status: u16) {
+    let _ = UPSTREAM_STATUS.try_with(|cell| cell.set(Some(status)));
+}
+
+/// Access lines dropped because the queue was full.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Writes a line per request in the combined log format, followed by the latency, the cache tier that answered,
+/// whether the request led or followed its single flight group, the upstream status if it went upstream and its request id.
+/// Lines are written once the body has been sent, so streamed bodies are counted in full.
+pub async fn access_log(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if WRITER.get().is_none() {
+        return Ok(next.call(req).await?.map_into_left_body())
+    }
+
+    let start = Instant::now();
+    let time = UtcDateTime::now();
+    let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok()).unwrap_or("-").to_string();
+    let line = format!(
+        "{} - - [{}] \"{} {} {:?}\"",
+        real_ip(&req).map_or("-".to_string(), |ip| ip.to_string()),
+        clf_time(time),
+        req.method(),
+        req.uri().path_and_query().map_or(req.path(), |path| path.as_str()),
+        req.version(),
+    );
+    let (referer, user_agent) = (header(REFERER), header(USER_AGENT));
+
+    let res = UPSTREAM_STATUS.scope(Cell::new(None), async {
+        let res = next.call(req).await;
+        (res, UPSTREAM_STATUS.with(Cell::get))
+    });
+    let (res, upstream) = res.await;
+    let res = res?;
+
+    let source = res.response().extensions().get::<Source>().copied();
+    let entry = Entry {
+        start,
+        prefix: format!("{line} {}", res.status().as_u16()),
+        referer,
+        user_agent,
+        tier: source.map_or("-", |source| source.tier.name()),
+        flight: source.map_or("-", |source| source.flight.name()),
+        upstream,
+        request_id: request_id::current().map_or("-".to_string(), |id| id.as_str().to_string()),
+    };
+    Ok(res.map_body(|_, body| LoggedBody { body: Box::pin(body), sent: 0, entry: Some(entry) }).map_into_right_body())
+}
+
+/// An access line waiting on its body, written once the body is sent or dropped.
+struct Entry {
+    start: Instant,
+    /// the combined format fields up to and including the status.
+    prefix: String,
+    referer: String,
+    user_agent: String,
+    tier: &'static str,
+    flight: &'static str,
+    upstream: Option<u16>,
+    request_id: String,
+}
+
+impl Entry {
+    fn line(&self, sent: u64) -> String {
+        format!(
+            "{} {sent} \"{}\" \"{}\" {:.3}ms tier={} flight={} upstream={} request_id={}",
+            self.prefix,
+            escape(&self.referer),
+            escape(&self.user_agent),
+            self.start.elapsed().as_secs_f64() * 1000.0,
+            self.tier,
+            self.flight,
+            self.upstream.map_or("-".to_string(), |status| status.to_string()),
+            self.request_id,
+        )
+    }
+}
+
+/// Counts the bytes of a body as they're sent.
+struct LoggedBody<B> {
+    body: Pin<Box<B>>,
+    sent: u64,
+    entry: Option<Entry>,
+}
+
+impl<B> Drop for LoggedBody<B> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            write(entry.line(self.sent));
+        }
+    }
+}
+
+impl<B: MessageBody> MessageBody for LoggedBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let chunk = self.body.as_mut().poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &chunk {
+            self.sent += chunk.len() as u64;
+        }
+        chunk
+    }
+}
+
+fn write(line: String) {
+    let Some(sender) = WRITER.get() else { return };
+    if let Err(TrySendError::Full(_)) = sender.try_send(Queued::Line(line)) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Waits for every line so far to be written, so none are lost when the process exits.
+pub async fn flush() {
+    let Some(sender) = WRITER.get().cloned() else { return };
+    let (tx, rx) = oneshot::channel();
+    // the queue may be full, and sending to it blocks until there's room.
+    if spawn_blocking(move || sender.send(Queued::Flush(tx))).await.is_ok_and(|sent| sent.is_ok()) {
+        let _ = rx.await;
+    }
+}
+
+/// Opens the access log set by `access_log.path`, if there is one, so a bad path fails at startup rather than on the first request.
+///
+/// # Errors
+/// Returns an error if `access_log.path` is a file that can't be opened.
+pub fn init() -> io::Result<()> {
+    let settings = &config().access_log;
+    let mut out: Box<dyn Write + Send> = match settings.path.as_str() {
+        "" => return Ok(()),
+        "stdout" => Box::new(io::stdout()),
+        path => Box::new(RotatingFile::open(PathBuf::from(path), settings.max_bytes, settings.keep)?),
+    };
+    let (tx, rx) = sync_channel::<Queued>(settings.queue.max(1));
+    thread::spawn(move || {
+        for queued in rx {
+            match queued {
+                Queued::Line(mut line) => {
+                    line.push('\n');
+                    let _ = out.write_all(line.as_bytes());
+                }
+                Queued::Flush(done) => {
+                    let _ = out.flush();
+                    let _ = done.send(());
+                }
+            }
+        }
+    });
+    let _ = WRITER.set(tx);
+    Ok(())
+}
+
+/// Time in the common log format, such as `10/Oct/2000:13:55:36 +0000`.
+fn clf_time(time: UtcDateTime) -> String {
+    let month = time.month().to_string();
+    format!("{:02}/{}/{}:{:02}:{:02}:{:02} +0000", time.day(), &month[..3], time.year(), time.hour(), time.minute(), time.second())
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
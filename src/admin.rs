@@ -0,0 +1,19 @@
+use actix_web::HttpRequest;
+use subtle::ConstantTimeEq;
+
+use crate::{config::config, error::ProcessError};
+
+/// Checks the request carries the configured `admin.key`. Admin endpoints are disabled if it isn't set.
+pub fn authorize(req: &HttpRequest) -> Result<(), ProcessError> {
+    let config = config();
+    let admin_key = config.admin.key.as_str();
+    if admin_key.is_empty() {
+        return Err(ProcessError::Forbidden("Admin endpoints are disabled."))
+    }
+
+    match req.headers().get("Admin-Key") {
+        // compared in constant time so response timing doesn't leak how much of the key was right.
+        Some(key) if bool::from(key.as_bytes().ct_eq(admin_key.as_bytes())) => Ok(()),
+        _ => Err(ProcessError::Forbidden("Invalid admin key.")),
+    }
+}
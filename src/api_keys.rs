@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use reqwest::header::{HeaderMap, HeaderValue};
+use simd_json::{OwnedValue, json};
+use simple_defer::{Deferred, defer};
+
+use crate::{cache::{cache_stats::WindowCounter, unix_secs}, config::config, logging::{Level, LogMessage, log}, routes::stats::{Forecast, RateLimit, stats_from_headers}};
+
+/// The hypixel api keys requests are spread across, configured by `upstream.api_keys` in the config file (or its `API_KEYS` override).
+pub struct ApiKeys {
+    keys: Vec<ApiKey>,
+}
+
+/// A single api key and the budget hypixel last reported for it.
+pub struct ApiKey {
+    header: HeaderValue,
+    /// the end of the key, so it can be told apart in `/stats` and logs without leaking it.
+    name: String,
+    rate_limit: RateLimit,
+    /// requests made upstream with this key, including retries.
+    calls: WindowCounter,
+    in_flight: AtomicU64,
+    active: AtomicBool,
+}
+
+impl ApiKeys {
+    /// The keys in `upstream.api_keys`, the config checks there's at least one when loaded.
+    pub fn from_config() -> Self {
+        let keys: Vec<ApiKey> = config().upstream.api_keys.iter().map(String::as_str).map(ApiKey::new).collect();
+        log(Level::Info, LogMessage::Startup { message: format!("Rotating between {} api keys", keys.len()) });
+        Self { keys }
+    }
+
+    /// Picks the key in rotation with the most budget left, or `None` if every key was revoked.
+    /// Keys without a known budget are picked first, so each key is tried before any is reused.
+    pub fn pick(&self) -> Option<&ApiKey> {
+        let now = unix_secs();
+        self.keys.iter()
+            .rev() // `max_by_key` returns the last of equal keys, ties should go to the first configured.
+            .filter(|key| key.active.load(Ordering::Relaxed))
+            .max_by_key(|key| key.budget(now).saturating_sub(key.in_flight.load(Ordering::Relaxed)))
+    }
+
+    /// The combined known budget of every key in rotation, and the soonest any of them resets.
+    pub fn total(&self) -> (u64, u64) {
+        let now = unix_secs();
+        self.keys.iter()
+            .filter(|key| key.active.load(Ordering::Relaxed))
+            .filter_map(|key| key.remaining(now))
+            .reduce(|(remaining, reset), (key_remaining, key_reset)| (remaining + key_remaining, reset.min(key_reset)))
+            .unwrap_or((0, 0))
+    }
+
+    /// Upstream requests made across every key.
+    pub fn calls(&self) -> OwnedValue {
+        let sum = |count: fn(&WindowCounter) -> u64| self.keys.iter().map(|key| count(&key.calls)).sum::<u64>();
+        json!({
+            "total": sum(WindowCounter::total),
+            "last_minute": sum(WindowCounter::last_minute),
+            "last_hour": sum(WindowCounter::last_hour),
+        })
+    }
+
+    /// Forecast of the combined budget of every key in rotation.
+    pub fn forecast(&self) -> Option<Forecast> {
+        let now = unix_secs();
+        Forecast::sum(self.keys.iter()
+            .filter(|key| key.active.load(Ordering::Relaxed))
+            .filter_map(|key| key.rate_limit.forecast(now)))
+    }
+
+    /// The name and last reported budget of every key in rotation, see `ApiKey::remaining`.
+    pub fn remaining(&self) -> impl Iterator<Item = (&str, Option<(u64, u64)>)> {
+        let now = unix_secs();
+        self.keys.iter()
+            .filter(|key| key.active.load(Ordering::Relaxed))
+            .map(move |key| (key.name.as_str(), key.remaining(now)))
+    }
+
+    pub fn to_json(&self) -> OwnedValue {
+        let now = unix_secs();
+        self.keys.iter().map(|key| {
+            let (remaining, reset) = key.remaining(now).unzip();
+            json!({
+                "key": key.name.as_str(),
+                "in_rotation": key.active.load(Ordering::Relaxed),
+                "RateLimit-Remaining": remaining,
+                "RateLimit-Reset": reset,
+                "in_flight": key.in_flight.load(Ordering::Relaxed),
+                "calls": key.calls.to_json(),
+                "forecast": key.rate_limit.forecast(now).map(|forecast| forecast.to_json()),
+                "history": key.rate_limit.history().into_iter().map(|sample| sample.to_json()).collect::<Vec<_>>(),
+            })
+        }).collect::<Vec<_>>().into()
+    }
+}
+
+impl ApiKey {
+    fn new(key: &str) -> Self {
+        let mut header = HeaderValue::from_str(key).expect("the config should check api keys are valid header values!");
+        header.set_sensitive(true);
+        let name = match key.char_indices().rev().nth(3) {
+            Some((start, _)) if key.len() > 8 => format!("...{}", &key[start..]),
+            _ => "...".to_string(),
+        };
+        Self { header, name, rate_limit: RateLimit::new(), calls: WindowCounter::new(), in_flight: AtomicU64::new(0), active: AtomicBool::new(true) }
+    }
+
+    pub fn header(&self) -> &HeaderValue {
+        &self.header
+    }
+
+    /// Counts a request as in flight on this key until the returned guard is dropped.
+    pub fn start(&self) -> impl Deferred + '_ {
+        self.calls.increment();
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        defer(|| self.in_flight.fetch_sub(1, Ordering::Relaxed))
+    }
+
+    /// Records the budget hypixel reported in a response made with this key.
+    pub fn record(&self, headers: &HeaderMap) {
+        if let Some((remaining, reset)) = stats_from_headers(headers) {
+            self.rate_limit.record(remaining, reset, unix_secs());
+        }
+    }
+
+    /// Takes the key out of rotation, called when hypixel rejects it.
+    pub fn revoke(&self) {
+        if self.active.swap(false, Ordering::Relaxed) {
+            log(Level::Warn, LogMessage::ApiKeyRevoked { name: self.name.clone() });
+        }
+    }
+
+    /// The last reported budget and seconds until it resets, `None` if it's unknown or has already reset.
+    fn remaining(&self, now: u64) -> Option<(u64, u64)> {
+        self.rate_limit.current(now)
+    }
+
+    fn budget(&self, now: u64) -> u64 {
+        self.remaining(now).map_or(u64::MAX, |(remaining, _)| remaining)
+    }
+}
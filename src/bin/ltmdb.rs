@@ -0,0 +1,240 @@
+//! Inspects and maintains an ltmdb data directory, such as the proxy's `storage.path`, without loading it.
+//!
+//! Keys are printed and given as hex, since the proxy's keys are binary. Values the proxy stores are lz4 compressed
+//! with their length prepended, `--lz4` decompresses them.
+//!
+//! `purge` and `compact` change the directory in place, and must not be run while the proxy has it open.
+
+use std::{env, fs, fmt::Write as _, io::{self, Write}, process::ExitCode, time::{SystemTime, UNIX_EPOCH}};
+
+use lz4_flex::decompress_size_prepended;
+use ltmdb::offline::{self, Location, PartitionFile, StoredEntry};
+use simd_json::{json, prelude::MutableObject};
+
+const USAGE: &str = "\
+Usage: ltmdb <command> <dir> [options]
+
+Commands:
+  list <dir>                          buckets and partitions, with their entry counts and sizes.
+  dump <dir> <key> [--lz4] [--text]   writes the newest value of a hex key to stdout, --text takes the key as text instead.
+  export <dir> [--lz4]                every live value as json lines, --lz4 leaves values that aren't compressed as they are.
+  verify <dir>                        checks every partition parses, printing those that don't to stderr and exiting with 1.
+  purge <dir> [--dry-run]             deletes expired partitions.
+  compact <dir> [--dry-run]           drops values replaced or removed by newer entries, deleting partitions left empty.
+
+purge and compact must not be run while the database is open.
+";
+
+/// A failed command, printed to stderr.
+type Failure = String;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let positional: Vec<&str> = args.iter().filter(|arg| !arg.starts_with("--")).map(String::as_str).collect();
+
+    let result = match positional.as_slice() {
+        ["list", dir] => list(dir),
+        ["dump", dir, key] => dump(dir, key, flag("--text"), flag("--lz4")),
+        ["export", dir] => export(dir, flag("--lz4")),
+        ["verify", dir] => verify(dir),
+        ["purge", dir] => purge(dir, flag("--dry-run")),
+        ["compact", dir] => compact(dir, flag("--dry-run")),
+        _ => {
+            eprint!("{USAGE}");
+            return ExitCode::from(2)
+        }
+    };
+    match result {
+        Ok(code) => code,
+        Err(failure) => {
+            eprintln!("{failure}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn list(dir: &str) -> Result<ExitCode, Failure> {
+    let partitions = scan(dir)?;
+    let now = unix_secs();
+    let mut out = io::stdout().lock();
+    let mut buckets = partitions.chunk_by(|a, b| a.bucket == b.bucket).peekable();
+    if buckets.peek().is_none() {
+        writeln!(out, "{dir} holds no partitions").map_err(|e| e.to_string())?;
+    }
+    for bucket in buckets {
+        let counts = bucket.iter().map(count).collect::<Result<Vec<_>, _>>()?;
+        writeln!(
+            out,
+            "bucket {}s: {} partitions, {} entries, {} bytes",
+            bucket[0].bucket.as_secs(),
+            bucket.len(),
+            counts.iter().sum::<usize>(),
+            bucket.iter().map(|partition| partition.len).sum::<u64>(),
+        ).map_err(|e| e.to_string())?;
+        for (partition, entries) in bucket.iter().zip(counts) {
+            let expiry = match partition.expires_at() {
+                expires_at if expires_at <= now => "expired".to_string(),
+                expires_at => format!("expires in {}s", expires_at - now),
+            };
+            writeln!(out, "  partition {}: {entries} entries, {} bytes, {expiry}", partition.created, partition.len).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn dump(dir: &str, key: &str, text: bool, lz4: bool) -> Result<ExitCode, Failure> {
+    let key = if text { key.as_bytes().to_vec() } else { unhex(key)? };
+    let partitions = scan(dir)?;
+    let latest = offline::latest(&partitions).map_err(|e| e.to_string())?;
+    let Some(location) = latest.get(key.as_slice()) else {
+        return Err(format!("{} isn't stored in {dir}", hex(&key)))
+    };
+
+    let partition = &partitions[location.partition];
+    let entry = read(partition, *location)?;
+    let Some(value) = entry.value else {
+        return Err(format!("{} was removed from {dir}", hex(&key)))
+    };
+    if partition.expires_at() <= unix_secs() {
+        eprintln!("partition {} has expired, this value would be purged once the database loads", partition.created);
+    }
+    let value = if lz4 { decompress(&value)? } else { value.to_vec() };
+    io::stdout().lock().write_all(&value).map_err(|e| e.to_string())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Writes a json line per live key, with the value as text if it's utf-8 and as hex otherwise.
+/// With `lz4`, values that aren't compressed, such as the proxy's api tokens, are written as they're stored.
+fn export(dir: &str, lz4: bool) -> Result<ExitCode, Failure> {
+    let partitions = scan(dir)?;
+    let latest = offline::latest(&partitions).map_err(|e| e.to_string())?;
+    let now = unix_secs();
+    let mut out = io::stdout().lock();
+    for (index, partition) in partitions.iter().enumerate() {
+        if partition.expires_at() <= now { continue }
+        for entry in partition.entries().map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            if latest.get(&entry.key) != Some(&Location { partition: index, offset: entry.offset }) { continue }
+            let Some(value) = entry.value else { continue }; // removed
+
+            let value = if lz4 { decompress(&value).unwrap_or_else(|_| value.to_vec()) } else { value.to_vec() };
+            let mut line = json!({
+                "key": hex(&entry.key),
+                "bucket_seconds": partition.bucket.as_secs(),
+                "partition": partition.created,
+                "expires_at": partition.expires_at(),
+            });
+            match String::from_utf8(value) {
+                Ok(value) => line.insert("value", value).ok(),
+                Err(value) => line.insert("value_hex", hex(value.as_bytes())).ok(),
+            };
+            writeln!(out, "{}", simd_json::to_string(&line).unwrap_or_default()).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify(dir: &str) -> Result<ExitCode, Failure> {
+    let partitions = scan(dir)?;
+    let mut bad = 0;
+    for partition in &partitions {
+        if let Err(e) = count(partition) {
+            eprintln!("{e}");
+            bad += 1;
+        }
+    }
+    println!("{} of {} partitions parsed", partitions.len() - bad, partitions.len());
+    Ok(if bad == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn purge(dir: &str, dry_run: bool) -> Result<ExitCode, Failure> {
+    let now = unix_secs();
+    let (mut purged, mut freed) = (0, 0);
+    for partition in scan(dir)?.iter().filter(|partition| partition.expires_at() <= now) {
+        if !dry_run {
+            fs::remove_file(&partition.path).map_err(|e| format!("{}: {e}", partition.path.display()))?;
+        }
+        println!("{} {}", if dry_run { "would purge" } else { "purged" }, partition.path.display());
+        purged += 1;
+        freed += partition.len;
+    }
+    println!("{purged} expired partitions, {freed} bytes");
+    Ok(ExitCode::SUCCESS)
+}
+
+fn compact(dir: &str, dry_run: bool) -> Result<ExitCode, Failure> {
+    let partitions = scan(dir)?;
+    let latest = offline::latest(&partitions).map_err(|e| e.to_string())?;
+    let is_latest = |index: usize, entry: &StoredEntry| latest.get(&entry.key) == Some(&Location { partition: index, offset: entry.offset });
+
+    let mut freed = 0;
+    for (index, partition) in partitions.iter().enumerate() {
+        let replaced = if dry_run {
+            let mut replaced = 0;
+            for entry in partition.entries().map_err(|e| e.to_string())? {
+                let entry = entry.map_err(|e| e.to_string())?;
+                if !is_latest(index, &entry) { replaced += entry.stored_len() }
+            }
+            replaced
+        } else {
+            partition.rewrite(|entry| is_latest(index, entry)).map_err(|e| e.to_string())?
+        };
+        if replaced > 0 {
+            println!("{} {replaced} bytes from {}", if dry_run { "would free" } else { "freed" }, partition.path.display());
+            freed += replaced;
+        }
+    }
+    println!("{freed} bytes of replaced values");
+    Ok(ExitCode::SUCCESS)
+}
+
+fn scan(dir: &str) -> Result<Vec<PartitionFile>, Failure> {
+    offline::scan(dir).map_err(|e| format!("{dir}: {e}"))
+}
+
+/// Entries in a partition, failing if it doesn't parse.
+fn count(partition: &PartitionFile) -> Result<usize, Failure> {
+    let mut entries = 0;
+    for entry in partition.entries().map_err(|e| format!("{}: {e}", partition.path.display()))? {
+        entry.map_err(|e| e.to_string())?;
+        entries += 1;
+    }
+    Ok(entries)
+}
+
+fn read(partition: &PartitionFile, location: Location) -> Result<StoredEntry, Failure> {
+    for entry in partition.entries().map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if entry.offset == location.offset {
+            return Ok(entry)
+        }
+    }
+    Err(format!("{} changed while it was read", partition.path.display()))
+}
+
+/// Values are written by the proxy's `cache::compression::compress`.
+fn decompress(value: &[u8]) -> Result<Vec<u8>, Failure> {
+    decompress_size_prepended(value).map_err(|e| format!("value isn't lz4 compressed: {e}"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
+
+fn unhex(key: &str) -> Result<Vec<u8>, Failure> {
+    if !key.len().is_multiple_of(2) {
+        return Err(format!("{key} isn't hex, use --text for a text key"))
+    }
+    (0..key.len()).step_by(2)
+        .map(|i| key.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| format!("{key} isn't hex, use --text for a text key"))
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -0,0 +1,185 @@
+//! A stand-in for the hypixel api, so the proxy can be run and tested without network access or an api key.
+//! Point the proxy at it with `UPSTREAM_URL=http://127.0.0.1:<MOCK_PORT>`.
+//!
+//! The response is picked by the first group of the requested uuid:
+//! - `00000403-...` responds 403 as hypixel does for an invalid key, whatever key was sent.
+//! - `00000429-...` responds 429 with a `Retry-After` header.
+//! - `00000500-...` and `00000503-...` respond with that server error.
+//! - `00000502-...` responds 502 to every other request, starting with the first.
+//! - `0000de1a-...` waits `MOCK_DELAY_MILLIS` before serving a fixture.
+//! - `0000fa15-...` responds 200 with `success: false`.
+//! - `0000bad0-...` responds 200 with a truncated body.
+//! - `0000b16f-...` streams a large profile in chunks, without a `Content-Length`.
+//! - `0000d1ed-...` starts streaming a profile, then drops the connection part way.
+//! - `0000404f-...` responds as hypixel does for an unknown player, with the requested field `null`.
+//! - anything else serves a fixture.
+//!
+//! Requests without an `API-Key` header, or with the key `revoked`, are rejected with a 403.
+//!
+//! `/mock/hits/{endpoint}/{uuid}` returns how many times `endpoint` (`profiles` or `player`) was requested for a uuid.
+
+use std::{collections::HashMap, str::FromStr, sync::{Mutex, atomic::{AtomicU64, Ordering}}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use actix_web::{App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer, Responder, get, http::StatusCode, web::{Bytes, Data, Path, Query}};
+use futures::{Stream, StreamExt, stream::iter};
+use serde::Deserialize;
+use simd_json::{OwnedValue, json};
+use tokio::time::sleep;
+use uuid::Uuid;
+
+/// Requests allowed per rate limit window, reported in `RateLimit-Limit`.
+const RATE_LIMIT: u64 = 300;
+/// Length of a rate limit window in seconds.
+const RATE_LIMIT_WINDOW: u64 = 300;
+
+struct MockState {
+    requests: AtomicU64,
+    hits: Mutex<HashMap<String, u64>>,
+    delay: Duration,
+}
+
+#[derive(Deserialize)]
+struct UuidQuery {
+    uuid: Option<String>,
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let port: u16 = var("MOCK_PORT", 8100);
+    let delay = Duration::from_millis(var("MOCK_DELAY_MILLIS", 500));
+    println!("Mock hypixel listening on 127.0.0.1:{port}!");
+
+    let state = Data::new(MockState { requests: AtomicU64::new(0), hits: Mutex::new(HashMap::new()), delay });
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .service(profiles)
+            .service(player)
+            .service(hits)
+    })
+    .bind(("127.0.0.1", port))?
+    .run()
+    .await
+}
+
+#[get("/v2/skyblock/profiles")]
+async fn profiles(req: HttpRequest, query: Query<UuidQuery>, state: Data<MockState>) -> HttpResponse {
+    respond(&req, query.into_inner(), &state, "profiles", |uuid| json!({
+        "success": true,
+        "profiles": [{
+            "profile_id": uuid.to_string(),
+            "cute_name": "Mock",
+            "selected": true,
+            "members": { uuid.simple().to_string(): { "player_data": { "experience": { "SKILL_MINING": 1000.0 } } } },
+        }],
+    })).await
+}
+
+#[get("/v2/player")]
+async fn player(req: HttpRequest, query: Query<UuidQuery>, state: Data<MockState>) -> HttpResponse {
+    respond(&req, query.into_inner(), &state, "player", |uuid| json!({
+        "success": true,
+        "player": {
+            "uuid": uuid.simple().to_string(),
+            "displayname": "Mock",
+            "achievements": { "skyblock_treasure_hunter": 42 },
+        },
+    })).await
+}
+
+#[get("/mock/hits/{endpoint}/{uuid}")]
+async fn hits(path: Path<(String, String)>, state: Data<MockState>) -> impl Responder {
+    let (endpoint, uuid) = path.into_inner();
+    let count = state.hits.lock().unwrap().get(&format!("{endpoint}/{uuid}")).copied().unwrap_or(0);
+    HttpResponse::Ok().body(count.to_string())
+}
+
+async fn respond(req: &HttpRequest, query: UuidQuery, state: &MockState, endpoint: &str, fixture: impl FnOnce(Uuid) -> OwnedValue) -> HttpResponse {
+    let count = state.requests.fetch_add(1, Ordering::Relaxed) + 1;
+    let remaining = RATE_LIMIT.saturating_sub(count % RATE_LIMIT);
+
+    if req.headers().get("API-Key").is_none_or(|key| key == "revoked") {
+        return failure(StatusCode::FORBIDDEN, remaining, "Invalid API key");
+    }
+    let Some(uuid) = query.uuid else {
+        return failure(StatusCode::BAD_REQUEST, remaining, "Missing one or more fields [uuid]");
+    };
+    let Ok(uuid) = Uuid::from_str(&uuid) else {
+        return failure(StatusCode::UNPROCESSABLE_ENTITY, remaining, "Malformed UUID");
+    };
+
+    let served = {
+        let mut counts = state.hits.lock().unwrap();
+        let served = counts.entry(format!("{endpoint}/{uuid}")).or_insert(0);
+        *served += 1;
+        *served
+    };
+
+    match uuid.as_fields().0 {
+        0x0000_0403 => failure(StatusCode::FORBIDDEN, remaining, "Invalid API key"),
+        0x0000_0429 => {
+            let mut res = failure(StatusCode::TOO_MANY_REQUESTS, 0, "Key throttle");
+            res.headers_mut().insert("Retry-After".parse().unwrap(), reset().to_string().parse().unwrap());
+            res
+        }
+        0x0000_0500 => failure(StatusCode::INTERNAL_SERVER_ERROR, remaining, "Internal error"),
+        0x0000_0502 if served % 2 == 1 => failure(StatusCode::BAD_GATEWAY, remaining, "Bad gateway"),
+        0x0000_0503 => failure(StatusCode::SERVICE_UNAVAILABLE, remaining, "Service unavailable"),
+        0x0000_fa15 => failure(StatusCode::OK, remaining, "Mock failure"),
+        0x0000_b16f => rate_limited(HttpResponse::Ok(), remaining).content_type("application/json").streaming(chunked_profiles(uuid, false)),
+        0x0000_d1ed => rate_limited(HttpResponse::Ok(), remaining).content_type("application/json").streaming(chunked_profiles(uuid, true)),
+        // the endpoint names match the field each one returns.
+        0x0000_404f => rate_limited(HttpResponse::Ok(), remaining).json(json!({ "success": true, endpoint: null })),
+        0x0000_bad0 => rate_limited(HttpResponse::Ok(), remaining).content_type("application/json").body(r#"{"success":true,"prof"#),
+        first => {
+            if first == 0x0000_de1a {
+                sleep(state.delay).await;
+            }
+            rate_limited(HttpResponse::Ok(), remaining).json(fixture(uuid))
+        }
+    }
+}
+
+/// Number of profiles in a streamed response, each padded to roughly `STREAMED_PROFILE_BYTES`.
+const STREAMED_PROFILES: usize = 16;
+const STREAMED_PROFILE_BYTES: usize = 32 * 1024;
+
+/// A profiles response sent one profile per chunk, failing half way through if `broken`.
+fn chunked_profiles(uuid: Uuid, broken: bool) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+    let chunks = (0..=STREAMED_PROFILES + 1).map(move |index| match index {
+        0 => Ok(Bytes::from_static(br#"{"success":true,"profiles":["#)),
+        _ if broken && index == STREAMED_PROFILES / 2 => Err(std::io::Error::other("mock stream broken")),
+        _ if index == STREAMED_PROFILES + 1 => Ok(Bytes::from_static(b"]}")),
+        _ => {
+            let separator = if index == 1 { "" } else { "," };
+            let padding = "x".repeat(STREAMED_PROFILE_BYTES);
+            Ok(Bytes::from(format!(r#"{separator}{{"profile_id":"{uuid}","cute_name":"Mock {index}","padding":"{padding}"}}"#)))
+        }
+    });
+    iter(chunks).then(|chunk| async move {
+        sleep(Duration::from_millis(20)).await;
+        chunk
+    })
+}
+
+fn failure(status: StatusCode, remaining: u64, cause: &str) -> HttpResponse {
+    rate_limited(HttpResponse::build(status), remaining).json(json!({ "success": false, "cause": cause }))
+}
+
+fn rate_limited(mut res: HttpResponseBuilder, remaining: u64) -> HttpResponseBuilder {
+    res.insert_header(("RateLimit-Limit", RATE_LIMIT))
+        .insert_header(("RateLimit-Remaining", remaining))
+        .insert_header(("RateLimit-Reset", reset()));
+    res
+}
+
+/// Seconds until the current rate limit window resets.
+fn reset() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    RATE_LIMIT_WINDOW - now % RATE_LIMIT_WINDOW
+}
+
+fn var<T: FromStr>(key: &str, default: T) -> T {
+    std::env::var(key).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
@@ -0,0 +1,206 @@
+use std::sync::atomic::Ordering;
+
+use portable_atomic::AtomicU128;
+use simd_json::{OwnedValue, json};
+
+use crate::{cache::{cache_stats::WindowCounter, unix_secs}, config::config, error::ProcessError, logging::{Level, LogMessage, log}};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// requests go upstream, failures are counted.
+    Closed,
+    /// requests fail fast without going upstream.
+    Open,
+    /// a limited number of probes go upstream, the first result decides whether the circuit closes or opens again.
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+
+    const fn from_u32(value: u32) -> Self {
+        match value {
+            1 => Self::Open,
+            2 => Self::HalfOpen,
+            _ => Self::Closed,
+        }
+    }
+}
+
+/// Stops sending requests upstream while it keeps failing, so cache misses fail fast instead of each waiting on a timeout.
+///
+/// The state is packed into a single atomic u128, laid out as
+/// ```text
+/// | u32 state | u32 count | u64 since |
+/// | 128..96   | 95..64    | 63..0     |
+/// ```
+/// where count is the consecutive failures while closed and the probes in flight while half open,
+/// and since is the unix time in seconds the state was entered.
+pub struct CircuitBreaker {
+    state: AtomicU128,
+    opened: WindowCounter,
+    half_opened: WindowCounter,
+    closed: WindowCounter,
+    rejected: WindowCounter,
+}
+
+/// Allows a single request upstream, retries included, reporting its result back to the breaker.
+#[must_use = "the result of the request should be reported to the breaker."]
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    /// whether this is one of the half open state's probes, only their results can close or reopen it.
+    probe: bool,
+    /// when the state the permit was given in was entered, so results from an earlier half open state are ignored.
+    since: u64,
+    reported: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self {
+            state: AtomicU128::new(pack(BreakerState::Closed, 0, 0)),
+            opened: WindowCounter::new(),
+            half_opened: WindowCounter::new(),
+            closed: WindowCounter::new(),
+            rejected: WindowCounter::new(),
+        }
+    }
+
+    /// # Errors
+    /// Returns `ProcessError::Unavailable` if the circuit is open, or half open with every probe already in flight.
+    pub fn acquire(&self) -> Result<Permit<'_>, ProcessError> {
+        let now = unix_secs();
+        let result = self.state.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+            let (state, count, since) = unpack(value);
+            match state {
+                BreakerState::Open if now.saturating_sub(since) >= config().breaker.open_seconds => Some(pack(BreakerState::HalfOpen, 1, now)),
+                BreakerState::HalfOpen if count < config().breaker.probes => Some(pack(BreakerState::HalfOpen, count + 1, since)),
+                _ => None, // closed needs no update, and anything else is rejected.
+            }
+        });
+
+        match result.map(unpack) {
+            Err(value) if unpack(value).0 == BreakerState::Closed => Ok(Permit { breaker: self, probe: false, since: unpack(value).2, reported: false }),
+            Ok((BreakerState::Open, ..)) => {
+                self.transition(BreakerState::Open, BreakerState::HalfOpen);
+                Ok(Permit { breaker: self, probe: true, since: now, reported: false })
+            }
+            Ok((_, _, since)) => Ok(Permit { breaker: self, probe: true, since, reported: false }),
+            Err(_) => {
+                self.rejected.increment();
+                Err(ProcessError::Unavailable("Hypixel api is failing, try again later."))
+            }
+        }
+    }
+
+    fn success(&self, permit: &Permit) {
+        let now = unix_secs();
+        let result = self.state.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+            match unpack(value) {
+                (BreakerState::Closed, 0, _) | (BreakerState::Open, ..) => None, // results from before the circuit opened are ignored.
+                (BreakerState::Closed, _, since) => Some(pack(BreakerState::Closed, 0, since)),
+                (BreakerState::HalfOpen, _, since) if permit.is_probe_of(since) => Some(pack(BreakerState::Closed, 0, now)),
+                (BreakerState::HalfOpen, ..) => None, // only this half open state's probes decide it.
+            }
+        });
+        if let Ok(value) = result && unpack(value).0 == BreakerState::HalfOpen {
+            self.transition(BreakerState::HalfOpen, BreakerState::Closed);
+        }
+    }
+
+    fn failure(&self, permit: &Permit) {
+        let now = unix_secs();
+        let result = self.state.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+            match unpack(value) {
+                (BreakerState::Closed, count, since) if count + 1 < config().breaker.failures => Some(pack(BreakerState::Closed, count + 1, since)),
+                (BreakerState::Closed, ..) => Some(pack(BreakerState::Open, 0, now)),
+                (BreakerState::HalfOpen, _, since) if permit.is_probe_of(since) => Some(pack(BreakerState::Open, 0, now)),
+                (BreakerState::HalfOpen | BreakerState::Open, ..) => None,
+            }
+        });
+        if let Ok(value) = result {
+            let (state, count, _) = unpack(value);
+            if state == BreakerState::HalfOpen || count + 1 >= config().breaker.failures {
+                self.transition(state, BreakerState::Open);
+            }
+        }
+    }
+
+    /// Frees the slot of a probe that ended without a result, such as when its request was dropped.
+    fn release(&self, permit: &Permit) {
+        let _ = self.state.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+            match unpack(value) {
+                (BreakerState::HalfOpen, count, since) if permit.is_probe_of(since) => Some(pack(BreakerState::HalfOpen, count.saturating_sub(1), since)),
+                _ => None,
+            }
+        });
+    }
+
+    fn transition(&self, from: BreakerState, to: BreakerState) {
+        match to {
+            BreakerState::Open => self.opened.increment(),
+            BreakerState::HalfOpen => self.half_opened.increment(),
+            BreakerState::Closed => self.closed.increment(),
+        }
+        log(Level::Warn, LogMessage::BreakerTransition { from: from.name(), to: to.name() });
+    }
+
+    pub fn state(&self) -> BreakerState {
+        unpack(self.state.load(Ordering::Relaxed)).0
+    }
+
+    pub fn to_json(&self) -> OwnedValue {
+        let (state, count, since) = unpack(self.state.load(Ordering::Relaxed));
+        json!({
+            "state": state.name(),
+            "consecutive_failures": if state == BreakerState::Closed { count } else { 0 },
+            "since": since,
+            "opened": self.opened.to_json(),
+            "half_opened": self.half_opened.to_json(),
+            "closed": self.closed.to_json(),
+            "rejected": self.rejected.to_json(),
+        })
+    }
+}
+
+impl Permit<'_> {
+    pub fn success(mut self) {
+        self.reported = true;
+        self.breaker.success(&self);
+    }
+
+    pub fn failure(mut self) {
+        self.reported = true;
+        self.breaker.failure(&self);
+    }
+
+    /// Whether this permit is a probe of the half open state entered at `since`.
+    fn is_probe_of(&self, since: u64) -> bool {
+        self.probe && self.since == since
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.reported {
+            self.breaker.release(self);
+        }
+    }
+}
+
+#[inline]
+fn pack(state: BreakerState, count: u32, since: u64) -> u128 {
+    u128::from(state as u32) << 96 | u128::from(count) << 64 | u128::from(since)
+}
+
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+fn unpack(value: u128) -> (BreakerState, u32, u64) {
+    (BreakerState::from_u32((value >> 96) as u32), (value >> 64) as u32, value as u64)
+}
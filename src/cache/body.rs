@@ -0,0 +1,95 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::web::{Bytes, BytesMut};
+use futures::{Stream, stream::unfold};
+use tokio::sync::Notify;
+
+use crate::error::ProcessError;
+
+/// A cached value, or one still being streamed in from upstream.
+#[derive(Clone)]
+pub enum Body {
+    Full(Bytes),
+    Streaming(SharedBody),
+}
+
+/// A body read from upstream chunk by chunk, which any number of clients can stream from at once.
+/// Each stream starts from the first chunk, no matter how late it joins.
+#[derive(Clone)]
+pub struct SharedBody {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    chunks: Vec<Bytes>,
+    len: usize,
+    /// set once the body is complete or has failed.
+    end: Option<Result<(), ProcessError>>,
+}
+
+impl SharedBody {
+    pub fn new() -> Self {
+        Self { inner: Arc::new(Inner { state: Mutex::new(State::default()), notify: Notify::new() }) }
+    }
+
+    pub fn push(&self, chunk: Bytes) {
+        let mut state = self.inner.state.lock().expect("Shared body should never be poisoned");
+        state.len += chunk.len();
+        state.chunks.push(chunk);
+        drop(state);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Ends the body. If `result` is an error, streams fail with it rather than ending cleanly.
+    pub fn finish(&self, result: Result<(), ProcessError>) {
+        self.inner.state.lock().expect("Shared body should never be poisoned").end = Some(result);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Every chunk pushed so far, joined.
+    pub fn collect(&self) -> Bytes {
+        let state = self.inner.state.lock().expect("Shared body should never be poisoned");
+        let mut body = BytesMut::with_capacity(state.len);
+        for chunk in &state.chunks {
+            body.extend_from_slice(chunk);
+        }
+        body.freeze()
+    }
+
+    pub fn stream(&self) -> impl Stream<Item = Result<Bytes, ProcessError>> + 'static {
+        unfold((self.inner.clone(), 0, false), |(inner, index, ended)| async move {
+            if ended { return None }
+            let next = loop {
+                // registered before the state is checked, so a chunk pushed in between still wakes us.
+                let notified = inner.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+
+                let next = {
+                    let state = inner.state.lock().expect("Shared body should never be poisoned");
+                    match (state.chunks.get(index), &state.end) {
+                        (Some(chunk), _) => Some(Ok(chunk.clone())),
+                        (None, Some(Err(err))) => Some(Err(err.clone())),
+                        (None, Some(Ok(()))) => return None,
+                        (None, None) => None,
+                    }
+                };
+                match next {
+                    Some(next) => break next,
+                    None => notified.await,
+                }
+            };
+
+            match next {
+                Ok(chunk) => Some((Ok(chunk), (inner, index + 1, false))),
+                Err(err) => Some((Err(err), (inner, index, true))),
+            }
+        })
+    }
+}
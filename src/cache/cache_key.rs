@@ -1,25 +1,66 @@
 use std::time::Duration;
 
 use actix_web::web::Bytes;
-use uuid::Uuid;
+use reqwest::Response;
+use tokio::time::Instant;
 
-use crate::{cache::{UuidKey, cache_router::Database}, error::ProcessError, routes::stats::RateLimit};
+use crate::{api_keys::ApiKeys, cache::{EncodedKey, KeyId}, error::ProcessError, retry::RetryPolicy, validation::Validator};
 
-pub trait CacheKey: Send {
+/// The layers of the `CacheRouter` a `CacheKey` keeps its values in.
+/// Upstream is always used when every enabled layer misses.
+pub struct Tiers {
+    pub memory: bool,
+    /// values are compressed before being stored.
+    pub storage: bool,
+}
+
+/// What `CacheKey::fetch` got from upstream.
+pub enum Fetched {
+    Body(Bytes),
+    /// a response whose body hasn't been read yet. Large bodies are streamed to clients as they arrive,
+    /// and only cached once they're complete and pass `CacheKey::VALIDATOR`.
+    Response(Response),
+}
+
+pub trait CacheKey: Send + Sync {
     /// flag for db storage/etc. 
     /// MUST be unique across implementations of `CacheKey`.
-    /// can only store a max of 8 values rn
+    /// keys with a `Uuid` id can only store a max of 8 values rn
     const KEYFLAG: u8;
+
+    /// name this key type is reported under in `/stats`.
+    const NAME: &'static str;
+
+    const TIERS: Tiers;
+
+    /// how upstream requests made by `fetch` are retried.
+    const RETRY: RetryPolicy = RetryPolicy::DEFAULT;
+
+    /// checks upstream payloads before `fetch` returns them, so nothing invalid is cached.
+    const VALIDATOR: Validator;
+
+    /// the id this key is looked up by, before it is namespaced by `KEYFLAG`.
+    type Id: KeyId + ?Sized;
     
-    fn uuid(&self) -> Uuid;
+    fn id(&self) -> &Self::Id;
+
+    /// how long values of this key are kept in the memory cache.
+    fn memory_ttl(&self) -> Duration;
+
+    /// how long values of this key are kept in storage. Only used if `TIERS.storage` is set.
+    fn storage_ttl(&self) -> Duration {
+        self.memory_ttl()
+    }
 
-    /// This function is run when this key results in a cache miss on the memory cache.
-    /// If this function returns `Ok()`, it will add the Bytes into the memory cache.
-    /// Otherwise, no entry will be added to the memory cache and the error should be
-    /// propegated upwards.
-    fn get_or_insert(&self, db: &Database, stats: &RateLimit) -> impl Future<Output = Result<(Bytes, Duration), ProcessError>> + Send;
+    /// This function is run when this key misses every tier it uses.
+    /// If this function returns `Ok()`, the body will be added to each of those tiers.
+    /// Otherwise, nothing will be cached and the error should be propegated upwards.
+    ///
+    /// `deadline` is when the request being answered runs out of its `RETRY` budget, counted from when it arrived,
+    /// so time already spent on peers and storage isn't given to upstream again.
+    fn fetch(&self, keys: &ApiKeys, deadline: Instant) -> impl Future<Output = Result<Fetched, ProcessError>> + Send;
     
-    fn key(&self) -> UuidKey {
-        UuidKey::encode(self.uuid(), Self::KEYFLAG)
+    fn key(&self) -> EncodedKey {
+        self.id().encode(Self::KEYFLAG)
     }
-}
\ No newline at end of file
+}
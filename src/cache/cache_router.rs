@@ -1,63 +1,377 @@
-use std::sync::LazyLock;
+use std::{collections::HashMap, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, time::Duration};
 
 use actix_web::web::Bytes;
-use ltmdb::{ResultExt, Runtime};
+use reqwest::Response;
+use ltmdb::{CloseSummary, ResultExt, Runtime};
 use pingora_memory_cache::MemoryCache;
 use rapidhash_lite::RandomHash;
+use simd_json::{OwnedValue, json};
 use simple_defer::{Deferred, defer};
 use single_flight::Group;
 use tokio::{spawn, task::spawn_blocking, time::{Instant, sleep}};
 
-use crate::{cache::{UuidKey, cache_key::CacheKey}, env_var, error::ProcessError, logging::{LogMessage, log}, routes::stats::RateLimit};
+use crate::{api_keys::ApiKeys, cache::{EncodedKey, body::{Body, SharedBody}, cache_key::{CacheKey, Fetched, Tiers}, cache_stats::{CacheStats, Tier}, compression::{compress, decompress, decompressed_len}, storage::{AnyStorage, Storage}, unix_secs}, config::config, error::ProcessError, logging::{Level, LogMessage, log}, peers::{PeerError, Peers}, request_id::{self, RequestId}, validation::Validator};
 
-// pingora_memory_cache::MemoryCache doesnt give us access to TinyUFO's weight handling, instead being sized by number of entries.
-static CACHE_SIZE: LazyLock<usize> = LazyLock::new(|| env_var("CACHE_SIZE", 256));
+/// Routes cache requests to the memory cache and storage.
+/// which tiers are used and how values are fetched is handled via the `CacheKey` trait.
+pub struct CacheRouter<S = AnyStorage> {
+    layers: Arc<Layers<S>>,
+    /// values are shared with the leader's request id, so followers can log who they waited on.
+    group: Group<EncodedKey, (Body, Tier, Option<RequestId>), ProcessError, RandomHash>,
+    stats: CacheStats,
+    peers: Option<Peers>,
+}
+
+/// A value returned by the router, and where it came from.
+pub struct Served {
+    pub body: Body,
+    pub source: Source,
+}
+
+/// Where a served value came from, attached to responses so middleware can see it.
+#[derive(Clone, Copy)]
+pub struct Source {
+    /// the layer that answered, followers share their leader's.
+    pub tier: Tier,
+    pub flight: Flight,
+}
 
-pub type Database = ltmdb::Database<TokioRT, RandomHash>;
+/// A request's part in its key's single flight group.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Flight {
+    /// answered from memory before joining a group.
+    None,
+    /// did the work for the group.
+    Leader,
+    /// waited on another request's work.
+    Follower,
+}
+
+impl Flight {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::None => "-",
+            Self::Leader => "leader",
+            Self::Follower => "follower",
+        }
+    }
+}
 
-/// Routes cache requests to the memory cache and db cache.
-/// behavior during insertion is handled via the `CacheKey` trait.
-pub struct CacheRouter {
-    cache: MemoryCache<UuidKey, Bytes>,
-    database: Database,
-    group: Group<UuidKey, Bytes, ProcessError, RandomHash>,
+/// The parts of the router a streamed body is cached into, shared with the task reading it.
+struct Layers<S> {
+    cache: MemoryCache<EncodedKey, Bytes>,
+    storage: S,
+    /// bodies still being streamed in, so requests for them join the stream rather than fetching again.
+    filling: Mutex<HashMap<EncodedKey, SharedBody, RandomHash>>,
 }
 
 impl CacheRouter {
     pub async fn load() -> Result<Self, ProcessError> {
         let now = Instant::now();
-        let database = Database::load(".db").await?;
-        log(LogMessage::TimeElapsed { elapsed: now.elapsed(), name: "database load" });
-        Ok(Self { cache: MemoryCache::new(*CACHE_SIZE), database, group: Group::with_hasher(RandomHash::default()) })
+        let (kind, path) = {
+            let config = config();
+            (config.storage.kind, config.storage.path.clone())
+        };
+        let storage = AnyStorage::load(kind, path).await?;
+        log(Level::Info, LogMessage::TimeElapsed { elapsed: now.elapsed(), name: "storage load" });
+        Ok(Self::new(storage, Peers::from_config()))
+    }
+
+    /// Lets bodies still streaming in finish caching, then closes storage. Each gets up to `timeout`.
+    ///
+    /// # Errors
+    /// Returns an error if storage couldn't be closed.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<Shutdown, ProcessError> {
+        let deadline = Instant::now() + timeout;
+        let filling = || self.layers.filling.lock().expect("Filling should never be poisoned").len();
+        while filling() > 0 && Instant::now() < deadline {
+            sleep(Duration::from_millis(10)).await;
+        }
+        let fills_abandoned = filling();
+        let storage = self.layers.storage.close(timeout).await?;
+        Ok(Shutdown { fills_abandoned, storage })
+    }
+}
+
+/// What `CacheRouter::shutdown` did.
+pub struct Shutdown {
+    /// streamed bodies still being read at the deadline, which won't be cached.
+    pub fills_abandoned: usize,
+    pub storage: Option<CloseSummary>,
+}
+
+impl<S: Storage> CacheRouter<S> {
+    pub fn new(storage: S, peers: Option<Peers>) -> Self {
+        // pingora_memory_cache::MemoryCache doesnt give us access to TinyUFO's weight handling, instead being sized by number of entries.
+        let layers = Layers { cache: MemoryCache::new(config().cache.size), storage, filling: Mutex::new(HashMap::default()) };
+        Self { layers: Arc::new(layers), group: Group::with_hasher(RandomHash::default()), stats: CacheStats::new(), peers }
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// The storage tier, shared with anything else that needs to persist values such as api tokens.
+    pub fn storage(&self) -> &S {
+        &self.layers.storage
+    }
+
+    /// Reports where a key is currently cached without fetching it.
+    pub async fn inspect<K: CacheKey>(&self, key: &K) -> Result<OwnedValue, ProcessError> {
+        let k = key.key();
+        let memory = self.layers.cache.get(&k).0.map(|data| json!({ "size": data.len() }));
+
+        let storage = match (self.layers.storage.info(&k), self.layers.storage.ttl(&k)) {
+            (Some(info), ttl) => {
+                // values may have been purged between the info and the read, in which case we still report what we saw.
+                let data = self.layers.storage.read(k.clone()).await?;
+                let fresh_until = info.written_at + key.storage_ttl().as_secs();
+                Some(json!({
+                    "location": info.location,
+                    "written_at": info.written_at,
+                    "fresh_until": fresh_until,
+                    "stale": fresh_until <= unix_secs(),
+                    "expires_at": info.expires_at,
+                    "ttl_secs": ttl.map(|ttl| ttl.as_secs()),
+                    "compressed_size": info.size,
+                    "uncompressed_size": data.as_deref().and_then(decompressed_len),
+                }))
+            }
+            (None, _) => None,
+        };
+
+        Ok(json!({
+            "flag": K::KEYFLAG,
+            "key": k.to_string(),
+            "tiers": { "memory": K::TIERS.memory, "storage": K::TIERS.storage },
+            "memory": memory,
+            "storage": storage,
+            "in_flight": self.group.is_in_flight(&k),
+            "streaming": self.layers.filling(&k).is_some(),
+        }))
+    }
+
+    /// Removes a key from the memory cache and storage, returning `true` if it was stored.
+    pub async fn evict<K: CacheKey>(&self, key: &K) -> Result<bool, ProcessError> {
+        let k = key.key();
+        self.layers.cache.remove(&k);
+        self.layers.storage.delete(k).await
     }
 
     /// Attempts to get the cache entry from the cache or fetches an entry into the cache if there is none.
-    pub async fn get<K: CacheKey>(&self, key: K, rate_limit: &RateLimit) -> Result<Bytes, ProcessError> {
+    /// 
+    /// When peering, keys owned by another instance are requested from their owner before fetching locally.
+    pub async fn get<K: CacheKey>(&self, key: K, keys: &ApiKeys) -> Result<Served, ProcessError> {
+        self.get_inner(key, keys, true, false).await
+    }
+
+    /// Same as `get`, but never asks a peer. Used to answer peers so requests aren't forwarded in circles.
+    pub async fn get_local<K: CacheKey>(&self, key: K, keys: &ApiKeys) -> Result<Served, ProcessError> {
+        self.get_inner(key, keys, false, false).await
+    }
+
+    /// Same as `get`, but skips the memory cache, storage and peers, fetching from upstream to refill them.
+    /// Still joins a fetch of the key that's already in flight.
+    pub async fn get_fresh<K: CacheKey>(&self, key: K, keys: &ApiKeys) -> Result<Served, ProcessError> {
+        self.get_inner(key, keys, false, true).await
+    }
+
+    async fn get_inner<K: CacheKey>(&self, key: K, keys: &ApiKeys, ask_peers: bool, fresh: bool) -> Result<Served, ProcessError> {
+        // counted from here, so waiting on another request's flight, peers and storage all come out of the same budget.
+        let deadline = Instant::now() + K::RETRY.budget;
         let k = key.key();
+        let stats = self.stats.key(K::KEYFLAG, K::NAME);
 
         // we check the cache outside the singleflight group, since it's much more expensive to start that work 
         // just to check the cache if its already there and doesn't need suppression
-        if let (Some(entry), _) = self.cache.get(&k) {
-            return Ok(entry);
+        if let Some(entry) = self.memory_get::<K>(&k).filter(|_| !fresh) {
+            stats.hit(Tier::Memory);
+            return Ok(Served { body: entry, source: Source { tier: Tier::Memory, flight: Flight::None } });
         }
 
-        let drop_logs = defer(|| log(LogMessage::MessageAndUser { key: k, message: "Dropped while in single flight group" }));
+        let drop_logs = defer(|| log(Level::Debug, LogMessage::MessageAndUser { key: k.clone(), message: "Dropped while in single flight group" }));
         
-        // singleflight coelesces the key.get_or_insert requests so we dont duplicate work on quick duplicate requests
+        let key_ref = &k; // lets the work future borrow the key rather than moving it.
+        let led = AtomicBool::new(false);
+        let led_ref = &led;
+
+        // singleflight coelesces the key.fetch requests so we dont duplicate work on quick duplicate requests
         let res = self.group.work(&k, async move {
+            led_ref.store(true, Ordering::Relaxed);
+
             // we check again here since it may have been added between the prior call and when the group started the work.
-            if let (Some(entry), _) = self.cache.get(&k) { 
-                return Ok(entry);
+            if let Some(entry) = self.memory_get::<K>(key_ref).filter(|_| !fresh) { 
+                stats.hit(Tier::Memory);
+                return Ok((entry, Tier::Memory, request_id::current()));
             }
             
-            let (data, ttl) = key.get_or_insert(&self.database, rate_limit).await?;
-            self.cache.put(&k, data.clone(), Some(ttl)); // store the result in the cache BEFORE the end of duplicate suppression
-            Ok(data)
+            let (body, tier) = match self.peer_fetch(key_ref, ask_peers).await? {
+                Some(data) => (Body::Full(data), Tier::Peer),
+                None => self.storage_get_or_fetch(&key, key_ref, keys, fresh, deadline).await?,
+            };
+
+            if let (true, Body::Full(data)) = (K::TIERS.memory, &body) {
+                self.layers.cache.put(key_ref, data.clone(), Some(key.memory_ttl())); // store the result in the cache BEFORE the end of duplicate suppression
+            }
+            stats.hit(tier);
+            Ok((body, tier, request_id::current()))
         }).await;
 
         drop_logs.cancel();
+
+        let led = led.load(Ordering::Relaxed);
+        if led {
+            stats.leaders.increment();
+        } else {
+            stats.followers.increment();
+        }
+        if res.is_err() {
+            stats.errors.increment();
+        }
+        if let (false, Ok((_, _, leader))) = (led, &res) {
+            log(Level::Debug, LogMessage::Followed { key: k.clone(), leader: leader.clone() });
+        }
         
-        res.map_err(|err| err.unwrap_or(ProcessError::InternalServer("Single Flight Leader failed!")))
+        let flight = if led { Flight::Leader } else { Flight::Follower };
+        res.map(|(body, tier, _)| Served { body, source: Source { tier, flight } })
+            .map_err(|err| err.unwrap_or(ProcessError::InternalServer("Single Flight Leader failed!")))
+    }
+
+    /// Checks the memory cache, and bodies still streaming in from upstream.
+    fn memory_get<K: CacheKey>(&self, key: &EncodedKey) -> Option<Body> {
+        if !K::TIERS.memory { return None }
+        match self.layers.cache.get(key).0 {
+            Some(data) => Some(Body::Full(data)),
+            None => self.layers.filling(key).map(Body::Streaming),
+        }
+    }
+
+    /// Reads the key from storage, fetching it and filling storage on a miss, if `fresh`, or if the stored value is past its ttl.
+    /// Values past their ttl are still answered while the circuit breaker is open, see `breaker.stale_seconds`.
+    async fn storage_get_or_fetch<K: CacheKey>(&self, key: &K, k: &EncodedKey, keys: &ApiKeys, fresh: bool, deadline: Instant) -> Result<(Body, Tier), ProcessError> {
+        let mut stale = None;
+        if K::TIERS.storage && !fresh {
+            let now = Instant::now();
+            let stored = self.layers.storage.read(k.clone()).await?;
+            log(Level::Debug, LogMessage::TimeElapsed { elapsed: now.elapsed(), name: "DB Read" });
+
+            if let Some(stored) = stored {
+                let decompressed = decompress(&stored).map_err(|e| ProcessError::Database(e.to_string()))?;
+                // a value purged between the read and its info is still fresh enough to answer with.
+                let expired = self.layers.storage.info(k).is_some_and(|info| info.written_at + key.storage_ttl().as_secs() <= unix_secs());
+                if !expired {
+                    log(Level::Debug, LogMessage::MessageAndUser { key: k.clone(), message: "DB Hit" });
+                    return Ok((Body::Full(decompressed.into()), Tier::Database))
+                }
+                stale = Some(decompressed);
+            }
+        }
+
+        let fill = Fill { key: k.clone(), tiers: K::TIERS, validator: K::VALIDATOR, memory_ttl: key.memory_ttl(), storage_ttl: key.storage_ttl() };
+        let fetched = match (key.fetch(keys, deadline).await, stale) {
+            // only the breaker fails with `Unavailable`, upstream isn't asked at all while it's open.
+            (Err(ProcessError::Unavailable(_)), Some(stale)) => {
+                log(Level::Info, LogMessage::MessageAndUser { key: k.clone(), message: "Circuit open, answering with a stale DB value" });
+                return Ok((Body::Full(stale.into()), Tier::Database))
+            }
+            (fetched, _) => fetched?,
+        };
+        let res = match fetched {
+            Fetched::Body(data) => {
+                self.layers.store(&fill, &data).await?;
+                return Ok((Body::Full(data), Tier::Upstream))
+            }
+            Fetched::Response(res) => res,
+        };
+
+        if res.content_length().is_some_and(|len| len < config().storage.stream_threshold_bytes) {
+            let data = res.bytes().await?;
+            fill.validator.check(&data)?;
+            self.layers.store(&fill, &data).await?;
+            return Ok((Body::Full(data), Tier::Upstream))
+        }
+
+        let body = SharedBody::new();
+        self.layers.filling.lock().expect("Filling should never be poisoned").insert(k.clone(), body.clone());
+        spawn(request_id::propagate(Layers::fill(self.layers.clone(), fill, res, body.clone())));
+        Ok((Body::Streaming(body), Tier::Upstream))
+    }
+
+    /// Asks the owning peer for the key, returning `Ok(None)` if we own it or it should be fetched locally instead.
+    async fn peer_fetch(&self, key: &EncodedKey, ask_peers: bool) -> Result<Option<Bytes>, ProcessError> {
+        let (Some(peers), EncodedKey::Uuid(uuid_key), true) = (&self.peers, key, ask_peers) else { return Ok(None) };
+        let Some(owner) = peers.owner(uuid_key) else { return Ok(None) };
+
+        match peers.fetch(owner, uuid_key).await {
+            Ok(data) => Ok(Some(data)),
+            Err(PeerError::Failed(reason)) => {
+                log(Level::Warn, LogMessage::PeerFailed { key: key.clone(), owner: owner.to_string(), reason });
+                Ok(None)
+            }
+            Err(PeerError::Upstream(err)) => Err(err),
+        }
+    }
+}
+
+/// How a streamed body is cached once it's complete, copied out of its `CacheKey` so the fill can outlive the request.
+struct Fill {
+    key: EncodedKey,
+    tiers: Tiers,
+    validator: Validator,
+    memory_ttl: Duration,
+    storage_ttl: Duration,
+}
+
+impl<S: Storage> Layers<S> {
+    fn filling(&self, key: &EncodedKey) -> Option<SharedBody> {
+        self.filling.lock().expect("Filling should never be poisoned").get(key).cloned()
+    }
+
+    /// Writes a fetched value to storage, kept for `breaker.stale_seconds` past its ttl.
+    /// The memory cache is left to the caller, which must fill it before leaving the single flight group.
+    async fn store(&self, fill: &Fill, data: &Bytes) -> Result<(), ProcessError> {
+        if !fill.tiers.storage { return Ok(()) }
+        let compressed = compress(data);
+        let kept_for = fill.storage_ttl + Duration::from_secs(config().breaker.stale_seconds);
+
+        let now = Instant::now();
+        self.storage.insert(fill.key.clone(), compressed.into(), kept_for).await?;
+        log(Level::Debug, LogMessage::TimeElapsed { elapsed: now.elapsed(), name: "DB write" });
+        Ok(())
+    }
+
+    /// Reads `res` into `body` chunk by chunk, caching it once it's complete and valid.
+    /// Nothing is cached if the body fails part way.
+    async fn fill(self: Arc<Self>, fill: Fill, mut res: Response, body: SharedBody) {
+        let read = async {
+            while let Some(chunk) = res.chunk().await? {
+                body.push(chunk);
+            }
+            let data = body.collect();
+            fill.validator.check(&data)?;
+            Ok::<_, ProcessError>(data)
+        };
+
+        let result = match read.await {
+            Ok(data) => {
+                if fill.tiers.memory {
+                    self.cache.put(&fill.key, data.clone(), Some(fill.memory_ttl));
+                }
+                // the body is already complete for anyone streaming it, a failed write only costs us the cached copy.
+                if self.store(&fill, &data).await.is_err() {
+                    log(Level::Warn, LogMessage::MessageAndUser { key: fill.key.clone(), message: "Failed to store streamed body" });
+                }
+                Ok(())
+            }
+            Err(err) => {
+                log(Level::Warn, LogMessage::MessageAndUser { key: fill.key.clone(), message: "Upstream body failed mid stream" });
+                Err(err)
+            }
+        };
+
+        // memory is filled before the body leaves `filling`, so there's no gap where a request would fetch again.
+        self.filling.lock().expect("Filling should never be poisoned").remove(&fill.key);
+        body.finish(result);
     }
 }
 
@@ -0,0 +1,210 @@
+use std::sync::{OnceLock, atomic::{AtomicU64, Ordering}};
+
+use portable_atomic::AtomicU128;
+use simd_json::{OwnedValue, json};
+
+use crate::cache::unix_secs;
+
+/// The layer of the cache a request was answered by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tier {
+    Memory,
+    Database,
+    Peer,
+    Upstream,
+}
+
+impl Tier {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+            Self::Database => "db",
+            Self::Peer => "peer",
+            Self::Upstream => "upstream",
+        }
+    }
+}
+
+/// Per `CacheKey::KEYFLAG` accounting of which layer answered each request.
+pub struct CacheStats {
+    keys: [OnceLock<Box<KeyStats>>; 256],
+}
+
+impl CacheStats {
+    pub fn new() -> Self {
+        Self {
+            keys: [const { OnceLock::new() }; 256],
+        }
+    }
+
+    /// Gets the stats of a key type, registering it under `name` the first time the flag is seen.
+    pub fn key(&self, flag: u8, name: &'static str) -> &KeyStats {
+        self.keys[usize::from(flag)].get_or_init(|| Box::new(KeyStats::new(name)))
+    }
+
+    /// Every key type seen so far.
+    pub fn iter(&self) -> impl Iterator<Item = &KeyStats> {
+        self.keys.iter().filter_map(|stats| stats.get().map(AsRef::as_ref))
+    }
+
+    pub fn to_json(&self) -> OwnedValue {
+        let mut map = simd_json::owned::Object::new();
+        for (flag, stats) in self.keys.iter().enumerate() {
+            let Some(stats) = stats.get() else { continue };
+            map.insert(stats.name.into(), stats.to_json(flag));
+        }
+        OwnedValue::from(map)
+    }
+}
+
+pub struct KeyStats {
+    name: &'static str,
+    pub memory_hits: WindowCounter,
+    pub db_hits: WindowCounter,
+    pub peer_hits: WindowCounter,
+    pub upstream_fetches: WindowCounter,
+    pub leaders: WindowCounter,
+    pub followers: WindowCounter,
+    pub errors: WindowCounter,
+}
+
+impl KeyStats {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            memory_hits: WindowCounter::new(),
+            db_hits: WindowCounter::new(),
+            peer_hits: WindowCounter::new(),
+            upstream_fetches: WindowCounter::new(),
+            leaders: WindowCounter::new(),
+            followers: WindowCounter::new(),
+            errors: WindowCounter::new(),
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn hit(&self, tier: Tier) {
+        match tier {
+            Tier::Memory => self.memory_hits.increment(),
+            Tier::Database => self.db_hits.increment(),
+            Tier::Peer => self.peer_hits.increment(),
+            Tier::Upstream => self.upstream_fetches.increment(),
+        }
+    }
+
+    fn to_json(&self, flag: usize) -> OwnedValue {
+        json!({
+            "flag": flag,
+            "memory_hits": self.memory_hits.to_json(),
+            "db_hits": self.db_hits.to_json(),
+            "peer_hits": self.peer_hits.to_json(),
+            "upstream_fetches": self.upstream_fetches.to_json(),
+            "single_flight_leaders": self.leaders.to_json(),
+            "single_flight_followers": self.followers.to_json(),
+            "errors": self.errors.to_json(),
+        })
+    }
+}
+
+const WINDOW_SLOTS: usize = 60;
+
+/// Counts events, bucketing them by second and by minute so the last minute and hour can be read back.
+pub struct WindowCounter {
+    total: AtomicU64,
+    seconds: [Slot; WINDOW_SLOTS],
+    minutes: [Slot; WINDOW_SLOTS],
+}
+
+impl WindowCounter {
+    pub fn new() -> Self {
+        Self {
+            total: AtomicU64::new(0),
+            seconds: [const { Slot::new() }; WINDOW_SLOTS],
+            minutes: [const { Slot::new() }; WINDOW_SLOTS],
+        }
+    }
+
+    pub fn increment(&self) {
+        let now = unix_secs();
+        let minute = now / 60;
+        self.total.fetch_add(1, Ordering::Relaxed);
+        self.seconds[slot_index(now)].increment(now);
+        self.minutes[slot_index(minute)].increment(minute);
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Number of events in the last 60 seconds.
+    pub fn last_minute(&self) -> u64 {
+        let now = unix_secs();
+        sum_since(&self.seconds, now, now.saturating_sub(WINDOW_SLOTS as u64 - 1))
+    }
+
+    /// Number of events in the last 60 minutes.
+    pub fn last_hour(&self) -> u64 {
+        let minute = unix_secs() / 60;
+        sum_since(&self.minutes, minute, minute.saturating_sub(WINDOW_SLOTS as u64 - 1))
+    }
+
+    pub fn to_json(&self) -> OwnedValue {
+        json!({
+            "total": self.total(),
+            "last_minute": self.last_minute(),
+            "last_hour": self.last_hour(),
+        })
+    }
+}
+
+fn sum_since(slots: &[Slot], newest: u64, oldest: u64) -> u64 {
+    slots.iter().map(|slot| slot.count(newest, oldest)).sum()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn slot_index(window: u64) -> usize {
+    (window % WINDOW_SLOTS as u64) as usize
+}
+
+/// packs a window and the number of events counted during it into a single atomic u128.
+/// the bits are laid out as
+/// ```text
+/// | u64 window | u64 count |
+/// | 128..64    | 63..0     |
+/// ```
+struct Slot {
+    value: AtomicU128,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self { value: AtomicU128::new(0) }
+    }
+
+    fn increment(&self, window: u64) {
+        let _ = self.value.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+            let (current, count) = unpack(value);
+            // the slot is reused once its window has passed, so stale counts are replaced rather than added to.
+            Some(if current == window { pack(window, count + 1) } else { pack(window, 1) })
+        });
+    }
+
+    fn count(&self, newest: u64, oldest: u64) -> u64 {
+        let (window, count) = unpack(self.value.load(Ordering::Relaxed));
+        if (oldest..=newest).contains(&window) { count } else { 0 }
+    }
+}
+
+#[inline]
+fn pack(window: u64, count: u64) -> u128 {
+    u128::from(window) << 64 | u128::from(count)
+}
+
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+fn unpack(value: u128) -> (u64, u64) {
+    ((value >> 64) as u64, value as u64)
+}
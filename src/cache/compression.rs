@@ -4,6 +4,12 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
     compress_prepend_size(data)
 }
 
+/// Reads the uncompressed length prepended by `compress()` without decompressing.
+pub fn decompressed_len(data: &[u8]) -> Option<usize> {
+    let prefix = data.get(..size_of::<u32>())?;
+    Some(u32::from_le_bytes(prefix.try_into().ok()?) as usize)
+}
+
 /// expected input should be gathered from `compress()`
 pub fn decompress(data: &[u8]) -> Result<Vec<u8>, DecompressError> {
     decompress_size_prepended(data)
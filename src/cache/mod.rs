@@ -1,11 +1,95 @@
-use std::{cmp::Ordering, fmt::Display, hash::{self, Hash}};
+use std::{cmp::Ordering, fmt::Display, hash::{self, Hash}, time::{Duration, SystemTime, UNIX_EPOCH}};
 
 use ltmdb::SizedBytes;
 use uuid::Uuid;
 
+pub mod body;
 pub mod compression;
 pub mod cache_router;
 pub mod cache_key;
+pub mod cache_stats;
+pub mod storage;
+
+#[inline]
+pub fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+/// A key namespaced by the flag of its `CacheKey` implementation.
+///
+/// Uuid ids keep their packed `u128` representation so the common lookups stay cheap,
+/// while every other id is stored as flag-prefixed bytes. Once encoded, each kind of key starts with its own tag,
+/// so keys of different kinds can never share the same bytes.
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub enum EncodedKey {
+    Uuid(UuidKey),
+    Bytes(BytesKey),
+}
+
+impl EncodedKey {
+    pub fn flag(&self) -> u8 {
+        match self {
+            Self::Uuid(key) => key.flag(),
+            Self::Bytes(key) => key.flag(),
+        }
+    }
+}
+
+impl From<UuidKey> for EncodedKey {
+    fn from(value: UuidKey) -> Self {
+        Self::Uuid(value)
+    }
+}
+
+impl From<BytesKey> for EncodedKey {
+    fn from(value: BytesKey) -> Self {
+        Self::Bytes(value)
+    }
+}
+
+impl From<EncodedKey> for SizedBytes {
+    fn from(value: EncodedKey) -> Self {
+        match value {
+            EncodedKey::Uuid(key) => key.into(),
+            EncodedKey::Bytes(key) => key.into(),
+        }
+    }
+}
+
+impl Display for EncodedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Uuid(key) => key.fmt(f),
+            Self::Bytes(key) => key.fmt(f),
+        }
+    }
+}
+
+/// An id that can be namespaced into an `EncodedKey`.
+pub trait KeyId {
+    fn encode(&self, flag: u8) -> EncodedKey;
+}
+
+impl KeyId for Uuid {
+    fn encode(&self, flag: u8) -> EncodedKey {
+        UuidKey::encode(*self, flag).into()
+    }
+}
+
+impl KeyId for str {
+    fn encode(&self, flag: u8) -> EncodedKey {
+        BytesKey::encode(self.as_bytes(), flag).into()
+    }
+}
+
+impl KeyId for String {
+    fn encode(&self, flag: u8) -> EncodedKey {
+        self.as_str().encode(flag)
+    }
+}
 
 #[derive(Eq, Clone, Copy)]
 pub struct UuidKey {
@@ -38,7 +122,9 @@ impl Ord for UuidKey {
 
 impl From<UuidKey> for SizedBytes {
     fn from(value: UuidKey) -> Self {
-        SizedBytes::from(value.as_u128().to_be_bytes())
+        let mut key = [UUID_TAG; 17];
+        key[1..].copy_from_slice(&value.as_u128().to_be_bytes());
+        SizedBytes::from(key)
     }
 }
 
@@ -86,4 +172,100 @@ impl Display for UuidKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "UuidKey(flag: {}, uuid: {})", self.flag(), self.uuid())
     }
-}
\ No newline at end of file
+}
+
+/// Leading byte of every encoded `UuidKey`, followed by its 16 bytes.
+/// Uuid keys were stored as the bare 16 bytes before 1.5.0, so entries written by older versions are never read.
+const UUID_TAG: u8 = 0x00;
+/// Leading byte of every encoded `BytesKey`, which can't match a `UuidKey` since their tags differ.
+const BYTES_TAG: u8 = 0xFF;
+
+/// A flag-prefixed key for ids which aren't uuids.
+/// the bytes are laid out as
+/// ```text
+/// | tag | flag | id   |
+/// | 0   | 1    | 2..  |
+/// ```
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct BytesKey {
+    key: SizedBytes
+}
+
+impl BytesKey {
+    pub fn encode(id: &[u8], flag: u8) -> Self {
+        let mut key = Vec::with_capacity(id.len() + 2);
+        key.extend_from_slice(&[BYTES_TAG, flag]);
+        key.extend_from_slice(id);
+        Self { key: SizedBytes::from(key.as_slice()) }
+    }
+
+    pub fn flag(&self) -> u8 {
+        self.key[1]
+    }
+
+    pub fn id(&self) -> &[u8] {
+        &self.key[2..]
+    }
+}
+
+impl From<BytesKey> for SizedBytes {
+    fn from(value: BytesKey) -> Self {
+        value.key
+    }
+}
+
+impl Display for BytesKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BytesKey(flag: {}, id: {})", self.flag(), String::from_utf8_lossy(self.id()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ltmdb::SizedBytes;
+    use uuid::Uuid;
+
+    use super::{BytesKey, EncodedKey, KeyId, UuidKey};
+
+    const UUID: Uuid = Uuid::from_u128(0x0c3a_8f51_2b7e_4d0f_9a61_5e2f_8c7d_4b19);
+
+    #[test]
+    fn uuid_keys_round_trip() {
+        for flag in 0..8 {
+            let key = UuidKey::encode(UUID, flag);
+            assert_eq!(key.flag(), flag);
+            assert_eq!(key.uuid(), UUID);
+        }
+    }
+
+    #[test]
+    fn bytes_keys_round_trip() {
+        for id in ["", "ENCHANTED_DIAMOND", "guild/5f3c2a1b"] {
+            let key = BytesKey::encode(id.as_bytes(), 3);
+            assert_eq!(key.flag(), 3);
+            assert_eq!(key.id(), id.as_bytes());
+        }
+    }
+
+    #[test]
+    fn flags_namespace_ids() {
+        assert_ne!(SizedBytes::from(UuidKey::encode(UUID, 0)), SizedBytes::from(UuidKey::encode(UUID, 1)));
+        assert_ne!(SizedBytes::from(BytesKey::encode(b"id", 0)), SizedBytes::from(BytesKey::encode(b"id", 1)));
+    }
+
+    #[test]
+    fn key_types_never_collide() {
+        for flag in 0..8 {
+            let uuid = SizedBytes::from(UUID.encode(flag));
+            // every length a uuid key could be mistaken for, including its raw bytes.
+            let raw = UuidKey::encode(UUID, flag).as_u128().to_be_bytes();
+            for len in 0..=raw.len() {
+                for id in [&raw[..len], &raw[raw.len() - len..]] {
+                    assert_ne!(SizedBytes::from(BytesKey::encode(id, flag)), uuid);
+                }
+            }
+            let as_bytes = SizedBytes::from(EncodedKey::from(BytesKey::encode(&raw, flag)));
+            assert_ne!(as_bytes, uuid);
+        }
+    }
+}
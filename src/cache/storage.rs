@@ -0,0 +1,320 @@
+use std::{collections::HashMap, str::FromStr, sync::{RwLock, atomic::{AtomicU64, Ordering}}, time::Duration};
+
+use actix_web::web::Bytes;
+use ltmdb::CloseSummary;
+use rapidhash_lite::RandomHash;
+
+use crate::{cache::{EncodedKey, cache_router::TokioRT, unix_secs}, error::ProcessError, metrics::StorageObserver};
+
+pub type Database = ltmdb::Database<TokioRT, RandomHash>;
+
+/// Where and when a value was stored, as reported by `Storage::info`.
+pub struct StoredInfo {
+    /// backend specific location of the value, if it has one.
+    pub location: Option<String>,
+    /// unix time in seconds the value was written. Backends may only know it roughly, ltmdb reports when
+    /// the value's partition was created, which can be up to a minute before the write.
+    pub written_at: u64,
+    /// unix time in seconds the value expires.
+    pub expires_at: u64,
+    /// length in bytes of the stored value.
+    pub size: usize,
+}
+
+/// The persistent tier of the `CacheRouter`, sitting between the memory cache and upstream.
+pub trait Storage: Send + Sync + 'static {
+    /// Returns `Ok(None)` if the key isn't stored.
+    fn read(&self, key: EncodedKey) -> impl Future<Output = Result<Option<Bytes>, ProcessError>> + Send;
+
+    /// Stores a value, replacing any existing value for the key.
+    fn insert(&self, key: EncodedKey, value: Bytes, ttl: Duration) -> impl Future<Output = Result<(), ProcessError>> + Send;
+
+    /// Removes a value, returning `true` if it was stored.
+    fn delete(&self, key: EncodedKey) -> impl Future<Output = Result<bool, ProcessError>> + Send;
+
+    /// Describes a stored value without reading it.
+    fn info(&self, key: &EncodedKey) -> Option<StoredInfo>;
+
+    /// Time left until a stored value expires.
+    fn ttl(&self, key: &EncodedKey) -> Option<Duration> {
+        self.info(key).map(|info| Duration::from_secs(info.expires_at.saturating_sub(unix_secs())))
+    }
+}
+
+impl Storage for Database {
+    async fn read(&self, key: EncodedKey) -> Result<Option<Bytes>, ProcessError> {
+        Ok(Database::read(self, key).await?)
+    }
+
+    async fn insert(&self, key: EncodedKey, value: Bytes, ttl: Duration) -> Result<(), ProcessError> {
+        Ok(Database::insert(self, key, value, ttl).await?)
+    }
+
+    async fn delete(&self, key: EncodedKey) -> Result<bool, ProcessError> {
+        Ok(self.remove(key).await?)
+    }
+
+    fn info(&self, key: &EncodedKey) -> Option<StoredInfo> {
+        let info = self.inspect(key.clone())?;
+        Some(StoredInfo {
+            location: Some(format!("bucket {}s, partition {}", info.bucket.as_secs(), info.partition)),
+            written_at: info.partition, // partitions take writes for a minute after they're created.
+            expires_at: info.expires_at,
+            size: info.len,
+        })
+    }
+}
+
+/// Seconds between purges of `MemoryStorage`'s expired entries.
+const PURGE_INTERVAL_SECS: u64 = 60;
+
+/// Stores values in a map that is lost on restart. Useful for tests and instances without a disk.
+#[derive(Default)]
+pub struct MemoryStorage {
+    entries: RwLock<HashMap<EncodedKey, MemoryEntry, RandomHash>>,
+    /// unix time in seconds after which the next insert purges expired entries.
+    next_purge: AtomicU64,
+}
+
+struct MemoryEntry {
+    value: Bytes,
+    written_at: u64,
+    expires_at: u64,
+}
+
+impl MemoryStorage {
+    /// Returns `true` for the one insert that should purge expired entries, at most once every `PURGE_INTERVAL_SECS`.
+    /// Expired entries are already hidden from reads, purging only keeps the map from growing with them.
+    fn claim_purge(&self, now: u64) -> bool {
+        let next = self.next_purge.load(Ordering::Relaxed);
+        now >= next && self.next_purge.compare_exchange(next, now + PURGE_INTERVAL_SECS, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+    }
+}
+
+impl Storage for MemoryStorage {
+    async fn read(&self, key: EncodedKey) -> Result<Option<Bytes>, ProcessError> {
+        let entries = self.entries.read().map_err(|_| ProcessError::internal("Memory storage was poisoned."))?;
+        Ok(entries.get(&key).filter(|entry| entry.expires_at > unix_secs()).map(|entry| entry.value.clone()))
+    }
+
+    async fn insert(&self, key: EncodedKey, value: Bytes, ttl: Duration) -> Result<(), ProcessError> {
+        let now = unix_secs();
+        let purge = self.claim_purge(now);
+        let mut entries = self.entries.write().map_err(|_| ProcessError::internal("Memory storage was poisoned."))?;
+        if purge {
+            entries.retain(|_, entry| entry.expires_at > now);
+        }
+        entries.insert(key, MemoryEntry { value, written_at: now, expires_at: now + ttl.as_secs() });
+        Ok(())
+    }
+
+    async fn delete(&self, key: EncodedKey) -> Result<bool, ProcessError> {
+        let mut entries = self.entries.write().map_err(|_| ProcessError::internal("Memory storage was poisoned."))?;
+        Ok(entries.remove(&key).is_some())
+    }
+
+    fn info(&self, key: &EncodedKey) -> Option<StoredInfo> {
+        let entries = self.entries.read().ok()?;
+        let entry = entries.get(key).filter(|entry| entry.expires_at > unix_secs())?;
+        Some(StoredInfo { location: None, written_at: entry.written_at, expires_at: entry.expires_at, size: entry.value.len() })
+    }
+}
+
+/// Stores nothing, every read is a miss.
+pub struct NoStorage;
+
+impl Storage for NoStorage {
+    async fn read(&self, _: EncodedKey) -> Result<Option<Bytes>, ProcessError> {
+        Ok(None)
+    }
+
+    async fn insert(&self, _: EncodedKey, _: Bytes, _: Duration) -> Result<(), ProcessError> {
+        Ok(())
+    }
+
+    async fn delete(&self, _: EncodedKey) -> Result<bool, ProcessError> {
+        Ok(false)
+    }
+
+    fn info(&self, _: &EncodedKey) -> Option<StoredInfo> {
+        None
+    }
+}
+
+/// Backend of the storage tier, see `storage.kind`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StorageKind {
+    Ltmdb,
+    Memory,
+    None,
+}
+
+impl FromStr for StorageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ltmdb" => Ok(Self::Ltmdb),
+            "memory" => Ok(Self::Memory),
+            "none" => Ok(Self::None),
+            other => Err(format!("unknown storage {other}, expected ltmdb, memory or none")),
+        }
+    }
+}
+
+/// The storage selected by `storage.kind`: `ltmdb` (default), `memory` or `none`.
+pub enum AnyStorage {
+    Ltmdb(Database),
+    Memory(MemoryStorage),
+    None(NoStorage),
+}
+
+impl AnyStorage {
+    /// # Errors
+    /// Returns an error if the database fails to load.
+    pub async fn load(kind: StorageKind, db_path: String) -> Result<Self, ProcessError> {
+        match kind {
+            StorageKind::Ltmdb => {
+                let db = Database::load(db_path).await?;
+                db.observe(StorageObserver);
+                Ok(Self::Ltmdb(db))
+            }
+            StorageKind::Memory => Ok(Self::Memory(MemoryStorage::default())),
+            StorageKind::None => Ok(Self::None(NoStorage)),
+        }
+    }
+
+    /// Closes ltmdb for shutdown, giving pending writes `timeout` to finish. `Ok(None)` for storage without a disk.
+    ///
+    /// # Errors
+    /// Returns an error if the database couldn't be synced to disk.
+    pub async fn close(&self, timeout: Duration) -> Result<Option<CloseSummary>, ProcessError> {
+        match self {
+            Self::Ltmdb(db) => Ok(Some(db.close(timeout).await?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Bytes ltmdb holds on disk, `None` for storage without a disk.
+    pub fn disk_bytes(&self) -> Option<u64> {
+        match self {
+            Self::Ltmdb(db) => Some(db.disk_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Whether ltmdb's expiration task is still running, `None` for storage without a disk.
+    pub fn expiration_task_alive(&self) -> Option<bool> {
+        match self {
+            Self::Ltmdb(db) => Some(db.expiration_task_alive()),
+            _ => None,
+        }
+    }
+
+    /// Checks ltmdb can still write to its directory, `Ok(false)` for storage without a disk.
+    ///
+    /// # Errors
+    /// Returns an error if the directory can't be written to.
+    pub async fn check_writable(&self) -> Result<bool, ProcessError> {
+        match self {
+            Self::Ltmdb(db) => Ok(db.check_writable().await.map(|()| true)?),
+            _ => Ok(false),
+        }
+    }
+
+    /// Partitions in ltmdb's expiration queue, `None` for storage without a disk.
+    pub fn expiration_queue_len(&self) -> Option<usize> {
+        match self {
+            Self::Ltmdb(db) => Some(db.expiration_queue_len()),
+            _ => None,
+        }
+    }
+}
+
+impl Storage for AnyStorage {
+    async fn read(&self, key: EncodedKey) -> Result<Option<Bytes>, ProcessError> {
+        match self {
+            Self::Ltmdb(storage) => Storage::read(storage, key).await,
+            Self::Memory(storage) => storage.read(key).await,
+            Self::None(storage) => storage.read(key).await,
+        }
+    }
+
+    async fn insert(&self, key: EncodedKey, value: Bytes, ttl: Duration) -> Result<(), ProcessError> {
+        match self {
+            Self::Ltmdb(storage) => Storage::insert(storage, key, value, ttl).await,
+            Self::Memory(storage) => storage.insert(key, value, ttl).await,
+            Self::None(storage) => storage.insert(key, value, ttl).await,
+        }
+    }
+
+    async fn delete(&self, key: EncodedKey) -> Result<bool, ProcessError> {
+        match self {
+            Self::Ltmdb(storage) => storage.delete(key).await,
+            Self::Memory(storage) => storage.delete(key).await,
+            Self::None(storage) => storage.delete(key).await,
+        }
+    }
+
+    fn info(&self, key: &EncodedKey) -> Option<StoredInfo> {
+        match self {
+            Self::Ltmdb(storage) => storage.info(key),
+            Self::Memory(storage) => storage.info(key),
+            Self::None(storage) => storage.info(key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    use actix_web::web::Bytes;
+    use uuid::Uuid;
+
+    use super::{MemoryStorage, NoStorage, Storage};
+    use crate::cache::{EncodedKey, KeyId};
+
+    fn key(n: u128) -> EncodedKey {
+        Uuid::from_u128(n).encode(0)
+    }
+
+    #[tokio::test]
+    async fn memory_storage_stores_until_expiry() {
+        let storage = MemoryStorage::default();
+        storage.insert(key(1), Bytes::from_static(b"kept"), Duration::from_secs(60)).await.unwrap();
+        storage.insert(key(2), Bytes::from_static(b"expired"), Duration::ZERO).await.unwrap();
+
+        assert_eq!(storage.read(key(1)).await.unwrap().as_deref(), Some(&b"kept"[..]));
+        assert!(storage.ttl(&key(1)).is_some_and(|ttl| ttl > Duration::from_secs(58)));
+        assert_eq!(storage.info(&key(1)).map(|info| info.size), Some(4));
+        assert_eq!(storage.read(key(2)).await.unwrap(), None);
+        assert!(storage.info(&key(2)).is_none());
+
+        assert!(storage.delete(key(1)).await.unwrap());
+        assert!(!storage.delete(key(1)).await.unwrap());
+        assert_eq!(storage.read(key(1)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn memory_storage_purges_in_batches() {
+        let storage = MemoryStorage::default();
+        let len = || storage.entries.read().unwrap().len();
+        storage.insert(key(1), Bytes::new(), Duration::ZERO).await.unwrap();
+        storage.insert(key(2), Bytes::new(), Duration::ZERO).await.unwrap();
+        // the first insert purged the empty map, so expired entries wait for the next purge.
+        assert_eq!(len(), 2);
+
+        storage.next_purge.store(0, Ordering::Relaxed);
+        storage.insert(key(3), Bytes::new(), Duration::from_secs(60)).await.unwrap();
+        assert_eq!(len(), 1);
+    }
+
+    #[tokio::test]
+    async fn no_storage_stores_nothing() {
+        NoStorage.insert(key(1), Bytes::from_static(b"value"), Duration::from_secs(60)).await.unwrap();
+        assert_eq!(NoStorage.read(key(1)).await.unwrap(), None);
+        assert!(NoStorage.info(&key(1)).is_none());
+        assert!(!NoStorage.delete(key(1)).await.unwrap());
+    }
+}
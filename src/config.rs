@@ -0,0 +1,518 @@
+//! Settings read from a toml config file, overridden by command line flags, in turn overridden by environment variables.
+
+use std::{collections::HashMap, env, fmt::Write, fs, net::IpAddr, ops::Deref, path::{Path, PathBuf}, str::FromStr, sync::{Mutex, OnceLock}};
+
+use cusp::{Cell, LocalGuard, PinnedCell};
+use ipnet::IpNet;
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, de::IntoDeserializer};
+use toml::{Spanned, Value, de::{DeTable, DeValue}};
+
+use crate::{cache::storage::StorageKind, key_extractor::parse_net, logging::{Filter, Format}};
+
+/// Config file read when neither `--config` nor `CONFIG_FILE` are given, skipped if it doesn't exist.
+const DEFAULT_FILE: &str = "config.toml";
+
+/// The config in use, swapped out whole when it's reloaded.
+static CONFIG: OnceLock<Cell<Config>> = OnceLock::new();
+/// Flags the config was loaded with, so reloads read the same file and keep the same overrides.
+static ARGS: OnceLock<Vec<String>> = OnceLock::new();
+/// Held while reloading, so concurrent reloads can't undo each other.
+static RELOADING: Mutex<()> = Mutex::new(());
+
+/// A setting as listed by `--help`.
+struct Setting {
+    /// `section.key`, as written in the config file and as a flag.
+    path: &'static str,
+    /// environment variables overriding it, the first one set wins.
+    env: &'static [&'static str],
+    default: &'static str,
+    doc: &'static str,
+    /// whether a change only takes effect after a restart, because what it configures is built once at startup.
+    restart: bool,
+}
+
+/// Declares every setting, its type, default, environment variables and whether it can be reloaded `live` or needs a `restart`,
+/// along with the `Config` holding them. Defaults are written as they would be in the config file and parsed like any other value.
+macro_rules! settings {
+    (@restart live) => { false };
+    (@restart restart) => { true };
+    ($($section:ident: $Section:ident {
+        $(#[doc = $doc:literal] $field:ident: $ty:ty = $default:literal, $($env:literal)|+, $reload:ident;)+
+    })+) => {
+        $(
+            #[derive(Clone, Debug, PartialEq)]
+            pub struct $Section {
+                $(#[doc = $doc] pub $field: $ty,)+
+            }
+        )+
+
+        #[derive(Clone, Debug)]
+        pub struct Config {
+            $(pub $section: $Section,)+
+        }
+
+        const SETTINGS: &[Setting] = &[
+            $($(Setting {
+                path: concat!(stringify!($section), ".", stringify!($field)),
+                env: &[$($env),+],
+                default: $default,
+                doc: $doc,
+                restart: settings!(@restart $reload),
+            },)+)+
+        ];
+
+        impl Config {
+            fn resolve(sources: &Sources, errors: &mut Vec<String>) -> Self {
+                Self {
+                    $($section: $Section {
+                        $($field: sources.get(concat!(stringify!($section), ".", stringify!($field)), &[$($env),+], $default, errors),)+
+                    },)+
+                }
+            }
+
+            /// Settings that differ from `current`. Those needing a restart are put back to their `current` value,
+            /// so the config always describes what's actually in use.
+            fn changes(&mut self, current: &Self) -> Reloaded {
+                let mut reloaded = Reloaded::default();
+                $($(if self.$section.$field != current.$section.$field {
+                    let path = concat!(stringify!($section), ".", stringify!($field));
+                    if settings!(@restart $reload) {
+                        self.$section.$field = current.$section.$field.clone();
+                        reloaded.restart_required.push(path);
+                    } else {
+                        reloaded.applied.push(path);
+                    }
+                })+)+
+                reloaded
+            }
+        }
+    };
+}
+
+settings! {
+    server: Server {
+        /// Address to listen on.
+        bind: IpAddr = "127.0.0.1", "IP_ADDR", restart;
+        /// Port to listen on.
+        port: u16 = "8000", "PORT", restart;
+        /// Seconds in flight requests get to finish after a `SIGTERM`, and again for pending storage writes.
+        shutdown_timeout_seconds: u64 = "30", "SHUTDOWN_TIMEOUT_SECONDS", restart;
+    }
+    storage: Storage {
+        /// Backend of the storage tier, one of `ltmdb`, `memory` or `none`.
+        kind: StorageKind = "ltmdb", "STORAGE", restart;
+        /// Directory the database is stored in. Instances running side by side each need their own.
+        path: String = ".db", "DB_PATH", restart;
+        /// Upstream bodies at least this large, or of unknown length, are streamed to clients as they arrive.
+        stream_threshold_bytes: u64 = "262144", "STREAM_THRESHOLD_BYTES", live;
+    }
+    cache: Cache {
+        /// Entries kept in the memory cache.
+        size: usize = "256", "CACHE_SIZE", restart;
+    }
+    ttl: Ttl {
+        /// Seconds profiles are kept in storage.
+        profile_db_seconds: u64 = "3600", "PROFILE_DB_TTL_SECONDS", live;
+        /// Seconds profiles are kept in the memory cache.
+        profile_cache_seconds: u64 = "120", "PROFILE_CACHE_TTL_SECONDS", live;
+        /// Seconds secrets are kept in the memory cache, they aren't stored.
+        secrets_seconds: u64 = "120", "SECRETS_TTL_SECONDS", live;
+    }
+    ratelimit: RateLimit {
+        /// Seconds between requests for anonymous clients.
+        refresh_seconds: u64 = "3", "RATELIMIT_REFRESH", live;
+        /// Requests anonymous clients can make at once.
+        burst: u32 = "10", "RATELIMIT_BURST", live;
+        /// Milliseconds between requests for the standard token tier.
+        standard_refresh_millis: u64 = "1000", "RATELIMIT_STANDARD_REFRESH_MILLIS", live;
+        /// Burst size of the standard token tier.
+        standard_burst: u32 = "30", "RATELIMIT_STANDARD_BURST", live;
+        /// Milliseconds between requests for the premium token tier.
+        premium_refresh_millis: u64 = "250", "RATELIMIT_PREMIUM_REFRESH_MILLIS", live;
+        /// Burst size of the premium token tier.
+        premium_burst: u32 = "100", "RATELIMIT_PREMIUM_BURST", live;
+        /// Days a token is valid for when it's issued without its own expiry.
+        token_ttl_days: u64 = "365", "TOKEN_TTL_DAYS", live;
+        /// Ips or cidr ranges of the reverse proxies in front of us, forwarding headers are only read from these.
+        trusted_proxies: Networks = "127.0.0.1/8,::1/128", "TRUSTED_PROXIES", live;
+        /// Prefix length ipv6 clients are grouped by, a single client usually holds a whole /64.
+        ipv6_prefix: u8 = "64", "IPV6_PREFIX", live;
+    }
+    upstream: Upstream {
+        /// Base url every upstream path is requested from. Can be pointed at the bundled `mock_hypixel` server.
+        url: String = "https://api.hypixel.net", "UPSTREAM_URL", live;
+        /// Hypixel api keys requests rotate between.
+        api_keys: List = "", "API_KEYS" | "API_KEY", restart;
+    }
+    breaker: Breaker {
+        /// Consecutive upstream failures after which the circuit opens.
+        failures: u32 = "5", "BREAKER_FAILURES", live;
+        /// Seconds the circuit stays open before probe requests are let through.
+        open_seconds: u64 = "30", "BREAKER_OPEN_SECONDS", live;
+        /// Number of probe requests let through at once while half open.
+        probes: u32 = "1", "BREAKER_PROBES", live;
+        /// Seconds stored values are kept past their ttl, so they can still be answered while the circuit is open.
+        stale_seconds: u64 = "86400", "BREAKER_STALE_SECONDS", live;
+    }
+    peers: Peers {
+        /// Base urls of every instance sharing the cache, including this one. Empty to disable peering.
+        urls: List = "", "PEERS", restart;
+        /// This instance's entry in `peers.urls`.
+        self_url: String = "", "SELF_URL", restart;
+        /// Milliseconds to wait on a peer before falling back to fetching locally.
+        timeout_millis: u64 = "2000", "PEER_TIMEOUT_MILLIS", restart;
+        /// Shared by every peer and sent in the `Peer-Secret` header, `/peer` requests without it are refused.
+        secret: String = "", "PEER_SECRET", restart;
+    }
+    admin: Admin {
+        /// Key required in the `Admin-Key` header, admin endpoints are disabled if empty.
+        key: String = "", "ADMIN_KEY", live;
+    }
+    log: Log {
+        /// Minimum level logged, optionally followed by per module overrides, such as `info,cache=debug`.
+        level: Filter = "info", "LOG_LEVEL", live;
+        /// `text` or `json`, one object per line.
+        format: Format = "text", "LOG_FORMAT", restart;
+        /// File logs are written to instead of stdout. Empty for stdout.
+        file: String = "", "LOG_FILE", restart;
+        /// Size the log file is rotated at.
+        file_max_bytes: u64 = "16777216", "LOG_FILE_MAX_BYTES", restart;
+        /// Number of rotated log files kept.
+        file_keep: u32 = "5", "LOG_FILE_KEEP", restart;
+        /// Messages waiting to be written before new ones are dropped.
+        queue: usize = "4096", "LOG_QUEUE", restart;
+    }
+    access_log: AccessLog {
+        /// Where access lines are written: empty to disable it, `stdout`, or a file.
+        path: String = "", "ACCESS_LOG", restart;
+        /// Size the access log is rotated at.
+        max_bytes: u64 = "67108864", "ACCESS_LOG_MAX_BYTES", restart;
+        /// Number of rotated access logs kept.
+        keep: u32 = "5", "ACCESS_LOG_KEEP", restart;
+        /// Lines waiting to be written before new ones are dropped.
+        queue: usize = "4096", "ACCESS_LOG_QUEUE", restart;
+    }
+}
+
+/// Shorter flags for the settings most often changed by hand.
+const ALIASES: &[(&str, &str)] = &[("bind", "server.bind"), ("port", "server.port"), ("db-path", "storage.path")];
+
+/// The config at the time `config` was called. Reloads don't change a snapshot already taken,
+/// so it should be dropped rather than held across long running work.
+pub struct Snapshot(PinnedCell<'static, Config, LocalGuard<'static>>);
+
+impl Deref for Snapshot {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        self.0.get()
+    }
+}
+
+/// The config in use.
+///
+/// # Panics
+/// panics if called before `load`.
+pub fn config() -> Snapshot {
+    Snapshot(CONFIG.get().expect("config should be loaded before it's read!").pin())
+}
+
+/// Settings changed by a reload.
+#[derive(Default, Debug)]
+pub struct Reloaded {
+    pub applied: Vec<&'static str>,
+    /// changed settings that were left as they were, until the next restart.
+    pub restart_required: Vec<&'static str>,
+}
+
+/// Reads the config file, `args` and the environment, returning the config file used if there was one.
+///
+/// # Errors
+/// Returns every problem found, so they can all be fixed at once.
+pub fn load(args: &[String]) -> Result<Option<PathBuf>, Vec<String>> {
+    let (file, config) = read(args)?;
+    ARGS.set(args.to_vec()).expect("config should only be loaded once!");
+    CONFIG.set(Cell::new(config)).unwrap_or_else(|_| panic!("config should only be loaded once!"));
+    Ok(file)
+}
+
+/// Reads the config again, from the same file and flags it was loaded with, and swaps it in.
+/// Environment variables still override it, they can't change while running.
+///
+/// # Errors
+/// Returns every problem found, the config in use is kept if there are any.
+pub fn reload() -> Result<Reloaded, Vec<String>> {
+    let _reloading = RELOADING.lock().unwrap_or_else(|e| e.into_inner());
+    let (_, mut config) = read(ARGS.get().expect("config should be loaded before it's reloaded!"))?;
+    let cell = CONFIG.get().expect("config should be loaded before it's reloaded!");
+    let guard = cell.guard();
+    let reloaded = config.changes(cell.get(&guard));
+    cell.set(config, &guard);
+    Ok(reloaded)
+}
+
+fn read(args: &[String]) -> Result<(Option<PathBuf>, Config), Vec<String>> {
+    let mut errors = Vec::new();
+    let (file, flags) = parse_args(args, &mut errors);
+
+    let file = file
+        .or_else(|| env::var("CONFIG_FILE").ok().filter(|file| !file.is_empty()).map(PathBuf::from))
+        .or_else(|| Path::new(DEFAULT_FILE).exists().then(|| PathBuf::from(DEFAULT_FILE)));
+    let values = file.as_deref().map_or_else(HashMap::new, |path| read_file(path, &mut errors));
+
+    let sources = Sources { file: file.as_deref().map_or(String::new(), |file| file.display().to_string()), values, flags };
+    let config = Config::resolve(&sources, &mut errors);
+    config.validate(&mut errors);
+
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+    Ok((file, config))
+}
+
+/// Usage text listing every setting, its flag, environment variables and default.
+pub fn usage() -> String {
+    let mut usage = String::from("Usage: hypixel_api [--config <file>] [--<section>.<key> <value>]...\n\n");
+    let _ = writeln!(usage, "Settings are read from the config file (`CONFIG_FILE`, or {DEFAULT_FILE} if it exists), then flags, then environment variables.");
+    let _ = writeln!(usage, "The file is read again on SIGHUP or POST /config/reload.");
+    let _ = writeln!(usage, "Aliases: {}\n", ALIASES.iter().map(|(alias, path)| format!("--{alias} for --{path}")).collect::<Vec<_>>().join(", "));
+    for setting in SETTINGS {
+        let restart = if setting.restart { " Needs a restart to change." } else { "" };
+        let _ = writeln!(usage, "  --{} <{}> [{}]\n      {}{restart}", setting.path, setting.default, setting.env.join(", "), setting.doc.trim());
+    }
+    usage
+}
+
+impl Config {
+    /// Checks what parsing alone can't.
+    fn validate(&self, errors: &mut Vec<String>) {
+        let mut check = |ok: bool, error: &str| if !ok { errors.push(error.to_string()) };
+
+        check(!self.upstream.api_keys.is_empty(), "upstream.api_keys should contain at least one key, set it or API_KEYS");
+        // both are sent as headers, which would panic at startup rather than be reported here. The values are secret, so only positions are named.
+        for (index, key) in self.upstream.api_keys.iter().enumerate() {
+            check(HeaderValue::from_str(key).is_ok(), &format!("upstream.api_keys[{index}] should only contain valid header characters"));
+        }
+        check(self.upstream.url.starts_with("http://") || self.upstream.url.starts_with("https://"), "upstream.url should start with http:// or https://");
+        check(self.ratelimit.ipv6_prefix <= 128, "ratelimit.ipv6_prefix should be at most 128");
+        for (path, burst) in [("burst", self.ratelimit.burst), ("standard_burst", self.ratelimit.standard_burst), ("premium_burst", self.ratelimit.premium_burst)] {
+            check(burst > 0, &format!("ratelimit.{path} should be more than 0"));
+        }
+        for (path, refresh) in [("refresh_seconds", self.ratelimit.refresh_seconds), ("standard_refresh_millis", self.ratelimit.standard_refresh_millis), ("premium_refresh_millis", self.ratelimit.premium_refresh_millis)] {
+            check(refresh > 0, &format!("ratelimit.{path} should be more than 0"));
+        }
+        check(self.breaker.probes > 0, "breaker.probes should be more than 0");
+        check(self.storage.kind != StorageKind::Ltmdb || !self.storage.path.is_empty(), "storage.path should be set when storage.kind is ltmdb");
+        if !self.peers.urls.is_empty() {
+            check(!self.peers.self_url.is_empty(), "peers.self_url should be set when peers.urls is");
+            check(self.peers.self_url.is_empty() || self.peers.index_of_self().is_some(), "peers.self_url should be one of peers.urls");
+            check(!self.peers.secret.is_empty(), "peers.secret should be set when peers.urls is");
+            check(HeaderValue::from_str(&self.peers.secret).is_ok(), "peers.secret should only contain valid header characters");
+        }
+    }
+}
+
+impl Upstream {
+    /// `url` without a trailing `/`, every upstream path starts with one.
+    pub fn base_url(&self) -> &str {
+        self.url.trim_end_matches('/')
+    }
+}
+
+impl Peers {
+    /// Position of `self_url` in `urls`, ignoring trailing slashes.
+    pub fn index_of_self(&self) -> Option<usize> {
+        let self_url = self.self_url.trim_end_matches('/');
+        self.urls.iter().position(|url| url.trim_end_matches('/') == self_url)
+    }
+}
+
+/// How a setting is parsed: from text, as it's given by flags, environment variables and defaults,
+/// or from a value in the config file.
+trait FromValue: Sized {
+    fn from_text(text: &str) -> Result<Self, String>;
+
+    /// Strings are parsed like text and other single values as they're written, only lists take arrays.
+    fn from_toml(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::String(text) => Self::from_text(text),
+            Value::Array(_) | Value::Table(_) => Err(format!("expected a single value, found {}", value.type_str())),
+            value => Self::from_text(&value.to_string()),
+        }
+    }
+}
+
+impl<T: FromStr<Err: ToString>> FromValue for T {
+    fn from_text(text: &str) -> Result<Self, String> {
+        text.trim().parse().map_err(|e: T::Err| e.to_string())
+    }
+}
+
+/// A comma separated list, or an array of strings in the config file. Empty entries are skipped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct List(Vec<String>);
+
+impl FromValue for List {
+    fn from_text(text: &str) -> Result<Self, String> {
+        Ok(Self(text.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()))
+    }
+
+    /// Array items are taken whole, commas and all.
+    fn from_toml(value: &Value) -> Result<Self, String> {
+        let Value::Array(items) = value else { return Self::from_text(&String::from_toml(value)?) };
+        items.iter()
+            .filter_map(|item| match item.as_str().map(str::trim) {
+                Some("") => None,
+                Some(item) => Some(Ok(item.to_string())),
+                None => Some(Err(format!("expected an array of strings, found {}", item.type_str()))),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Deref for List {
+    type Target = [String];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A list of ips or cidr ranges.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Networks(Vec<IpNet>);
+
+impl Networks {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(ip))
+    }
+
+    fn parse(list: &List) -> Result<Self, String> {
+        list.iter()
+            .map(|net| parse_net(net).ok_or_else(|| format!("{net} isn't an ip or cidr range")))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl FromValue for Networks {
+    fn from_text(text: &str) -> Result<Self, String> {
+        Self::parse(&List::from_text(text)?)
+    }
+
+    fn from_toml(value: &Value) -> Result<Self, String> {
+        Self::parse(&List::from_toml(value)?)
+    }
+}
+
+/// Where setting values come from, other than the environment.
+struct Sources {
+    /// name of the config file, for errors.
+    file: String,
+    /// values from the config file, by path, with the line they're on.
+    values: HashMap<String, (usize, Value)>,
+    /// values from flags, by path.
+    flags: HashMap<String, String>,
+}
+
+impl Sources {
+    /// The value of `path` from the highest precedence source it's set in.
+    fn get<T: FromValue>(&self, path: &str, env: &[&str], default: &str, errors: &mut Vec<String>) -> T {
+        let default = || T::from_text(default).unwrap_or_else(|_| panic!("default of {path} should be valid!"));
+        let parsed = if let Some((origin, text)) = env.iter()
+            .find_map(|name| env::var(name).ok().map(|value| (format!("environment variable {name}"), value)))
+            .or_else(|| self.flags.get(path).map(|value| (format!("flag --{path}"), value.clone())))
+        {
+            T::from_text(&text).map_err(|e| format!("{path} = {text:?} from {origin}: {e}"))
+        } else if let Some((line, value)) = self.values.get(path) {
+            T::from_toml(value).map_err(|e| format!("{path} = {value} from {}:{line}: {e}", self.file))
+        } else {
+            return default()
+        };
+
+        parsed.unwrap_or_else(|error| {
+            errors.push(error);
+            default()
+        })
+    }
+}
+
+/// Splits `args` into the config file and setting flags.
+fn parse_args(args: &[String], errors: &mut Vec<String>) -> (Option<PathBuf>, HashMap<String, String>) {
+    let mut file = None;
+    let mut flags = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            errors.push(format!("unexpected argument {arg:?}, settings are given as --<section>.<key> <value>"));
+            continue
+        };
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (flag, args.next().cloned()),
+        };
+        let Some(value) = value else {
+            errors.push(format!("flag --{name} is missing a value"));
+            continue
+        };
+
+        let path = ALIASES.iter().find(|(alias, _)| *alias == name).map_or(name, |(_, path)| path);
+        if path == "config" {
+            file = Some(PathBuf::from(value));
+        } else if SETTINGS.iter().any(|setting| setting.path == path) {
+            flags.insert(path.to_string(), value);
+        } else {
+            errors.push(format!("unknown flag --{name}, see --help for every setting"));
+        }
+    }
+    (file, flags)
+}
+
+/// Reads and parses the config file, reporting unknown settings.
+fn read_file(path: &Path, errors: &mut Vec<String>) -> HashMap<String, (usize, Value)> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            errors.push(format!("config file {} couldn't be read: {e}", path.display()));
+            return HashMap::new()
+        }
+    };
+
+    let values = parse_toml(&text).unwrap_or_else(|(line, e)| {
+        errors.push(format!("{}:{line}: {e}", path.display()));
+        HashMap::new()
+    });
+    for (key, (line, _)) in &values {
+        if !SETTINGS.iter().any(|setting| setting.path == key) {
+            errors.push(format!("{}:{line}: unknown setting {key}", path.display()));
+        }
+    }
+    values
+}
+
+/// Parses the config file into values by their dotted `section.key` path, with the line each is on.
+/// Values outside of a section, or tables nested in one, are kept under their own path to be reported as unknown settings.
+fn parse_toml(text: &str) -> Result<HashMap<String, (usize, Value)>, (usize, String)> {
+    let document = DeTable::parse(text).map_err(|e| (e.span().map_or(1, |span| line_of(text, span.start)), e.message().to_string()))?;
+    let mut values = HashMap::new();
+    for (name, section) in document.into_inner() {
+        let span = section.span();
+        let entries = match section.into_inner() {
+            DeValue::Table(entries) => entries.into_iter().map(|(key, value)| (format!("{}.{}", name.get_ref(), key.get_ref()), value)).collect(),
+            value => vec![(name.get_ref().to_string(), Spanned::new(span, value))],
+        };
+        for (path, value) in entries {
+            let line = line_of(text, value.span().start);
+            let value = Value::deserialize(value.into_deserializer()).map_err(|e| (line, format!("{path}: {}", e.message())))?;
+            values.insert(path, (line, value));
+        }
+    }
+    Ok(values)
+}
+
+/// The line `offset` is on, counting from 1.
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
@@ -1,32 +1,107 @@
 use std::{error::Error, fmt::Display};
 
-use actix_web::{ResponseError, http::StatusCode as ActixStatusCode};
+use actix_web::{HttpMessage, HttpResponse, ResponseError, body::MessageBody, dev::{ServiceRequest, ServiceResponse}, http::{StatusCode as ActixStatusCode, header::{CONTENT_LENGTH, CONTENT_TYPE}}, middleware::Next};
 use reqwest::{Error as ReqwestError, StatusCode};
-use simd_json::Error as SimdError;
+use simd_json::{Error as SimdError, OwnedValue, json};
 use serde_json::Error as SerdeError;
 
+use crate::request_id::RequestId;
+
 
 #[derive(Clone, Debug)]
 pub enum ProcessError {
     InternalServer(&'static str),
+    /// the client sent something we can't use, such as a malformed uuid.
+    BadRequest(&'static str),
+    /// upstream has nothing for the requested player.
+    NotFound(&'static str),
     Request(StatusCode),
+    /// upstream didn't answer within the request's budget.
+    Timeout,
+    /// upstream couldn't be connected to, or the connection failed before the response was read.
+    Unreachable,
+    /// the client sent an api token we don't know.
+    Unauthorized(&'static str),
+    /// the client is over its quota, see `rate_limit`.
+    RateLimited,
     Serialization(String),
     Database(String),
+    Forbidden(&'static str),
+    Unavailable(&'static str),
+    /// hypixel answered with an error or an unusable payload, `cause` is its explanation.
+    Hypixel { status: StatusCode, cause: String },
+    /// a reloaded config had problems, listed in `cause`. The config in use is kept.
+    InvalidConfig(String),
 }
 
 impl ProcessError {
     pub const fn internal(msg: &'static str) -> Self {
         Self::InternalServer(msg)
     }
+
+    /// Stable, machine readable name of the error, returned as `code` in error bodies.
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::InternalServer(_) => "internal_error",
+            Self::BadRequest(_) => "invalid_input",
+            Self::NotFound(_) => "not_found",
+            Self::Timeout => "upstream_timeout",
+            Self::Unreachable => "upstream_unreachable",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::RateLimited => "rate_limited",
+            Self::Serialization(_) => "serialization_error",
+            Self::Database(_) => "storage_error",
+            Self::Forbidden(_) => "forbidden",
+            Self::Unavailable(_) => "upstream_unavailable",
+            Self::InvalidConfig(_) => "invalid_config",
+            Self::Request(status) | Self::Hypixel { status, .. } => match *status {
+                StatusCode::TOO_MANY_REQUESTS => "upstream_throttled",
+                StatusCode::BAD_GATEWAY => "upstream_invalid",
+                StatusCode::NOT_FOUND => "not_found",
+                _ => "upstream_error",
+            },
+        }
+    }
+
+    /// Renders the error as the json body clients get, see `json_errors`.
+    pub fn to_json(&self, request_id: Option<&RequestId>) -> OwnedValue {
+        let (upstream_status, cause) = match self {
+            Self::Request(status) => (Some(status.as_u16()), None),
+            Self::Hypixel { status, cause } => (Some(status.as_u16()), Some(cause.as_str())),
+            Self::InvalidConfig(cause) => (None, Some(cause.as_str())),
+            _ => (None, None),
+        };
+        let message = match self {
+            Self::InternalServer(msg) | Self::BadRequest(msg) | Self::NotFound(msg) | Self::Forbidden(msg) | Self::Unavailable(msg) | Self::Unauthorized(msg) => msg,
+            Self::RateLimited => "Too many requests, slow down.",
+            Self::Serialization(msg) | Self::Database(msg) => msg.as_str(),
+            Self::Request(_) => "Upstream request failed.",
+            Self::Hypixel { .. } => "Upstream rejected the request.",
+            Self::Timeout => "Upstream took too long to respond.",
+            Self::Unreachable => "Upstream couldn't be reached.",
+            Self::InvalidConfig(_) => "The config is invalid, the current one was kept.",
+        };
+        error_json(self.code(), message, upstream_status, cause, request_id)
+    }
 }
 
 impl Display for ProcessError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::InternalServer(msg) => write!(f, "{}: {}", StatusCode::INTERNAL_SERVER_ERROR, msg),
+            Self::BadRequest(msg) => write!(f, "{}: {}", StatusCode::BAD_REQUEST, msg),
+            Self::NotFound(msg) => write!(f, "{}: {}", StatusCode::NOT_FOUND, msg),
+            Self::Timeout => write!(f, "{}: Upstream timed out", StatusCode::GATEWAY_TIMEOUT),
+            Self::Unreachable => write!(f, "{}: Upstream unreachable", StatusCode::BAD_GATEWAY),
+            Self::Unauthorized(msg) => write!(f, "{}: {}", StatusCode::UNAUTHORIZED, msg),
+            Self::RateLimited => write!(f, "{}: Too many requests", StatusCode::TOO_MANY_REQUESTS),
             Self::Request(error_code) => write!(f, "{error_code}: Request Error"),
             Self::Serialization(msg) | 
             Self::Database(msg) => write!(f, "{}: {}", StatusCode::INTERNAL_SERVER_ERROR, msg),
+            Self::Forbidden(msg) => write!(f, "{}: {}", StatusCode::FORBIDDEN, msg),
+            Self::Unavailable(msg) => write!(f, "{}: {}", StatusCode::SERVICE_UNAVAILABLE, msg),
+            Self::Hypixel { status, cause } => write!(f, "{status}: Hypixel: {cause}"),
+            Self::InvalidConfig(cause) => write!(f, "{}: Invalid config: {cause}", StatusCode::BAD_REQUEST),
         }
     }
 }
@@ -35,7 +110,11 @@ impl Error for ProcessError {}
 
 impl From<ReqwestError> for ProcessError {
     fn from(value: ReqwestError) -> Self {
-        Self::Request(value.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR))
+        if value.is_timeout() {
+            return Self::Timeout
+        }
+        // only errors made from a response carry a status, the rest never got a usable one.
+        value.status().map_or(Self::Unreachable, Self::Request)
     }
 }
 
@@ -60,8 +139,65 @@ impl From<ltmdb::Error> for ProcessError {
 impl ResponseError for ProcessError {
     fn status_code(&self) -> ActixStatusCode {
         match self {
-            Self::Request(code) => ActixStatusCode::from_u16(code.as_u16()).unwrap_or(ActixStatusCode::INTERNAL_SERVER_ERROR),
+            Self::Request(code) | Self::Hypixel { status: code, .. } => ActixStatusCode::from_u16(code.as_u16()).unwrap_or(ActixStatusCode::INTERNAL_SERVER_ERROR),
+            Self::BadRequest(_) | Self::InvalidConfig(_) => ActixStatusCode::BAD_REQUEST,
+            Self::NotFound(_) => ActixStatusCode::NOT_FOUND,
+            Self::Timeout => ActixStatusCode::GATEWAY_TIMEOUT,
+            Self::Unreachable => ActixStatusCode::BAD_GATEWAY,
+            Self::Unauthorized(_) => ActixStatusCode::UNAUTHORIZED,
+            Self::RateLimited => ActixStatusCode::TOO_MANY_REQUESTS,
+            Self::Forbidden(_) => ActixStatusCode::FORBIDDEN,
+            Self::Unavailable(_) => ActixStatusCode::SERVICE_UNAVAILABLE,
             _ => ActixStatusCode::INTERNAL_SERVER_ERROR,
         }
     }
-}
\ No newline at end of file
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self.to_json(None))
+    }
+}
+
+/// Rewrites every error response into a json body carrying the request's id.
+/// Errors that aren't a `ProcessError`, such as the rate limiter's, are given a code based on their status.
+pub async fn json_errors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<RequestId>().cloned();
+    let res = next.call(req).await?;
+
+    let Some(err) = res.response().error() else { return Ok(res.map_into_left_body()) };
+    let json = match err.as_error::<ProcessError>() {
+        Some(err) => err.to_json(request_id.as_ref()),
+        None => error_json(status_code(res.status()), &err.to_string(), None, None, request_id.as_ref()),
+    };
+
+    let mut rewritten = HttpResponse::build(res.status()).json(json);
+    // keep headers such as the rate limiter's retry hints.
+    for (name, value) in res.headers() {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+            rewritten.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    Ok(res.into_response(rewritten.map_into_right_body()))
+}
+
+fn status_code(status: ActixStatusCode) -> &'static str {
+    match status {
+        ActixStatusCode::BAD_REQUEST => "invalid_input",
+        ActixStatusCode::NOT_FOUND => "not_found",
+        ActixStatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        ActixStatusCode::FORBIDDEN => "forbidden",
+        _ => "internal_error",
+    }
+}
+
+fn error_json(code: &str, message: &str, upstream_status: Option<u16>, cause: Option<&str>, request_id: Option<&RequestId>) -> OwnedValue {
+    json!({
+        "code": code,
+        "message": message,
+        "upstream_status": upstream_status,
+        "cause": cause,
+        "request_id": request_id.map(RequestId::as_str),
+    })
+}
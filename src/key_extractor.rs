@@ -1,6 +1,9 @@
 use actix_governor::{KeyExtractor, SimpleKeyExtractionError};
 use actix_web::dev::ServiceRequest;
-use std::net::IpAddr;
+use ipnet::{IpNet, Ipv6Net};
+use std::{net::IpAddr, str::FromStr};
+
+use crate::config::config;
 
 #[derive(Clone)]
 pub struct RealKeyExtractor;
@@ -10,9 +13,86 @@ impl KeyExtractor for RealKeyExtractor {
     type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
 
     fn extract(&self, req: &ServiceRequest) -> Result<Self::Key, Self::KeyExtractionError> {
-        req.connection_info()
-            .realip_remote_addr()
-            .and_then(|ip| ip.parse::<IpAddr>().ok())
-            .ok_or(SimpleKeyExtractionError::new("No remote address"))
+        real_ip(req).map(bucket).ok_or(SimpleKeyExtractionError::new("No remote address"))
+    }
+}
+
+/// The client's ip, read from forwarding headers set by trusted proxies. Unlike the rate limiter's key it isn't grouped by prefix.
+pub fn real_ip(req: &ServiceRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip().to_canonical();
+    Some(client_ip(req, peer))
+}
+
+/// Walks the forwarding chain back from the tcp peer, the client is the first hop that isn't a trusted proxy.
+/// If every hop is trusted the furthest one is used.
+fn client_ip(req: &ServiceRequest, peer: IpAddr) -> IpAddr {
+    if !is_trusted(peer) {
+        return peer
+    }
+
+    let mut client = peer;
+    for hop in forwarded_chain(req).into_iter().rev() {
+        // anything unreadable in the chain could have come from anyone, so it's not walked past.
+        let Some(hop) = hop else { break };
+        client = hop;
+        if !is_trusted(hop) {
+            break
+        }
+    }
+    client
+}
+
+/// The hops listed by `Forwarded`, or `X-Forwarded-For` if it isn't set, oldest first.
+fn forwarded_chain(req: &ServiceRequest) -> Vec<Option<IpAddr>> {
+    let headers = req.headers();
+    let forwarded: Vec<_> = headers.get_all("Forwarded")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| element.split(';').find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            name.eq_ignore_ascii_case("for").then(|| parse_forwarded_for(value))
+        }))
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded
+    }
+
+    headers.get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|hop| parse_ip(hop.trim()))
+        .collect()
+}
+
+/// A `Forwarded` `for` value, such as `192.0.2.60`, `"192.0.2.60:4711"` or `"[2001:db8::1]:4711"`.
+fn parse_forwarded_for(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest.split_once(']').and_then(|(ip, _)| IpAddr::from_str(ip).ok())
+    }
+    parse_ip(value)
+}
+
+/// An ip, optionally followed by a port.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    IpAddr::from_str(value).ok()
+        .or_else(|| value.rsplit_once(':').and_then(|(ip, _)| IpAddr::from_str(ip).ok()))
+        .map(|ip| ip.to_canonical())
+}
+
+pub fn parse_net(net: &str) -> Option<IpNet> {
+    IpNet::from_str(net).ok().or_else(|| IpAddr::from_str(net).ok().map(IpNet::from))
+}
+
+/// Forwarding headers are only read from `ratelimit.trusted_proxies`, anyone else could use them to pick their own ip.
+fn is_trusted(ip: IpAddr) -> bool {
+    config().ratelimit.trusted_proxies.contains(&ip)
+}
+
+/// Groups ipv6 clients by `ratelimit.ipv6_prefix`, ipv4 clients are limited individually.
+fn bucket(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => Ipv6Net::new(ip, config().ratelimit.ipv6_prefix).map_or(IpAddr::V6(ip), |net| IpAddr::V6(net.network())),
     }
 }
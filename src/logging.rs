@@ -1,9 +1,123 @@
-use std::{fmt::Display, thread, time::Duration};
+use std::{fmt::Display, fs::{self, File, OpenOptions}, io::{self, Write}, panic::Location, path::PathBuf, str::FromStr, sync::{atomic::{AtomicU64, Ordering}}, thread, time::Duration};
 
 use actix_web::cookie::time::UtcDateTime;
-use tokio::sync::{OnceCell, mpsc::{UnboundedSender, unbounded_channel}};
+use simd_json::{OwnedValue, json, prelude::MutableObject};
+use tokio::sync::{OnceCell, mpsc::{Sender, channel, error::TrySendError}, oneshot};
 
-use crate::cache::UuidKey;
+use crate::{cache::{EncodedKey, cache_router::Shutdown}, config::config, request_id::{self, RequestId}};
+
+/// Messages dropped because the queue was full, since the last time it was reported.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static DROPPED_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            "trace" => Ok(Self::Trace),
+            other => Err(format!("unknown log level {other}, expected error, warn, info, debug or trace")),
+        }
+    }
+}
+
+/// Which levels are logged: a minimum level, optionally followed by per module overrides, such as `info,cache=debug,ltmdb=warn`.
+/// Modules are matched by prefix, the most specific match wins.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    default: Level,
+    modules: Vec<(String, Level)>,
+}
+
+impl Filter {
+    fn enabled(&self, level: Level, target: &str) -> bool {
+        let max = self.modules.iter()
+            .filter(|(module, _)| target == module || target.strip_prefix(module.as_str()).is_some_and(|rest| rest.starts_with("::")))
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |(_, level)| *level);
+        level <= max
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self { default: Level::Info, modules: Vec::new() }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Self::default();
+        for directive in s.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => filter.modules.push((module.trim().to_string(), level.parse()?)),
+                None => filter.default = directive.parse()?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.default.name())?;
+        self.modules.iter().try_for_each(|(module, level)| write!(f, ",{module}={}", level.name()))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown log format {other}, expected text or json")),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        })
+    }
+}
 
 pub enum LogMessage {
     TimeElapsed {
@@ -11,15 +125,132 @@ pub enum LogMessage {
         name: &'static str,
     },
     ElapsedUserStatus {
-        key: UuidKey,
+        key: EncodedKey,
         elapsed: Duration,
         message: &'static str,
         code: u16,
     },
     MessageAndUser {
-        key: UuidKey,
+        key: EncodedKey,
         message: &'static str,
     },
+    ApiKeyRevoked {
+        name: String,
+    },
+    BreakerTransition {
+        from: &'static str,
+        to: &'static str,
+    },
+    Startup {
+        message: String,
+    },
+    /// Reported by ltmdb through its `Observer`.
+    Storage {
+        message: String,
+    },
+    Dropped {
+        count: u64,
+    },
+    ConfigReloaded {
+        applied: Vec<&'static str>,
+        restart_required: Vec<&'static str>,
+    },
+    ConfigRejected {
+        errors: Vec<String>,
+    },
+    /// A request waited on another request for the same key rather than fetching it.
+    Followed {
+        key: EncodedKey,
+        leader: Option<RequestId>,
+    },
+    /// A peer couldn't answer for a key it owns, so it's fetched locally instead.
+    PeerFailed {
+        key: EncodedKey,
+        owner: String,
+        reason: String,
+    },
+    /// The process was asked to stop by a signal.
+    Stopping {
+        signal: &'static str,
+    },
+    Shutdown {
+        elapsed: Duration,
+        summary: Shutdown,
+    },
+    ShutdownFailed {
+        error: String,
+    },
+    /// Storage couldn't be loaded, so the process exits.
+    StartupFailed {
+        error: String,
+    },
+}
+
+impl LogMessage {
+    /// Typed fields included in json lines, next to the formatted message.
+    fn fields(&self, fields: &mut OwnedValue) {
+        match self {
+            Self::TimeElapsed { elapsed, name } => {
+                fields.insert("name", *name).ok();
+                fields.insert("elapsed_ms", millis(*elapsed)).ok();
+            }
+            Self::ElapsedUserStatus { key, elapsed, code, .. } => {
+                fields.insert("key", key.to_string()).ok();
+                fields.insert("flag", key.flag()).ok();
+                fields.insert("elapsed_ms", millis(*elapsed)).ok();
+                fields.insert("status", *code).ok();
+            }
+            Self::MessageAndUser { key, .. } => {
+                fields.insert("key", key.to_string()).ok();
+                fields.insert("flag", key.flag()).ok();
+            }
+            Self::ApiKeyRevoked { name } => {
+                fields.insert("api_key", name.as_str()).ok();
+            }
+            Self::BreakerTransition { from, to } => {
+                fields.insert("from", *from).ok();
+                fields.insert("to", *to).ok();
+            }
+            Self::Dropped { count } => {
+                fields.insert("dropped", *count).ok();
+            }
+            Self::ConfigReloaded { applied, restart_required } => {
+                fields.insert("applied", applied.clone()).ok();
+                fields.insert("restart_required", restart_required.clone()).ok();
+            }
+            Self::ConfigRejected { errors } => {
+                fields.insert("errors", errors.clone()).ok();
+            }
+            Self::Followed { key, leader } => {
+                fields.insert("key", key.to_string()).ok();
+                fields.insert("flag", key.flag()).ok();
+                fields.insert("leader", leader.as_ref().map(RequestId::as_str)).ok();
+            }
+            Self::PeerFailed { key, owner, reason } => {
+                fields.insert("key", key.to_string()).ok();
+                fields.insert("flag", key.flag()).ok();
+                fields.insert("peer", owner.as_str()).ok();
+                fields.insert("reason", reason.as_str()).ok();
+            }
+            Self::Stopping { signal } => {
+                fields.insert("signal", *signal).ok();
+            }
+            Self::Shutdown { elapsed, summary } => {
+                fields.insert("elapsed_ms", millis(*elapsed)).ok();
+                fields.insert("fills_abandoned", summary.fills_abandoned).ok();
+                if let Some(storage) = summary.storage {
+                    fields.insert("writes_finished", storage.writes_finished).ok();
+                    fields.insert("writes_abandoned", storage.writes_abandoned).ok();
+                    fields.insert("partitions_synced", storage.partitions_synced).ok();
+                    fields.insert("purges_finished", storage.purges_finished).ok();
+                }
+            }
+            Self::ShutdownFailed { error } | Self::StartupFailed { error } => {
+                fields.insert("error", error.as_str()).ok();
+            }
+            Self::Startup { .. } | Self::Storage { .. } => {}
+        }
+    }
 }
 
 impl Display for LogMessage {
@@ -34,22 +265,247 @@ impl Display for LogMessage {
             Self::MessageAndUser { key, message: field } => {
                 write!(f, "{field}: {key}")
             }
+            Self::ApiKeyRevoked { name } => {
+                write!(f, "Api key {name} was rejected, removing it from rotation")
+            }
+            Self::BreakerTransition { from, to } => {
+                write!(f, "Upstream circuit breaker went from {from} to {to}")
+            }
+            Self::Startup { message } | Self::Storage { message } => {
+                f.write_str(message)
+            }
+            Self::Dropped { count } => {
+                write!(f, "Dropped {count} log messages, the log queue was full")
+            }
+            Self::ConfigReloaded { applied, restart_required } if restart_required.is_empty() => {
+                write!(f, "Reloaded config, changed [{}]", applied.join(", "))
+            }
+            Self::ConfigReloaded { applied, restart_required } => {
+                write!(f, "Reloaded config, changed [{}], [{}] need a restart to change", applied.join(", "), restart_required.join(", "))
+            }
+            Self::ConfigRejected { errors } => {
+                write!(f, "Config wasn't reloaded, keeping the current one: {}", errors.join("; "))
+            }
+            Self::Followed { key, leader } => {
+                write!(f, "Followed request {} for {key}", leader.as_ref().map_or("unknown", RequestId::as_str))
+            }
+            Self::PeerFailed { key, owner, reason } => {
+                write!(f, "Peer {owner} failed, fetching locally: {key} ({reason})")
+            }
+            Self::Stopping { signal } => {
+                write!(f, "Got {signal}, finishing in flight requests before shutting down")
+            }
+            Self::Shutdown { elapsed, summary } => {
+                write!(f, "Shut down in {elapsed:?}, {} streamed bodies abandoned", summary.fills_abandoned)?;
+                match summary.storage {
+                    Some(storage) => write!(
+                        f,
+                        ", {} pending writes finished, {} abandoned, {} partitions synced, {} purges finished",
+                        storage.writes_finished, storage.writes_abandoned, storage.partitions_synced, storage.purges_finished,
+                    ),
+                    None => Ok(()),
+                }
+            }
+            Self::ShutdownFailed { error } => {
+                write!(f, "Storage failed to close, recent writes may be lost: {error}")
+            }
+            Self::StartupFailed { error } => {
+                write!(f, "Storage failed to load, exiting: {error}")
+            }
+        }
+    }
+}
+
+/// A message waiting to be written.
+struct Record {
+    time: UtcDateTime,
+    level: Level,
+    /// module the message came from, such as `cache::cache_router`.
+    target: String,
+    /// the request being handled when the message was logged.
+    request_id: Option<RequestId>,
+    msg: LogMessage,
+}
+
+impl Record {
+    fn format(&self, format: Format) -> String {
+        match format {
+            Format::Text => match &self.request_id {
+                Some(id) => format!("{} {:<5} {} [{}]: {}", self.time, self.level.name().to_ascii_uppercase(), self.target, id.as_str(), self.msg),
+                None => format!("{} {:<5} {}: {}", self.time, self.level.name().to_ascii_uppercase(), self.target, self.msg),
+            },
+            Format::Json => {
+                let mut line = json!({
+                    "time": self.time.to_string(),
+                    "level": self.level.name(),
+                    "target": self.target.as_str(),
+                    "request_id": self.request_id.as_ref().map(RequestId::as_str),
+                    "message": self.msg.to_string(),
+                });
+                self.msg.fields(&mut line);
+                simd_json::to_string(&line).unwrap_or_default()
+            }
         }
-    } 
+    }
+}
+
+/// Taken by the writer thread in order.
+enum Queued {
+    Record(Record),
+    /// answered once everything queued before it is written.
+    Flush(oneshot::Sender<()>),
 }
 
-static SENDER: OnceCell<UnboundedSender<(UtcDateTime, LogMessage)>> = OnceCell::const_new();
+static SENDER: OnceCell<Sender<Queued>> = OnceCell::const_new();
 
-pub fn log(msg: LogMessage) {
-    let _ = SENDER.get().expect("Logger should be initialized").send((UtcDateTime::now(), msg));
+/// Logs a message from the calling module, if `LOG_LEVEL` allows it.
+#[track_caller]
+pub fn log(level: Level, msg: LogMessage) {
+    log_to(level, module(Location::caller().file()), msg);
 }
 
-pub fn init() {
-    let (tx, mut rx) = unbounded_channel::<(UtcDateTime, LogMessage)>();
+/// Logs a message as coming from `target`, for messages bridged from other crates.
+pub fn log_to(level: Level, target: impl Into<String>, msg: LogMessage) {
+    let target = target.into();
+    // anything logged before the logger exists, such as while the config loads, goes straight to stderr.
+    let Some(sender) = SENDER.get() else {
+        eprintln!("{}", Record { time: UtcDateTime::now(), level, target, request_id: None, msg }.format(Format::Text));
+        return
+    };
+    if !config().log.level.enabled(level, &target) {
+        return
+    }
+    if let Err(TrySendError::Full(_)) = sender.try_send(Queued::Record(Record { time: UtcDateTime::now(), level, target, request_id: request_id::current(), msg })) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        DROPPED_TOTAL.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Messages dropped because the log queue was full.
+pub fn dropped() -> u64 {
+    DROPPED_TOTAL.load(Ordering::Relaxed)
+}
+
+/// Waits for every message logged so far to be written, so none are lost when the process exits.
+pub async fn flush() {
+    let Some(sender) = SENDER.get() else { return };
+    let (tx, rx) = oneshot::channel();
+    if sender.send(Queued::Flush(tx)).await.is_ok() {
+        let _ = rx.await;
+    }
+}
+
+/// Starts writing logs to `log.file`, rotated once it reaches `log.file_max_bytes` with `log.file_keep` old files kept, or to stdout.
+///
+/// # Errors
+/// Returns an error if `log.file` can't be opened.
+pub fn init() -> io::Result<()> {
+    let settings = &config().log;
+    let (tx, mut rx) = channel::<Queued>(settings.queue.max(1));
+    let format = settings.format;
+    let mut out: Box<dyn Write + Send> = if settings.file.is_empty() {
+        Box::new(io::stdout())
+    } else {
+        Box::new(RotatingFile::open(PathBuf::from(settings.file.as_str()), settings.file_max_bytes, settings.file_keep)?)
+    };
     SENDER.set(tx).unwrap();
+
     thread::spawn(move || {
-        while let Some((time, msg)) = rx.blocking_recv() {
-            println!("{time}: {msg}");
+        while let Some(queued) = rx.blocking_recv() {
+            let record = match queued {
+                Queued::Record(record) => record,
+                Queued::Flush(done) => {
+                    let _ = out.flush();
+                    let _ = done.send(());
+                    continue
+                }
+            };
+            let dropped = DROPPED.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                let report = Record { time: record.time, level: Level::Warn, target: "logging".to_string(), request_id: None, msg: LogMessage::Dropped { count: dropped } };
+                write_line(&mut out, &report, format);
+            }
+            write_line(&mut out, &record, format);
         }
     });
-}
\ No newline at end of file
+    Ok(())
+}
+
+/// A log file that's moved aside once it grows past `max_bytes`, keeping `keep` old files.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    max_bytes: u64,
+    keep: u32,
+}
+
+impl RotatingFile {
+    /// # Errors
+    /// Returns an error if the file can't be opened for appending.
+    pub fn open(path: PathBuf, max_bytes: u64, keep: u32) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(Self { path, file, len, max_bytes, keep })
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for index in (1..self.keep).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.len > 0 && self.len + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Writes a record in one call, so a file can't be rotated in the middle of a line.
+fn write_line(out: &mut impl Write, record: &Record, format: Format) {
+    let mut line = record.format(format);
+    line.push('\n');
+    let _ = out.write_all(line.as_bytes());
+}
+
+/// The module a source file belongs to, `src/cache/cache_router.rs` is `cache::cache_router`.
+fn module(file: &str) -> String {
+    let file = file.strip_prefix("src/").unwrap_or(file);
+    let file = file.strip_suffix(".rs").unwrap_or(file);
+    let file = file.strip_suffix("/mod").unwrap_or(file);
+    file.replace('/', "::")
+}
+
+fn millis(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64() * 1000.0
+}
@@ -1,70 +1,166 @@
-use std::{env, fmt::{Debug, Display}, str::FromStr, sync::OnceLock};
+use std::{env, process::ExitCode, sync::LazyLock, time::{Duration, Instant}};
 
-use actix_governor::{Governor, GovernorConfigBuilder};
-use actix_web::{App, HttpServer, middleware::from_fn, web::Data};
+use actix_web::{App, HttpServer, dev::ServerHandle, middleware::from_fn, web::{Data, scope}};
 use mimalloc::MiMalloc;
+use tokio::{select, signal::unix::{SignalKind, signal}, time::timeout};
 
-use crate::{cache::cache_router::CacheRouter, key_extractor::RealKeyExtractor, routes::{profile::profile, secrets::secrets, stats::{RateLimit, statistics}}};
+use crate::{api_keys::ApiKeys, cache::cache_router::CacheRouter, config::config, logging::{Level, LogMessage, log}, routes::{cache::{evict_cache, inspect_cache}, config::reload_config, health::{STARTED, healthz, readyz}, metrics::prometheus_metrics, peer::peer, profile::profile, secrets::secrets, stats::statistics, tokens::{issue_token, revoke_token}}, tokens::Tokens};
 
+mod access_log;
+mod admin;
+mod api_keys;
+mod breaker;
 mod cache;
+mod config;
 mod key_extractor;
 mod routes;
 mod timer;
+mod tokens;
 mod request_utils;
 mod logging;
+mod metrics;
 mod error;
+mod peers;
+mod rate_limit;
+mod request_id;
+mod retry;
+mod validation;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-pub static API_KEY: OnceLock<String> = OnceLock::new();
-
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    logging::init();
+async fn main() -> std::io::Result<ExitCode> {
+    LazyLock::force(&STARTED);
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", config::usage());
+        return Ok(ExitCode::SUCCESS)
+    }
+    let file = match config::load(&args) {
+        Ok(file) => file,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            errors.iter().for_each(|error| eprintln!("  {error}"));
+            return Ok(ExitCode::from(2))
+        }
+    };
+    if let Err(e) = logging::init() {
+        eprintln!("log.file {} couldn't be opened: {e}", config().log.file);
+        return Ok(ExitCode::FAILURE)
+    }
+    if let Err(e) = access_log::init() {
+        eprintln!("access_log.path {} couldn't be opened: {e}", config().access_log.path);
+        return Ok(ExitCode::FAILURE)
+    }
+    if let Some(file) = file {
+        log(Level::Info, LogMessage::Startup { message: format!("Loaded config from {}", file.display()) });
+    }
 
-    let api_key = std::env::var("API_KEY").expect("no api key env variable found");
-    API_KEY.set(api_key).expect("API_KEY should be available to set!");
-    let ip_addr: String = std::env::var("IP_ADDR").unwrap_or("127.0.0.1".to_string());
-    println!("Listening on {ip_addr}:8000!");
+    let keys = Data::new(ApiKeys::from_config());
+    let (ip_addr, port) = (config().server.bind, config().server.port);
+    log(Level::Info, LogMessage::Startup { message: format!("Listening on {ip_addr}:{port}!") });
 
-    let rate_limit = GovernorConfigBuilder::default()
-        .key_extractor(RealKeyExtractor)
-        .seconds_per_request(env_var("RATELIMIT_REFRESH", 3))
-        .burst_size(env_var("RATELIMIT_BURST", 10))
-        .finish()
-        .unwrap();
+    // answers health checks while storage loads, which can take a while with a large database.
+    let loading = HttpServer::new(|| App::new().service(healthz).service(readyz))
+        .workers(1)
+        .disable_signals()
+        .bind((ip_addr, port))?
+        .run();
+    let loading_handle = loading.handle();
+    let loading = actix_web::rt::spawn(loading);
 
-    let stats = Data::new(RateLimit::new());
-    let cache = Data::new(CacheRouter::load().await.unwrap());
+    let tokens = Data::new(Tokens::new());
+    actix_web::rt::spawn(reload_on_hangup(tokens.clone()));
+    let loaded = CacheRouter::load().await;
+    loading_handle.stop(false).await; // probes cut off here are simply retried against the full server.
+    let _ = loading.await;
+    let cache = match loaded {
+        Ok(router) => Data::new(router),
+        Err(e) => {
+            log(Level::Error, LogMessage::StartupFailed { error: e.to_string() });
+            flush_logs().await;
+            return Ok(ExitCode::FAILURE)
+        }
+    };
+    let router = cache.clone();
 
-    HttpServer::new(move || {
+    let shutdown_timeout = Duration::from_secs(config().server.shutdown_timeout_seconds);
+    let server = HttpServer::new(move || {
         App::new()
-            .app_data(stats.clone())
+            .app_data(keys.clone())
             .app_data(cache.clone())
-            .wrap(Governor::new(&rate_limit))
+            .app_data(tokens.clone())
+            .wrap(from_fn(error::json_errors))
             .wrap(from_fn(timer::timer))
-            .service(secrets)
-            .service(profile)
-            .service(statistics)
+            .wrap(from_fn(access_log::access_log))
+            .wrap(from_fn(request_id::request_id))
+            .service(healthz) // health checks aren't rate limited, so probes can't be starved by clients.
+            .service(readyz)
+            .service(
+                scope("")
+                    .wrap(from_fn(rate_limit::rate_limit))
+                    .service(peer)
+                    .service(secrets)
+                    .service(profile)
+                    .service(statistics)
+                    .service(prometheus_metrics)
+                    .service(inspect_cache)
+                    .service(evict_cache)
+                    .service(issue_token)
+                    .service(revoke_token)
+                    .service(reload_config)
+            )
     })
-    .bind((ip_addr, 8000))?
-    .run()
-    .await
-}
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
+    .bind((ip_addr, port))?
+    .run();
+    actix_web::rt::spawn(stop_on_terminate(server.handle()));
+    server.await?;
 
-/// # Panics
-/// panics if the environment variable is not parsable as `T`.
-pub fn env_var<T>(key: &'static str, default: T) -> T
-where
-    T: FromStr + Display,
-    T::Err: Debug
-{
-    match env::var(key) {
-        Ok(str) => str.parse::<T>().unwrap_or_else(|e| panic!("{} should be a {}!: {e:?}", key, std::any::type_name::<T>())),
+    let stopped = Instant::now();
+    let code = match router.shutdown(shutdown_timeout).await {
+        Ok(summary) => {
+            log(Level::Info, LogMessage::Shutdown { elapsed: stopped.elapsed(), summary });
+            ExitCode::SUCCESS
+        }
         Err(e) => {
-            eprintln!("{e}: {key}, using {default} default.");
-            default
+            log(Level::Error, LogMessage::ShutdownFailed { error: e.to_string() });
+            ExitCode::FAILURE
         }
+    };
+    flush_logs().await;
+    Ok(code)
+}
+
+/// Waits for queued log and access lines to be written before exiting.
+async fn flush_logs() {
+    // the writers may be stuck on a full disk or a closed stdout, which shouldn't keep the process alive.
+    let _ = timeout(Duration::from_secs(5), async { tokio::join!(logging::flush(), access_log::flush()) }).await;
+}
+
+/// Stops the server on the first `SIGTERM` or `SIGINT`, letting in flight requests finish for `server.shutdown_timeout_seconds`.
+/// A second signal stops it straight away.
+async fn stop_on_terminate(server: ServerHandle) {
+    let (Ok(mut terminate), Ok(mut interrupt)) = (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) else { return };
+    let signal = select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    };
+    log(Level::Info, LogMessage::Stopping { signal });
+    let graceful = server.stop(true);
+    select! {
+        () = graceful => {}
+        _ = terminate.recv() => server.stop(false).await,
+        _ = interrupt.recv() => server.stop(false).await,
     }
-}
\ No newline at end of file
+}
+
+/// Reloads the config every time the process gets a `SIGHUP`.
+async fn reload_on_hangup(tokens: Data<Tokens>) {
+    let Ok(mut hangups) = signal(SignalKind::hangup()) else { return };
+    while hangups.recv().await.is_some() {
+        let _ = routes::config::reload(&tokens);
+    }
+}
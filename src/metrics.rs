@@ -0,0 +1,212 @@
+use std::{collections::HashMap, fmt::Write, sync::{Arc, LazyLock, RwLock, atomic::{AtomicU64, Ordering}}, time::Duration};
+
+use rapidhash_lite::RandomHash;
+
+use crate::{access_log, api_keys::ApiKeys, breaker::BreakerState, cache::{cache_router::CacheRouter, cache_stats::{KeyStats, WindowCounter}}, logging::{self, Level, LogMessage, log_to}, request_utils::BREAKER};
+
+/// Upper bounds in seconds of the latency histogram buckets, `+Inf` is implied.
+const BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Instrumentation read by `/metrics`, recorded where the work happens rather than parsed from logs.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// A latency histogram with fixed buckets.
+pub struct Histogram {
+    /// events per bucket, not cumulative. The last is everything above the largest bound.
+    counts: [AtomicU64; BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self { counts: [const { AtomicU64::new(0) }; BUCKETS.len() + 1], sum_micros: AtomicU64::new(0) }
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|&bound| secs <= bound).unwrap_or(BUCKETS.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// Writes the `_bucket`, `_sum` and `_count` series, `labels` is rendered before `le` and may be empty.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = BUCKETS.get(index).map_or("+Inf".to_string(), f64::to_string);
+            let _ = writeln!(out, "{name}_bucket{{{labels}{sep}le=\"{le}\"}} {cumulative}");
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let labels = if labels.is_empty() { String::new() } else { format!("{{{labels}}}") };
+        let _ = writeln!(out, "{name}_sum{labels} {sum}");
+        let _ = writeln!(out, "{name}_count{labels} {cumulative}");
+    }
+}
+
+/// Histograms keyed by their rendered labels, created the first time a label set is seen.
+struct Family {
+    histograms: RwLock<HashMap<String, Arc<Histogram>, RandomHash>>,
+}
+
+impl Family {
+    fn new() -> Self {
+        Self { histograms: RwLock::new(HashMap::default()) }
+    }
+
+    fn observe(&self, labels: String, elapsed: Duration) {
+        let existing = self.histograms.read().ok().and_then(|histograms| histograms.get(&labels).cloned());
+        let histogram = match existing {
+            Some(histogram) => histogram,
+            None => {
+                let Ok(mut histograms) = self.histograms.write() else { return };
+                histograms.entry(labels).or_insert_with(|| Arc::new(Histogram::new())).clone()
+            }
+        };
+        histogram.observe(elapsed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let Ok(histograms) = self.histograms.read() else { return };
+        let mut sorted: Vec<_> = histograms.iter().collect();
+        sorted.sort_unstable_by_key(|(labels, _)| labels.as_str());
+        for (labels, histogram) in sorted {
+            histogram.render(out, name, labels);
+        }
+    }
+}
+
+pub struct Metrics {
+    /// handled requests by route pattern and status.
+    requests: Family,
+    /// upstream attempts by status, or `error` if no response came back.
+    upstream: Family,
+    /// ltmdb reads by whether the key was stored.
+    storage_reads: Family,
+    storage_writes: Histogram,
+    storage_written_bytes: AtomicU64,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            requests: Family::new(),
+            upstream: Family::new(),
+            storage_reads: Family::new(),
+            storage_writes: Histogram::new(),
+            storage_written_bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn request(&self, route: &str, status: u16, elapsed: Duration) {
+        self.requests.observe(format!("route=\"{}\",status=\"{status}\"", escape(route)), elapsed);
+    }
+
+    /// Records an upstream attempt, `status` is `None` if it failed without a response.
+    pub fn upstream(&self, status: Option<u16>, elapsed: Duration) {
+        let status = status.map_or("error".to_string(), |status| status.to_string());
+        self.upstream.observe(format!("status=\"{status}\""), elapsed);
+    }
+
+    /// Renders every metric in the prometheus text format.
+    pub fn render(&self, keys: &ApiKeys, cache: &CacheRouter) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "hypixel_api_request_duration_seconds", "histogram", "Time taken to handle requests, by route and status.");
+        self.requests.render(&mut out, "hypixel_api_request_duration_seconds");
+
+        header(&mut out, "hypixel_api_cache_hits_total", "counter", "Requests answered by each cache tier, by key type.");
+        for stats in cache.stats().iter() {
+            let key = stats.name();
+            for (tier, counter) in [("memory", &stats.memory_hits), ("database", &stats.db_hits), ("peer", &stats.peer_hits)] {
+                let _ = writeln!(out, "hypixel_api_cache_hits_total{{key=\"{key}\",tier=\"{tier}\"}} {}", counter.total());
+            }
+        }
+        counters(&mut out, cache, "hypixel_api_cache_misses_total", "Requests no cache tier could answer, fetched from upstream.", |stats| &stats.upstream_fetches);
+        counters(&mut out, cache, "hypixel_api_single_flight_leaders_total", "Requests that did the work for their key.", |stats| &stats.leaders);
+        counters(&mut out, cache, "hypixel_api_single_flight_followers_total", "Requests that waited on another request for the same key.", |stats| &stats.followers);
+        counters(&mut out, cache, "hypixel_api_cache_errors_total", "Requests for a key that failed.", |stats| &stats.errors);
+
+        header(&mut out, "hypixel_api_upstream_request_duration_seconds", "histogram", "Time taken by each upstream attempt, by status.");
+        self.upstream.render(&mut out, "hypixel_api_upstream_request_duration_seconds");
+
+        header(&mut out, "hypixel_api_upstream_ratelimit_remaining", "gauge", "Requests hypixel last reported left for each api key in rotation.");
+        for (name, remaining) in keys.remaining() {
+            if let Some((remaining, _)) = remaining {
+                let _ = writeln!(out, "hypixel_api_upstream_ratelimit_remaining{{key=\"{}\"}} {remaining}", escape(name));
+            }
+        }
+        header(&mut out, "hypixel_api_upstream_ratelimit_reset_seconds", "gauge", "Seconds until each api key's reported budget resets.");
+        for (name, remaining) in keys.remaining() {
+            if let Some((_, reset)) = remaining {
+                let _ = writeln!(out, "hypixel_api_upstream_ratelimit_reset_seconds{{key=\"{}\"}} {reset}", escape(name));
+            }
+        }
+
+        header(&mut out, "hypixel_api_breaker_state", "gauge", "Whether the upstream circuit breaker is in each state.");
+        let current = BREAKER.state();
+        for state in [BreakerState::Closed, BreakerState::Open, BreakerState::HalfOpen] {
+            let _ = writeln!(out, "hypixel_api_breaker_state{{state=\"{}\"}} {}", state.name(), u8::from(state == current));
+        }
+
+        header(&mut out, "hypixel_api_storage_read_duration_seconds", "histogram", "Time taken by ltmdb reads, by whether the key was stored.");
+        self.storage_reads.render(&mut out, "hypixel_api_storage_read_duration_seconds");
+        header(&mut out, "hypixel_api_storage_write_duration_seconds", "histogram", "Time taken by ltmdb writes.");
+        self.storage_writes.render(&mut out, "hypixel_api_storage_write_duration_seconds", "");
+        header(&mut out, "hypixel_api_storage_written_bytes_total", "counter", "Bytes written to ltmdb, including entry metadata.");
+        let _ = writeln!(out, "hypixel_api_storage_written_bytes_total {}", self.storage_written_bytes.load(Ordering::Relaxed));
+
+        header(&mut out, "hypixel_api_log_dropped_total", "counter", "Log messages dropped because the log queue was full.");
+        let _ = writeln!(out, "hypixel_api_log_dropped_total {}", logging::dropped());
+        header(&mut out, "hypixel_api_access_log_dropped_total", "counter", "Access log lines dropped because the access log queue was full.");
+        let _ = writeln!(out, "hypixel_api_access_log_dropped_total {}", access_log::dropped());
+
+        let storage = cache.storage();
+        if let Some(bytes) = storage.disk_bytes() {
+            header(&mut out, "hypixel_api_storage_disk_bytes", "gauge", "Bytes held by ltmdb partition files, including values not yet purged.");
+            let _ = writeln!(out, "hypixel_api_storage_disk_bytes {bytes}");
+        }
+        if let Some(depth) = storage.expiration_queue_len() {
+            header(&mut out, "hypixel_api_storage_expiration_queue_depth", "gauge", "ltmdb partitions in the expiration queue, each is purged once it expires.");
+            let _ = writeln!(out, "hypixel_api_storage_expiration_queue_depth {depth}");
+        }
+        out
+    }
+}
+
+/// Feeds ltmdb's reads and writes into `METRICS`.
+pub struct StorageObserver;
+
+impl ltmdb::Observer for StorageObserver {
+    fn read(&self, elapsed: Duration, hit: bool) {
+        METRICS.storage_reads.observe(format!("result=\"{}\"", if hit { "hit" } else { "miss" }), elapsed);
+    }
+
+    fn write(&self, elapsed: Duration, len: u64) {
+        METRICS.storage_writes.observe(elapsed);
+        METRICS.storage_written_bytes.fetch_add(len, Ordering::Relaxed);
+    }
+
+    fn purge_failed(&self, partition: usize, err: &ltmdb::Error) {
+        log_to(Level::Warn, "ltmdb", LogMessage::Storage { message: format!("Failed to delete partition {partition}: {err}") });
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// A counter per key type, read from its `KeyStats`.
+fn counters(out: &mut String, cache: &CacheRouter, name: &str, help: &str, counter: fn(&KeyStats) -> &WindowCounter) {
+    header(out, name, "counter", help);
+    for stats in cache.stats().iter() {
+        let _ = writeln!(out, "{name}{{key=\"{}\"}} {}", stats.name(), counter(stats).total());
+    }
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use actix_web::{http::header::HeaderMap, web::Bytes};
+use rapidhash_lite::RapidHash;
+use reqwest::{Client, StatusCode, header::HeaderValue};
+use simd_json::prelude::{ValueAsScalar, ValueObjectAccess};
+use subtle::ConstantTimeEq;
+use tokio::time::Instant;
+
+use crate::{cache::{EncodedKey, UuidKey}, config::config, error::ProcessError, logging::{Level, LogMessage, log}, request_id::{self, REQUEST_ID_HEADER}};
+
+/// Number of points each peer is given on the hash ring. More points spread keys more evenly.
+const VIRTUAL_NODES: usize = 64;
+
+/// Header peers send `peers.secret` in.
+pub const SECRET_HEADER: &str = "Peer-Secret";
+
+/// Peers sharing one cache, each owning the keys that hash to them on a consistent hash ring.
+///
+/// Configured by `peers.urls`, a list of peer base urls, and `peers.self_url`, this instance's
+/// entry in that list. Every peer must be given the same list so they agree on owners.
+pub struct Peers {
+    ring: Vec<(u64, usize)>,
+    urls: Vec<String>,
+    self_index: usize,
+    client: Client,
+    secret: HeaderValue,
+}
+
+impl Peers {
+    /// Returns `None` if peering isn't configured. The config checks `peers.self_url` is one of `peers.urls` when loaded.
+    pub fn from_config() -> Option<Self> {
+        let peers = &config().peers;
+        let self_index = peers.index_of_self().filter(|_| !peers.urls.is_empty())?;
+        let urls: Vec<String> = peers.urls.iter().map(|url| url.trim_end_matches('/').to_string()).collect();
+
+        let client = Client::builder()
+            .timeout(Duration::from_millis(peers.timeout_millis))
+            .build()
+            .expect("Peer client should build");
+
+        let mut secret = HeaderValue::from_str(&peers.secret).expect("the config should check peers.secret is a valid header value!");
+        secret.set_sensitive(true);
+
+        log(Level::Info, LogMessage::Startup { message: format!("Peering with {} instances as {}", urls.len(), peers.self_url) });
+        Some(Self::new(urls, self_index, client, secret))
+    }
+
+    fn new(urls: Vec<String>, self_index: usize, client: Client, secret: HeaderValue) -> Self {
+        // the hasher must not be random, every peer needs to build the exact same ring.
+        let hasher = RapidHash::new();
+        let mut ring = Vec::with_capacity(urls.len() * VIRTUAL_NODES);
+        for (index, url) in urls.iter().enumerate() {
+            for node in 0..VIRTUAL_NODES {
+                ring.push((hasher.hash(format!("{url}#{node}").as_bytes()), index));
+            }
+        }
+        ring.sort_unstable();
+
+        Self { ring, urls, self_index, client, secret }
+    }
+
+    /// Returns the base url of the peer owning `key`, or `None` if this instance owns it.
+    pub fn owner(&self, key: &UuidKey) -> Option<&str> {
+        let hash = RapidHash::new().hash(&key.as_u128().to_be_bytes());
+        let point = self.ring.partition_point(|(node, _)| *node < hash);
+        let (_, index) = self.ring.get(point).or_else(|| self.ring.first())?;
+
+        if *index == self.self_index { None } else { Some(&self.urls[*index]) }
+    }
+
+    /// Asks the owning peer for a key. The owner fetches it locally if it isn't cached there.
+    ///
+    /// # Errors
+    /// Returns `PeerError::Upstream` for an error the owner got from upstream, and `PeerError::Failed` for anything else.
+    pub async fn fetch(&self, owner: &str, key: &UuidKey) -> Result<Bytes, PeerError> {
+        let now = Instant::now();
+        let url = format!("{owner}/peer/{}/{}", key.flag(), key.uuid());
+        let mut req = self.client.get(url).header(SECRET_HEADER, self.secret.clone());
+        // the owner adopts our id, so its logs for the fetch can be matched with ours.
+        if let Some(id) = request_id::current() {
+            req = req.header(REQUEST_ID_HEADER, id.as_str());
+        }
+        let res = req.send().await.map_err(|e| PeerError::Failed(e.to_string()))?;
+        let status = res.status();
+        log(Level::Debug, LogMessage::ElapsedUserStatus { key: EncodedKey::Uuid(*key), elapsed: now.elapsed(), message: "Peer hit", code: status.as_u16() });
+        if status.is_success() {
+            return res.bytes().await.map_err(|e| PeerError::Failed(e.to_string()))
+        }
+        Err(PeerError::from_response(status, &res.bytes().await.unwrap_or_default()))
+    }
+}
+
+/// Why a peer didn't answer with a value.
+pub enum PeerError {
+    /// an error the owner got from upstream, returned as is so we don't repeat its upstream call.
+    Upstream(ProcessError),
+    /// the owner couldn't be reached, failed, or refused the request itself, such as over a mismatched `peers.secret`.
+    /// The key should be fetched locally instead.
+    Failed(String),
+}
+
+impl PeerError {
+    /// Sorts an error response from `/peer` by its json body. Errors from upstream carry its `upstream_status`,
+    /// and `not_found` is the owner finding nothing upstream. Anything else is the owner's own doing.
+    /// Server errors are always treated as failures, the owner may be the one struggling.
+    fn from_response(status: StatusCode, body: &[u8]) -> Self {
+        let mut body = body.to_vec();
+        let json = simd_json::to_owned_value(&mut body).ok();
+        let field = |name| json.as_ref().and_then(|json| json.get(name));
+        let code = field("code").and_then(|code| code.as_str()).unwrap_or("unknown");
+        let from_upstream = field("upstream_status").and_then(|upstream| upstream.as_u16()).is_some() || code == "not_found";
+
+        if status.is_server_error() || !from_upstream {
+            let message = field("message").and_then(|message| message.as_str()).unwrap_or_default();
+            return Self::Failed(format!("{status} {code}: {message}"))
+        }
+        match field("cause").and_then(|cause| cause.as_str()) {
+            Some(cause) => Self::Upstream(ProcessError::Hypixel { status, cause: cause.to_string() }),
+            None => Self::Upstream(ProcessError::Request(status)),
+        }
+    }
+}
+
+/// Returns `true` if the request carries `peers.secret`, and so comes from a peer.
+/// Always `false` when peering isn't configured.
+pub fn authenticated(headers: &HeaderMap) -> bool {
+    let config = config();
+    let secret = config.peers.secret.as_bytes();
+    match headers.get(SECRET_HEADER) {
+        Some(sent) if !secret.is_empty() => sent.as_bytes().ct_eq(secret).into(),
+        _ => false,
+    }
+}
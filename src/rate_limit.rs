@@ -0,0 +1,114 @@
+use actix_governor::KeyExtractor;
+use actix_web::{HttpResponse, body::MessageBody, dev::{ServiceRequest, ServiceResponse}, http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER}, middleware::Next, web::{Data, Query}};
+
+use crate::{cache::{cache_router::{CacheRouter, Flight, Source}, cache_stats::Tier as CacheTier}, error::ProcessError, key_extractor::RealKeyExtractor, peers, routes::{FreshQuery, peer, profile, secrets}, tokens::{Client, Throttled, Tier, Tokens, Usage}};
+
+/// Header clients send their issued token in.
+pub const TOKEN_HEADER: &str = "X-Api-Token";
+
+/// What a request costs against its client's quota, declared by each route as `COST`.
+#[derive(Clone, Copy)]
+pub struct Cost {
+    /// charged before the request is handled, all a request answered from cache costs.
+    pub base: u32,
+    /// charged on top once a request is found to have gone upstream.
+    pub upstream: u32,
+    /// charged on top, before the request is handled, when it asks to skip the cache with `?fresh=true`.
+    pub fresh: u32,
+}
+
+impl Cost {
+    /// Cost of routes that never go upstream.
+    pub const FLAT: Self = Self { base: 1, upstream: 0, fresh: 0 };
+}
+
+/// The declared cost of a route, by its first path segment.
+fn cost(route: &str) -> Cost {
+    match route {
+        "get" => profile::COST,
+        "secrets" => secrets::COST,
+        "peer" => peer::COST,
+        _ => Cost::FLAT,
+    }
+}
+
+/// Limits requests by their token's quota, or by ip address for requests without one.
+/// Every response carries `X-RateLimit-*` headers describing the client's quota.
+///
+/// Peers sending `peers.secret` aren't limited, they only ask for keys their caller would otherwise fetch itself.
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let route = req.path().trim_start_matches('/').split('/').next().unwrap_or_default().to_string();
+    if route == "peer" && peers::authenticated(req.headers()) {
+        return Ok(next.call(req).await?.map_into_left_body())
+    }
+    let tokens = req.app_data::<Data<Tokens>>().expect("Tokens should be registered").clone();
+    let client = match client(&req, &route, &tokens).await {
+        Ok(client) => client,
+        Err(res) => return Ok(req.into_response(res).map_into_right_body()),
+    };
+    let tier = client.tier();
+
+    let cost = cost(&route);
+    let fresh = Query::<FreshQuery>::from_query(req.query_string()).is_ok_and(|query| query.fresh);
+    let upfront = cost.base + if fresh { cost.fresh } else { 0 };
+
+    match tokens.check(&client, upfront) {
+        Ok(mut usage) => {
+            let mut res = next.call(req).await?;
+            let source = res.response().extensions().get::<Source>().copied();
+            // followers and cache hits didn't cost us an upstream request, only the request that fetched did.
+            if let Some(Source { tier: CacheTier::Upstream, flight: Flight::Leader }) = source && cost.upstream > 0 {
+                usage = tokens.charge(&client, cost.upstream).unwrap_or(Usage { remaining: 0, ..usage });
+            }
+            headers(res.headers_mut(), tier, usage.limit, usage.remaining);
+            Ok(res.map_into_left_body())
+        }
+        Err(throttled) => Ok(req.into_response(throttled_response(tier, throttled)).map_into_right_body()),
+    }
+}
+
+/// Who the request is limited as, or the response refusing it.
+async fn client(req: &ServiceRequest, route: &str, tokens: &Tokens) -> Result<Client, HttpResponse> {
+    let ip = || RealKeyExtractor.extract(req).map_err(|_| HttpResponse::from_error(ProcessError::internal("Could not find the client's address.")));
+    let Some(secret) = req.headers().get(TOKEN_HEADER) else {
+        return Ok(Client::Anonymous(ip()?))
+    };
+
+    let secret = secret.to_str().map_err(|_| HttpResponse::from_error(ProcessError::Unauthorized("Unknown api token.")))?;
+    if !tokens.is_loaded(secret) {
+        // looking a token up reads storage, so it's charged to the ip like an anonymous request.
+        // otherwise made up tokens would skip the anonymous quota and cost a storage read each.
+        tokens.check(&Client::Anonymous(ip()?), 1).map_err(|throttled| throttled_response(Tier::Anonymous, throttled))?;
+    }
+    let cache = req.app_data::<Data<CacheRouter>>().expect("Cache should be registered");
+    let active = tokens.find(cache.storage(), secret).await
+        .and_then(|active| active.ok_or(ProcessError::Unauthorized("Unknown api token.")))
+        .map_err(HttpResponse::from_error)?;
+
+    if !active.token.allows(route) {
+        return Err(HttpResponse::from_error(ProcessError::Forbidden("Api token is not allowed on this route.")))
+    }
+    Ok(Client::Token(active))
+}
+
+/// The response to a request over its client's quota.
+fn throttled_response(tier: Tier, throttled: Throttled) -> HttpResponse {
+    let Some(wait) = throttled.wait else {
+        return HttpResponse::from_error(ProcessError::Forbidden("Request costs more than the quota allows."))
+    };
+    let mut res = HttpResponse::from_error(ProcessError::RateLimited);
+    let wait = wait.as_secs().max(1);
+    headers(res.headers_mut(), tier, throttled.limit, 0);
+    res.headers_mut().insert(RETRY_AFTER, HeaderValue::from(wait));
+    res.headers_mut().insert(HeaderName::from_static("x-ratelimit-reset"), HeaderValue::from(wait));
+    res
+}
+
+fn headers(headers: &mut HeaderMap, tier: Tier, limit: u32, remaining: u32) {
+    headers.insert(HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(limit));
+    headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(remaining));
+    headers.insert(HeaderName::from_static("x-ratelimit-tier"), HeaderValue::from_static(tier.name()));
+}
@@ -0,0 +1,64 @@
+use actix_web::{HttpMessage, body::MessageBody, dev::{ServiceRequest, ServiceResponse}, http::header::{HeaderName, HeaderValue}, middleware::Next};
+use uuid::Uuid;
+
+/// Header request ids are read from and echoed in.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+tokio::task_local! {
+    /// The id of the request the current task is handling, read by `logging` to tag each line.
+    static CURRENT: RequestId;
+}
+
+/// Identifies a single request in error bodies and logs. Taken from the client's `X-Request-Id` header if it sent a usable one.
+#[derive(Clone)]
+pub struct RequestId(String);
+
+impl RequestId {
+    fn from_request(req: &ServiceRequest) -> Self {
+        let sent = req.headers().get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= 64 && id.bytes().all(|byte| byte.is_ascii_graphic()));
+
+        match sent {
+            Some(id) => Self(id.to_string()),
+            None => Self(Uuid::new_v4().simple().to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The id of the request being handled by the current task, if any.
+pub fn current() -> Option<RequestId> {
+    CURRENT.try_with(RequestId::clone).ok()
+}
+
+/// Runs `fut` as part of the current request, for work that's spawned onto its own task.
+pub fn propagate<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    let id = current();
+    async move {
+        match id {
+            Some(id) => CURRENT.scope(id, fut).await,
+            None => fut.await,
+        }
+    }
+}
+
+/// Assigns every request a `RequestId`, stored in its extensions and echoed in the response's `X-Request-Id`.
+/// The rest of the request is handled within its scope, see `current`.
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let id = RequestId::from_request(&req);
+    req.extensions_mut().insert(id.clone());
+    let header = HeaderValue::from_str(id.as_str()).ok();
+
+    let mut res = CURRENT.scope(id, next.call(req)).await?;
+    if let Some(header) = header {
+        res.headers_mut().insert(HeaderName::from_static("x-request-id"), header);
+    }
+    Ok(res)
+}
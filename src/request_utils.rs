@@ -4,35 +4,119 @@ use actix_web::http::header::ContentType;
 use actix_web::web::Bytes;
 use actix_web::{mime, HttpResponse};
 use reqwest::Response;
-use reqwest::{header:: HeaderMap, Client};
-use tokio::time::Instant;
+use reqwest::{Client, StatusCode};
+use tokio::time::{Instant, sleep};
 
-use crate::API_KEY;
-use crate::cache::UuidKey;
+use crate::access_log;
+use crate::api_keys::ApiKeys;
+use crate::breaker::CircuitBreaker;
+use crate::config::config;
+use crate::retry::RetryPolicy;
+use crate::validation::{Validator, cause};
+use crate::cache::{EncodedKey, body::Body, cache_router::Served};
 use crate::error::ProcessError;
-use crate::logging::{LogMessage, log};
-
-static CLIENT: LazyLock<Client> = LazyLock::new(|| {
-    let api_key = API_KEY.get().expect("Api key should have been set already!");
-
-    let mut headers = HeaderMap::new();
-    headers.insert("API-Key", api_key.parse().unwrap());
-    
-    Client::builder()
-        .default_headers(headers.clone())
-        .build()
-        .unwrap()
-});
-
-pub async fn request(key: UuidKey, url: String) -> Result<Response, ProcessError> {
-    let now = Instant::now();
-    let res = CLIENT.get(url).send().await?;
-    log(LogMessage::ElapsedUserStatus { key, elapsed: now.elapsed(), message: "Upstream hit", code: res.status().as_u16() });
-    res.error_for_status().map_err(Into::into)
+use crate::logging::{Level, LogMessage, log};
+use crate::metrics::METRICS;
+
+static CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
+
+pub static BREAKER: LazyLock<CircuitBreaker> = LazyLock::new(CircuitBreaker::new);
+
+/// Requests `path` from the upstream api with the api key that has the most budget left, `path` should start with a `/`.
+/// Keys that are rejected are taken out of rotation and the request is retried with the next one.
+/// Transient failures are retried as `policy` allows, within its budget or until the caller's `deadline`, whichever is sooner.
+/// While `BREAKER` is open this fails fast with `ProcessError::Unavailable` instead.
+pub async fn request(keys: &ApiKeys, key: EncodedKey, path: String, policy: &RetryPolicy, deadline: Instant) -> Result<Response, ProcessError> {
+    // one permit covers every retry, so a request counts as a single failure however many times it was tried.
+    let permit = BREAKER.acquire()?;
+    let mut failed = None;
+    let res = request_with_retries(keys, key, path, policy, deadline, &mut failed).await;
+    match failed {
+        Some(true) => permit.failure(),
+        Some(false) => permit.success(),
+        None => drop(permit), // never reached upstream, such as when every key was rejected.
+    }
+    res
+}
+
+/// Does the work of `request`, setting `failed` to whether upstream failed on the last try.
+async fn request_with_retries(keys: &ApiKeys, key: EncodedKey, path: String, policy: &RetryPolicy, deadline: Instant, failed: &mut Option<bool>) -> Result<Response, ProcessError> {
+    let url = format!("{}{path}", config().upstream.base_url());
+    let deadline = deadline.min(Instant::now() + policy.budget);
+    let out_of_budget = |attempt: u32, delay| attempt + 1 >= policy.attempts || Instant::now() + delay >= deadline;
+    let mut attempt = 0;
+    loop {
+        let api_key = keys.pick().ok_or(ProcessError::internal("No api keys are in rotation."))?;
+        let in_flight = api_key.start();
+
+        let now = Instant::now();
+        let res = CLIENT.get(&url)
+            .header("API-Key", api_key.header())
+            .timeout(deadline.saturating_duration_since(now))
+            .send()
+            .await;
+        drop(in_flight);
+        METRICS.upstream(res.as_ref().ok().map(|res| res.status().as_u16()), now.elapsed());
+
+        *failed = Some(res.as_ref().map_or(true, |res| res.status().is_server_error()));
+
+        let delay = match res {
+            Ok(res) => {
+                log(Level::Info, LogMessage::ElapsedUserStatus { key: key.clone(), elapsed: now.elapsed(), message: "Upstream hit", code: res.status().as_u16() });
+                api_key.record(res.headers());
+                access_log::upstream_status(res.status().as_u16());
+
+                if res.status() == StatusCode::FORBIDDEN {
+                    // a rejected key says nothing about whether upstream is healthy.
+                    *failed = None;
+                    api_key.revoke();
+                    continue;
+                }
+                if !res.status().is_client_error() && !res.status().is_server_error() {
+                    return Ok(res)
+                }
+
+                match policy.response_delay(attempt, &res) {
+                    Some(delay) if !out_of_budget(attempt, delay) => delay,
+                    _ => return Err(upstream_error(res).await),
+                }
+            }
+            Err(err) => match policy.error_delay(attempt, &err) {
+                Some(delay) if !out_of_budget(attempt, delay) => delay,
+                _ => return Err(err.into()),
+            },
+        };
+        attempt += 1;
+
+        log(Level::Warn, LogMessage::MessageAndUser { key: key.clone(), message: "Retrying upstream" });
+        sleep(delay).await;
+    }
+}
+
+/// Turns an error response into a `ProcessError`, keeping hypixel's `cause` if it gave one.
+async fn upstream_error(res: Response) -> ProcessError {
+    let status = res.status();
+    match res.bytes().await.ok().as_deref().and_then(cause) {
+        Some(cause) => ProcessError::Hypixel { status, cause },
+        None => ProcessError::Request(status),
+    }
+}
+
+/// Requests `path` like `request`, and reads the body once `validator` accepts it.
+pub async fn request_valid(keys: &ApiKeys, key: EncodedKey, path: String, policy: &RetryPolicy, deadline: Instant, validator: &Validator) -> Result<Bytes, ProcessError> {
+    let body = request(keys, key, path, policy, deadline).await?.bytes().await?;
+    validator.check(&body)?;
+    Ok(body)
 }
 
-pub fn json_response(data: Bytes) -> HttpResponse {
-    HttpResponse::Ok()
-        .append_header(ContentType(mime::APPLICATION_JSON))
-        .body(data)
+/// Responds with a served value, keeping its `Source` in the response's extensions.
+pub fn json_response(served: Served) -> HttpResponse {
+    let mut res = HttpResponse::Ok();
+    res.append_header(ContentType(mime::APPLICATION_JSON));
+    let mut res = match served.body {
+        Body::Full(data) => res.body(data),
+        Body::Streaming(body) => res.streaming(body.stream()),
+    };
+    res.extensions_mut().insert(served.source);
+    res
 }
\ No newline at end of file
@@ -0,0 +1,53 @@
+use std::{hash::BuildHasher, time::Duration};
+
+use rapidhash_lite::RandomHash;
+use reqwest::{Error as ReqwestError, Response, StatusCode, header::RETRY_AFTER};
+
+/// How a `CacheKey` type retries failed upstream requests, see `CacheKey::RETRY`.
+pub struct RetryPolicy {
+    /// total number of tries, including the first.
+    pub attempts: u32,
+    /// delay before the first retry, doubled for each one after it.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// time from the first try after which no more are started, and in flight tries are cut off.
+    pub budget: Duration,
+}
+
+impl RetryPolicy {
+    pub const DEFAULT: Self = Self {
+        attempts: 3,
+        base_delay: Duration::from_millis(200),
+        max_delay: Duration::from_secs(2),
+        budget: Duration::from_secs(10),
+    };
+
+    /// Returns how long to wait before retrying a response, or `None` if it shouldn't be retried.
+    /// 429s wait for as long as `Retry-After` asks, everything else backs off exponentially with jitter.
+    pub fn response_delay(&self, attempt: u32, res: &Response) -> Option<Duration> {
+        match res.status() {
+            StatusCode::TOO_MANY_REQUESTS => Some(retry_after(res).unwrap_or_else(|| self.backoff(attempt))),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT => Some(self.backoff(attempt)),
+            _ => None,
+        }
+    }
+
+    /// Returns how long to wait before retrying a request that got no response, or `None` if it shouldn't be retried.
+    pub fn error_delay(&self, attempt: u32, err: &ReqwestError) -> Option<Duration> {
+        // timeouts are only hit once the budget is spent, so there's no time left to retry them.
+        (err.is_connect() || (err.is_request() && !err.is_timeout())).then(|| self.backoff(attempt))
+    }
+
+    /// "full jitter" backoff, a random delay up to `base_delay * 2^attempt` so retries from many requests spread out.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let cap = self.base_delay.saturating_mul(1 << attempt.min(16)).min(self.max_delay);
+        let random = RandomHash::default().hash_one(attempt) % 1024;
+        #[allow(clippy::cast_precision_loss)]
+        cap.mul_f64(random as f64 / 1024.0)
+    }
+}
+
+fn retry_after(res: &Response) -> Option<Duration> {
+    let secs = res.headers().get(RETRY_AFTER)?.to_str().ok()?.parse().ok()?;
+    Some(Duration::from_secs(secs))
+}
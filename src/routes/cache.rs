@@ -0,0 +1,49 @@
+use std::str::FromStr;
+
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, web::{Data, Path}};
+use simd_json::{OwnedValue, owned::Object};
+use uuid::Uuid;
+
+use crate::{admin, cache::{cache_key::CacheKey, cache_router::CacheRouter}, error::ProcessError, routes::{profile::ProfileKey, secrets::SecretsKey}};
+
+/// Reports the state of every uuid key type for a uuid across the cache layers.
+/// 
+/// A stored value's `written_at` is approximate with ltmdb, see `StoredInfo::written_at`.
+#[get("/cache/{uuid}")]
+async fn inspect_cache(
+    req: HttpRequest,
+    path: Path<String>,
+    cache: Data<CacheRouter>,
+) -> actix_web::Result<impl Responder> {
+    admin::authorize(&req)?;
+    let uuid = Uuid::from_str(&path.into_inner()).map_err(|_| ProcessError::BadRequest("Invalid uuid."))?;
+
+    let mut keys = Object::new();
+    keys.insert(ProfileKey::NAME.into(), cache.inspect(&ProfileKey(uuid)).await?);
+    keys.insert(SecretsKey::NAME.into(), cache.inspect(&SecretsKey(uuid)).await?);
+
+    let mut json = Object::new();
+    json.insert("uuid".into(), OwnedValue::from(uuid.to_string()));
+    json.insert("keys".into(), OwnedValue::from(keys));
+    Ok(HttpResponse::Ok().json(OwnedValue::from(json)))
+}
+
+/// Evicts every uuid key type for a uuid from the memory cache and storage.
+#[delete("/cache/{uuid}")]
+async fn evict_cache(
+    req: HttpRequest,
+    path: Path<String>,
+    cache: Data<CacheRouter>,
+) -> actix_web::Result<impl Responder> {
+    admin::authorize(&req)?;
+    let uuid = Uuid::from_str(&path.into_inner()).map_err(|_| ProcessError::BadRequest("Invalid uuid."))?;
+
+    let mut keys = Object::new();
+    keys.insert(ProfileKey::NAME.into(), OwnedValue::from(cache.evict(&ProfileKey(uuid)).await?));
+    keys.insert(SecretsKey::NAME.into(), OwnedValue::from(cache.evict(&SecretsKey(uuid)).await?));
+
+    let mut json = Object::new();
+    json.insert("uuid".into(), OwnedValue::from(uuid.to_string()));
+    json.insert("removed_from_storage".into(), OwnedValue::from(keys));
+    Ok(HttpResponse::Ok().json(OwnedValue::from(json)))
+}
@@ -0,0 +1,32 @@
+use actix_web::{HttpRequest, HttpResponse, Responder, post, web::Data};
+use simd_json::json;
+
+use crate::{admin, config::{self, Reloaded}, error::ProcessError, logging::{Level, LogMessage, log}, tokens::Tokens};
+
+/// Reads the config file again, see `reload`.
+#[post("/config/reload")]
+async fn reload_config(
+    req: HttpRequest,
+    tokens: Data<Tokens>,
+) -> actix_web::Result<impl Responder> {
+    admin::authorize(&req)?;
+    let Reloaded { applied, restart_required } = reload(&tokens).map_err(|errors| ProcessError::InvalidConfig(errors.join("; ")))?;
+    Ok(HttpResponse::Ok().json(json!({
+        "applied": applied,
+        "restart_required": restart_required,
+    })))
+}
+
+/// Swaps in the config as it is now and rebuilds what was built from the old one, such as rate limiters.
+/// Triggered by `POST /config/reload` and `SIGHUP`.
+///
+/// # Errors
+/// Returns every problem with the new config, the one in use is kept if there are any.
+pub fn reload(tokens: &Tokens) -> Result<Reloaded, Vec<String>> {
+    let reloaded = config::reload().inspect_err(|errors| {
+        log(Level::Error, LogMessage::ConfigRejected { errors: errors.clone() });
+    })?;
+    tokens.reload();
+    log(Level::Info, LogMessage::ConfigReloaded { applied: reloaded.applied.clone(), restart_required: reloaded.restart_required.clone() });
+    Ok(reloaded)
+}
@@ -0,0 +1,82 @@
+use std::{sync::LazyLock, time::{Duration, Instant}};
+
+use actix_web::{HttpResponse, Responder, get, web::Data};
+use simd_json::{OwnedValue, json};
+use tokio::sync::Mutex;
+
+use crate::{breaker::BreakerState, cache::{cache_router::CacheRouter, storage::AnyStorage}, request_utils::BREAKER};
+
+/// When the process started, forced by `main` before storage loads.
+pub static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// How long a storage writability check is reported before it's run again.
+const WRITABLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The last storage writability check and when it ran. Held while a check runs so concurrent probes share it.
+static WRITABLE: Mutex<Option<(Instant, Result<bool, String>)>> = Mutex::const_new(None);
+
+/// Liveness, answered as long as the process is serving requests, including while storage loads.
+#[get("/healthz")]
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(json!({
+        "status": "ok",
+        "uptime_secs": STARTED.elapsed().as_secs(),
+        "checks": {
+            "server": check(true, "serving requests"),
+        },
+    }))
+}
+
+/// Readiness, failing with a 503 while storage is loading or unwritable, ltmdb's expiration task has stopped,
+/// or the upstream circuit breaker is open.
+#[get("/readyz")]
+async fn readyz(
+    cache: Option<Data<CacheRouter>>,
+) -> impl Responder {
+    let breaker = BREAKER.state();
+    let mut checks = vec![("breaker", breaker != BreakerState::Open, breaker.name().to_string())];
+
+    match cache {
+        None => {
+            checks.push(("storage_loaded", false, "replaying partitions".to_string()));
+            checks.push(("storage_writable", false, "not loaded yet".to_string()));
+            checks.push(("expiration_task", false, "not loaded yet".to_string()));
+        }
+        Some(cache) => {
+            let storage = cache.storage();
+            checks.push(("storage_loaded", true, "loaded".to_string()));
+            checks.push(match check_writable(storage).await {
+                Ok(true) => ("storage_writable", true, "writable".to_string()),
+                Ok(false) => ("storage_writable", true, "storage has no directory".to_string()),
+                Err(err) => ("storage_writable", false, err),
+            });
+            checks.push(match storage.expiration_task_alive() {
+                Some(alive) => ("expiration_task", alive, if alive { "running" } else { "stopped" }.to_string()),
+                None => ("expiration_task", true, "storage has no expiration task".to_string()),
+            });
+        }
+    }
+
+    let ready = checks.iter().all(|(_, ok, _)| *ok);
+    let mut breakdown = simd_json::owned::Object::new();
+    for (name, ok, detail) in checks {
+        breakdown.insert(name.into(), check(ok, &detail));
+    }
+    let body = json!({ "status": if ready { "ready" } else { "not_ready" }, "checks": breakdown });
+    if ready { HttpResponse::Ok().json(body) } else { HttpResponse::ServiceUnavailable().json(body) }
+}
+
+/// Checks storage can be written to, reusing the last result for `WRITABLE_CHECK_INTERVAL` since each check writes a file.
+async fn check_writable(storage: &AnyStorage) -> Result<bool, String> {
+    let mut last = WRITABLE.lock().await;
+    if let Some((checked_at, result)) = last.as_ref() && checked_at.elapsed() < WRITABLE_CHECK_INTERVAL {
+        return result.clone()
+    }
+    let result = storage.check_writable().await.map_err(|err| err.to_string());
+    *last = Some((Instant::now(), result.clone()));
+    result
+}
+
+fn check(ok: bool, detail: &str) -> OwnedValue {
+    json!({ "ok": ok, "detail": detail })
+}
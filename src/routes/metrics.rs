@@ -0,0 +1,14 @@
+use actix_web::{HttpResponse, Responder, get, web::Data};
+
+use crate::{api_keys::ApiKeys, cache::cache_router::CacheRouter, metrics::METRICS};
+
+/// Metrics in the prometheus text format.
+#[get("/metrics")]
+async fn prometheus_metrics(
+    keys: Data<ApiKeys>,
+    cache: Data<CacheRouter>,
+) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(METRICS.render(&keys, &cache))
+}
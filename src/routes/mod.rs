@@ -1,4 +1,20 @@
+use serde::Deserialize;
+
 pub mod profile;
 pub mod secrets;
 pub mod dungeon;
-pub mod stats;
\ No newline at end of file
+pub mod stats;
+pub mod cache;
+pub mod peer;
+pub mod tokens;
+pub mod metrics;
+pub mod health;
+pub mod config;
+
+
+/// Query of routes that can skip the cache, such as `/get/<uuid>?fresh=true`.
+#[derive(Deserialize)]
+pub struct FreshQuery {
+    #[serde(default)]
+    pub fresh: bool,
+}
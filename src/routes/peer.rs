@@ -0,0 +1,34 @@
+use std::str::FromStr;
+
+use actix_web::{HttpRequest, Responder, get, web::{Data, Path}};
+use uuid::Uuid;
+
+use crate::{api_keys::ApiKeys, cache::{cache_key::CacheKey, cache_router::CacheRouter}, error::ProcessError, peers, rate_limit::Cost, request_utils::json_response, routes::{profile::{self, ProfileKey}, secrets::SecretsKey}};
+
+/// Only charged to callers without the peer secret, which are then refused. Priced like the dearest key a peer can ask for.
+pub const COST: Cost = profile::COST;
+
+/// Answers a peer asking for a key this instance owns.
+/// 
+/// Always fetches locally, so peers with mismatched `PEERS` lists can't bounce a request between them.
+/// Callers must send `peers.secret`, otherwise anyone could fetch through our api keys.
+#[get("/peer/{flag}/{uuid}")]
+async fn peer(
+    req: HttpRequest,
+    path: Path<(u8, String)>,
+    cache: Data<CacheRouter>,
+    keys: Data<ApiKeys>,
+) -> actix_web::Result<impl Responder> {
+    if !peers::authenticated(req.headers()) {
+        return Err(ProcessError::Forbidden("Invalid peer secret.").into())
+    }
+    let (flag, uuid) = path.into_inner();
+    let uuid = Uuid::from_str(&uuid).map_err(|_| ProcessError::BadRequest("Invalid uuid."))?;
+
+    let data = match flag {
+        ProfileKey::KEYFLAG => cache.get_local(ProfileKey(uuid), &keys).await?,
+        SecretsKey::KEYFLAG => cache.get_local(SecretsKey(uuid), &keys).await?,
+        _ => return Err(ProcessError::BadRequest("Unknown key flag.").into()),
+    };
+    Ok(json_response(data))
+}
@@ -1,60 +1,53 @@
-use std::{str::FromStr, sync::{LazyLock, atomic::Ordering}, time::{Duration, Instant}};
+use std::{str::FromStr, time::Duration};
 
-use actix_web::{Responder, error::ErrorInternalServerError, get, web::{Bytes, Data, Path}};
+use actix_web::{Responder, get, web::{Data, Path, Query}};
+use tokio::time::Instant;
 use uuid::Uuid;
 
-use crate::{cache::{cache_key::CacheKey, cache_router::{CacheRouter, Database}, compression::{compress, decompress}}, env_var, error::ProcessError, logging::{LogMessage, log}, request_utils::{json_response, request}, routes::stats::{RateLimit, stats_from_headers}};
+use crate::{api_keys::ApiKeys, cache::{cache_key::{CacheKey, Fetched, Tiers}, cache_router::CacheRouter}, config::config, error::ProcessError, rate_limit::Cost, request_utils::{json_response, request}, routes::FreshQuery, validation::Validator};
 
-/// Database time to live for profile queries in seconds.
-pub static PROFILE_DB_TTL_SECONDS: LazyLock<Duration> = LazyLock::new(|| Duration::from_secs(env_var("PROFILE_DB_TTL_SECONDS", 3600)));
-/// Cache time to live for profile queries in seconds.
-pub static PROFILE_CACHE_TTL_SECONDS: LazyLock<Duration> = LazyLock::new(|| Duration::from_secs(env_var("PROFILE_CACHE_TTL_SECONDS", 120)));
-
-struct ProfileKey(Uuid);
+pub struct ProfileKey(pub Uuid);
 
 impl CacheKey for ProfileKey {
     const KEYFLAG: u8 = 0;
+    const NAME: &'static str = "profile";
+    const TIERS: Tiers = Tiers { memory: true, storage: true };
+    const VALIDATOR: Validator = Validator::field("profiles");
+    type Id = Uuid;
+
+    fn id(&self) -> &Uuid {
+        &self.0
+    }
 
-    fn uuid(&self) -> Uuid {
-        self.0
+    fn memory_ttl(&self) -> Duration {
+        Duration::from_secs(config().ttl.profile_cache_seconds)
     }
  
-    async fn get_or_insert(&self, db: &Database, stats: &RateLimit) -> Result<(Bytes, Duration), ProcessError> {
-        let uuid_key = self.key();
-        let now = Instant::now();
-        let bytes = db.read(uuid_key).await?;
-        log(LogMessage::TimeElapsed { elapsed: now.elapsed(), name: "DB Read" });
-        
-        if let Some(db_data) = bytes {
-            let decompressed = decompress(&db_data).map_err(|e| ProcessError::Database(e.to_string()))?;
-            
-            log(LogMessage::MessageAndUser { key: uuid_key, message: "DB Hit" });
-            return Ok((decompressed.into(), *PROFILE_CACHE_TTL_SECONDS))
-        }
-
-        let res = request(uuid_key, format!("https://api.hypixel.net/v2/skyblock/profiles?uuid={}", self.uuid())).await?;
-        if let Some((remaining, reset)) = stats_from_headers(res.headers()) {
-            stats.store(remaining, reset, Ordering::Relaxed);
-        }
-        
-        let bytes = res.bytes().await?;
-        let compressed = compress(&bytes);
-        
-        let now = Instant::now();
-        db.insert(uuid_key, compressed, *PROFILE_DB_TTL_SECONDS).await?;
-        log(LogMessage::TimeElapsed { elapsed: now.elapsed(), name: "DB write" });
-        
-        Ok((bytes, *PROFILE_CACHE_TTL_SECONDS))
+    fn storage_ttl(&self) -> Duration {
+        Duration::from_secs(config().ttl.profile_db_seconds)
+    }
+ 
+    async fn fetch(&self, keys: &ApiKeys, deadline: Instant) -> Result<Fetched, ProcessError> {
+        // profiles can be several megabytes, so the body is left to the router to stream.
+        Ok(Fetched::Response(request(keys, self.key(), format!("/v2/skyblock/profiles?uuid={}", self.0), &Self::RETRY, deadline).await?))
     }
 }
 
+/// A profile that isn't cached costs us an upstream request of several megabytes.
+pub const COST: Cost = Cost { base: 1, upstream: 2, fresh: 3 };
+
 #[get("/get/{uuid}")]
 async fn profile(
     path: Path<String>,
+    query: Query<FreshQuery>,
     cache: Data<CacheRouter>,
-    stats: Data<RateLimit>,
+    keys: Data<ApiKeys>,
 ) -> actix_web::Result<impl Responder> {
-    let uuid = Uuid::from_str(&path.into_inner()).map_err(ErrorInternalServerError)?;
-    let data = cache.get(ProfileKey(uuid), &stats).await?;
+    let uuid = Uuid::from_str(&path.into_inner()).map_err(|_| ProcessError::BadRequest("Invalid uuid."))?;
+    let data = if query.fresh {
+        cache.get_fresh(ProfileKey(uuid), &keys).await?
+    } else {
+        cache.get(ProfileKey(uuid), &keys).await?
+    };
     Ok(json_response(data))
 }
@@ -1,45 +1,57 @@
-use std::{str::FromStr, sync::{LazyLock, atomic::Ordering}, time::Duration};
+use std::{str::FromStr, time::Duration};
 
-use actix_web::{Responder, error::ErrorInternalServerError, get, web::{Bytes, BytesMut, Data, Path}};
+use actix_web::{Responder, get, web::{BytesMut, Data, Path, Query}};
 use serde_json::to_vec;
 use simd_json::{BorrowedValue, derived::ValueObjectAccess, to_borrowed_value};
+use tokio::time::Instant;
 use uuid::Uuid;
 
-use crate::{cache::{cache_key::CacheKey, cache_router::{CacheRouter, Database}}, env_var, error::ProcessError, request_utils::{json_response, request}, routes::stats::{RateLimit, stats_from_headers}};
+use crate::{api_keys::ApiKeys, cache::{cache_key::{CacheKey, Fetched, Tiers}, cache_router::CacheRouter}, config::config, error::ProcessError, rate_limit::Cost, request_utils::{json_response, request_valid}, retry::RetryPolicy, routes::FreshQuery, validation::Validator};
 
-/// Cache time to live for secret queries in seconds. Secret queries do not query the database.
-pub static SECRETS_TTL_SECONDS: LazyLock<Duration> = LazyLock::new(|| Duration::from_secs(env_var("SECRETS_TTL_SECONDS", 120)));
-
-struct SecretsKey(Uuid);
+pub struct SecretsKey(pub Uuid);
 
 impl CacheKey for SecretsKey {
     const KEYFLAG: u8 = 1;
+    const NAME: &'static str = "secrets";
+    const TIERS: Tiers = Tiers { memory: true, storage: false };
+    const VALIDATOR: Validator = Validator::field("player");
+    // secrets are cheap to ask for again, so callers shouldn't be kept waiting long on them.
+    const RETRY: RetryPolicy = RetryPolicy { budget: Duration::from_secs(5), ..RetryPolicy::DEFAULT };
+    type Id = Uuid;
+
+    fn id(&self) -> &Uuid {
+        &self.0
+    }
 
-    fn uuid(&self) -> Uuid {
-        self.0
+    fn memory_ttl(&self) -> Duration {
+        Duration::from_secs(config().ttl.secrets_seconds)
     }
     
-    async fn get_or_insert(&self, _: &Database, rate_limit: &RateLimit) -> Result<(Bytes, Duration), ProcessError> {
-        let res = request(self.key(), format!("https://api.hypixel.net/v2/player?uuid={}", self.uuid())).await?;
-        if let Some((remaining, reset)) = stats_from_headers(res.headers()) {
-            rate_limit.store(remaining, reset, Ordering::Relaxed);
-        }
+    async fn fetch(&self, keys: &ApiKeys, deadline: Instant) -> Result<Fetched, ProcessError> {
+        let body = request_valid(keys, self.key(), format!("/v2/player?uuid={}", self.0), &Self::RETRY, deadline, &Self::VALIDATOR).await?;
         
-        let mut bytes = BytesMut::from(res.bytes().await?);
+        let mut bytes = BytesMut::from(body);
         let json = to_borrowed_value(&mut bytes)?;
-        let formatted = &find_secrets(&json).ok_or(ProcessError::internal("Could not find secrets."))?;
-        Ok((to_vec(formatted)?.into(), *SECRETS_TTL_SECONDS))
+        let formatted = &find_secrets(&json).ok_or(ProcessError::NotFound("Could not find secrets."))?;
+        Ok(Fetched::Body(to_vec(formatted)?.into()))
     }
 }
 
+pub const COST: Cost = Cost { base: 1, upstream: 1, fresh: 2 };
+
 #[get("/secrets/{uuid}")]
 async fn secrets(
     path: Path<String>,
+    query: Query<FreshQuery>,
     cache: Data<CacheRouter>,
-    rate_limit: Data<RateLimit>,
+    keys: Data<ApiKeys>,
 ) -> actix_web::Result<impl Responder> {
-    let uuid = Uuid::from_str(&path.into_inner()).map_err(ErrorInternalServerError)?;
-    let data = cache.get(SecretsKey(uuid), &rate_limit).await?;
+    let uuid = Uuid::from_str(&path.into_inner()).map_err(|_| ProcessError::BadRequest("Invalid uuid."))?;
+    let data = if query.fresh {
+        cache.get_fresh(SecretsKey(uuid), &keys).await?
+    } else {
+        cache.get(SecretsKey(uuid), &keys).await?
+    };
 
     Ok(json_response(data))
 }
@@ -1,33 +1,170 @@
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use actix_web::{HttpResponse, Responder, get, web::Data};
 use portable_atomic::AtomicU128;
 use reqwest::header::HeaderMap;
-use simd_json::json;
+use simd_json::{OwnedValue, json};
 
+use crate::{api_keys::ApiKeys, cache::cache_router::CacheRouter, request_utils::BREAKER};
+
+/// Number of rate limit samples kept per api key.
+const RATE_LIMIT_SAMPLES: usize = 64;
+
+/// The budget hypixel reports for an api key, and a history of recent reports.
+///
+/// The latest report is packed into a single atomic u128, laid out as
+/// ```text
+/// | u64 resets_at | u64 remaining |
+/// | 128..64       | 63..0         |
+/// ```
+/// where resets_at is the unix time in seconds the reported window ends.
 pub struct RateLimit {
-    inner: AtomicU128
+    latest: AtomicU128,
+    /// accepted reports, each packed as `| u64 at | u32 remaining | u32 reset |`.
+    samples: [AtomicU128; RATE_LIMIT_SAMPLES],
+    next: AtomicUsize,
+}
+
+/// A budget hypixel reported, `reset` seconds before its window ended.
+#[derive(Clone, Copy)]
+pub struct Sample {
+    pub at: u64,
+    pub remaining: u64,
+    pub reset: u64,
+}
+
+/// Where the budget is heading, from the rate it was used at so far this window.
+pub struct Forecast {
+    pub remaining: u64,
+    /// seconds until the window resets.
+    pub reset: u64,
+    pub used_per_minute: f64,
 }
 
 impl RateLimit {
     pub fn new() -> Self {
         Self {
-            inner: AtomicU128::new(0),
+            latest: AtomicU128::new(0),
+            samples: [const { AtomicU128::new(0) }; RATE_LIMIT_SAMPLES],
+            next: AtomicUsize::new(0),
         }
     }
 
-    pub fn store(&self, remaining: u64, reset: u64, order: Ordering) {
-        let value = u128::from(remaining) << 64 | u128::from(reset);
-        self.inner.store(value, order);
+    /// Records a reported budget, returning `false` if it was ignored.
+    ///
+    /// Within a window the budget only goes down, so a report with more remaining than the latest is a response
+    /// that raced a newer one and is ignored. Reports for a later window are always accepted.
+    pub fn record(&self, remaining: u64, reset: u64, now: u64) -> bool {
+        let resets_at = now + reset;
+        let accepted = self.latest.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+            let (latest_resets_at, latest_remaining) = unpack(value);
+            // reset is reported in whole seconds, so reports a second apart can still be the same window.
+            let same_window = resets_at.abs_diff(latest_resets_at) <= 1 && value != 0;
+            let newer = if same_window { remaining <= latest_remaining } else { resets_at > latest_resets_at };
+            newer.then_some(pack(resets_at.max(latest_resets_at), remaining))
+        }).is_ok();
+
+        if accepted {
+            let index = self.next.fetch_add(1, Ordering::Relaxed) % RATE_LIMIT_SAMPLES;
+            self.samples[index].store(pack_sample(Sample { at: now, remaining, reset }), Ordering::Relaxed);
+        }
+        accepted
     }
 
-    pub fn load(&self, order: Ordering) -> (u64, u64) {
-        let value = self.inner.load(order);
-        let remaining = (value >> 64) as u64;
-        #[allow(clippy::cast_possible_truncation)]
-        let reset = value as u64;
-        (remaining, reset)
+    /// The latest budget and seconds until it resets, `None` if it's unknown or has already reset.
+    pub fn current(&self, now: u64) -> Option<(u64, u64)> {
+        let (resets_at, remaining) = unpack(self.latest.load(Ordering::Relaxed));
+        (resets_at > now).then(|| (remaining, resets_at - now))
     }
+
+    /// Accepted reports, oldest first.
+    pub fn history(&self) -> Vec<Sample> {
+        let mut samples: Vec<_> = self.samples.iter()
+            .map(|sample| unpack_sample(sample.load(Ordering::Relaxed)))
+            .filter(|sample| sample.at != 0)
+            .collect();
+        samples.sort_by_key(|sample| (sample.at, u64::MAX - sample.remaining));
+        samples
+    }
+
+    /// Forecasts the current window from the reports made during it, `None` if the budget is unknown.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn forecast(&self, now: u64) -> Option<Forecast> {
+        let (remaining, reset) = self.current(now)?;
+        let resets_at = now + reset;
+        let first = self.history().into_iter().find(|sample| (sample.at + sample.reset).abs_diff(resets_at) <= 1);
+
+        let used_per_minute = match first {
+            Some(first) if now > first.at => first.remaining.saturating_sub(remaining) as f64 * 60.0 / (now - first.at) as f64,
+            _ => 0.0,
+        };
+        Some(Forecast { remaining, reset, used_per_minute })
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+impl Forecast {
+    /// Combines the forecasts of several keys, which reset when the soonest of them does.
+    pub fn sum(forecasts: impl IntoIterator<Item = Self>) -> Option<Self> {
+        forecasts.into_iter().reduce(|total, forecast| Self {
+            remaining: total.remaining + forecast.remaining,
+            reset: total.reset.min(forecast.reset),
+            used_per_minute: total.used_per_minute + forecast.used_per_minute,
+        })
+    }
+
+    /// Budget expected to be used before the window resets.
+    pub fn projected_use(&self) -> f64 {
+        let minutes = self.reset as f64 / 60.0;
+        self.used_per_minute * minutes
+    }
+
+    pub fn exhausts(&self) -> bool {
+        let remaining = self.remaining as f64;
+        self.projected_use() >= remaining && self.used_per_minute > 0.0
+    }
+
+    pub fn to_json(&self) -> OwnedValue {
+        let exhausted_in = (self.used_per_minute > 0.0).then(|| self.remaining as f64 / self.used_per_minute * 60.0);
+        json!({
+            "remaining": self.remaining,
+            "reset": self.reset,
+            "used_per_minute": self.used_per_minute,
+            "projected_use": self.projected_use(),
+            "exhausts_before_reset": self.exhausts(),
+            "exhausted_in_secs": exhausted_in,
+        })
+    }
+}
+
+impl Sample {
+    pub fn to_json(self) -> OwnedValue {
+        json!({ "at": self.at, "remaining": self.remaining, "reset": self.reset })
+    }
+}
+
+#[inline]
+fn pack(resets_at: u64, remaining: u64) -> u128 {
+    u128::from(resets_at) << 64 | u128::from(remaining)
+}
+
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+fn unpack(value: u128) -> (u64, u64) {
+    ((value >> 64) as u64, value as u64)
+}
+
+#[inline]
+fn pack_sample(sample: Sample) -> u128 {
+    let remaining = u32::try_from(sample.remaining).unwrap_or(u32::MAX);
+    let reset = u32::try_from(sample.reset).unwrap_or(u32::MAX);
+    u128::from(sample.at) << 64 | u128::from(remaining) << 32 | u128::from(reset)
+}
+
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+fn unpack_sample(value: u128) -> Sample {
+    Sample { at: (value >> 64) as u64, remaining: u64::from((value >> 32) as u32), reset: u64::from(value as u32) }
 }
 
 pub fn stats_from_headers(headers: &HeaderMap) -> Option<(u64, u64)> {
@@ -38,12 +175,18 @@ pub fn stats_from_headers(headers: &HeaderMap) -> Option<(u64, u64)> {
 
 #[get("/stats")]
 async fn statistics(
-    rate_limit: Data<RateLimit>,
+    keys: Data<ApiKeys>,
+    cache: Data<CacheRouter>,
 ) -> actix_web::Result<impl Responder> {
-    let (remaining, reset) = rate_limit.load(Ordering::Relaxed);
+    let (remaining, reset) = keys.total();
     let json = json!({
         "RateLimit-Remaining": remaining,
-        "RateLimit-Reset": reset
+        "RateLimit-Reset": reset,
+        "upstream_calls": keys.calls(),
+        "forecast": keys.forecast().map(|forecast| forecast.to_json()),
+        "keys": keys.to_json(),
+        "breaker": BREAKER.to_json(),
+        "cache": cache.stats().to_json(),
     });
     Ok(HttpResponse::Ok().json(json))
 }
@@ -0,0 +1,63 @@
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, post, web::{Data, Json}};
+use serde::Deserialize;
+use simd_json::json;
+
+use crate::{admin, cache::cache_router::CacheRouter, error::ProcessError, tokens::{Tier, Token, Tokens}};
+
+/// Body of a token issue request. Quota overrides default to the tier's.
+#[derive(Deserialize)]
+struct IssueToken {
+    name: String,
+    tier: Tier,
+    #[serde(default)]
+    routes: Vec<String>,
+    burst: Option<u32>,
+    refresh_millis: Option<u64>,
+    ttl_days: Option<u64>,
+}
+
+/// Issues a new api token, returning its secret. The secret can't be recovered afterwards.
+#[post("/tokens")]
+async fn issue_token(
+    req: HttpRequest,
+    body: Json<IssueToken>,
+    tokens: Data<Tokens>,
+    cache: Data<CacheRouter>,
+) -> actix_web::Result<impl Responder> {
+    admin::authorize(&req)?;
+    let IssueToken { name, tier, routes, burst, refresh_millis, ttl_days } = body.into_inner();
+    if tier == Tier::Anonymous {
+        return Err(ProcessError::BadRequest("Tokens can't be issued for the anonymous tier.").into())
+    }
+
+    let token = Token { burst, refresh_millis, ..Token::new(name, tier, routes, ttl_days) };
+    let secret = tokens.issue(cache.storage(), token.clone()).await?;
+
+    let json = json!({
+        "token": secret,
+        "name": token.name,
+        "tier": tier.name(),
+        "routes": token.routes,
+        "expires_at": token.expires_at,
+    });
+    Ok(HttpResponse::Created().json(json))
+}
+
+/// Body of a token revoke request.
+#[derive(Deserialize)]
+struct RevokeToken {
+    token: String,
+}
+
+/// Revokes an api token. The secret is taken from the body so it stays out of urls and access logs.
+#[delete("/tokens")]
+async fn revoke_token(
+    req: HttpRequest,
+    body: Json<RevokeToken>,
+    tokens: Data<Tokens>,
+    cache: Data<CacheRouter>,
+) -> actix_web::Result<impl Responder> {
+    admin::authorize(&req)?;
+    let revoked = tokens.revoke(cache.storage(), &body.token).await?;
+    Ok(HttpResponse::Ok().json(json!({ "revoked": revoked })))
+}
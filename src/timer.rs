@@ -3,14 +3,22 @@ use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::middleware::Next;
 use std::time::Instant;
 
-use crate::logging::{LogMessage, log};
+use crate::logging::{Level, LogMessage, log};
+use crate::metrics::METRICS;
+
 pub async fn timer(
     req: ServiceRequest,
     next: Next<impl MessageBody>,
 ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
     let now = Instant::now();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
     let res = next.call(req).await;
     let elapsed = now.elapsed();
-    log(LogMessage::TimeElapsed { elapsed, name: "the last request" });
+    log(Level::Debug, LogMessage::TimeElapsed { elapsed, name: "the last request" });
+    let status = match &res {
+        Ok(res) => res.status(),
+        Err(err) => err.as_response_error().status_code(),
+    };
+    METRICS.request(&route, status.as_u16(), elapsed);
     res
 }
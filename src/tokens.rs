@@ -0,0 +1,258 @@
+use std::{collections::HashMap, net::IpAddr, num::NonZeroU32, sync::{Arc, PoisonError, RwLock}, time::Duration};
+
+use actix_governor::governor::{Quota, RateLimiter, clock::{Clock, DefaultClock}, middleware::StateInformationMiddleware, state::{InMemoryState, NotKeyed, keyed::DefaultKeyedStateStore}};
+use rapidhash_lite::RandomHash;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{cache::{BytesKey, EncodedKey, storage::Storage, unix_secs}, config::config, error::ProcessError};
+
+/// Flag tokens are namespaced by in storage, kept clear of the `CacheKey` flags.
+const TOKEN_FLAG: u8 = u8::MAX;
+/// Tokens are stored for their lifetime rounded up to a multiple of this, so ltmdb keeps a few buckets for them rather than one per token.
+/// `find` checks `expires_at`, so a token kept past it isn't accepted.
+const STORAGE_TTL_UNIT: u64 = 30 * 24 * 60 * 60;
+
+type DirectLimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock, StateInformationMiddleware>;
+type KeyedLimiter = RateLimiter<IpAddr, DefaultKeyedStateStore<IpAddr>, DefaultClock, StateInformationMiddleware>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    /// requests without a token, limited per ip address.
+    Anonymous,
+    Standard,
+    Premium,
+}
+
+impl Tier {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Anonymous => "anonymous",
+            Self::Standard => "standard",
+            Self::Premium => "premium",
+        }
+    }
+
+    fn quota(self) -> Quota {
+        let limits = &config().ratelimit;
+        match self {
+            Self::Anonymous => quota(Duration::from_secs(limits.refresh_seconds), limits.burst),
+            Self::Standard => quota(Duration::from_millis(limits.standard_refresh_millis), limits.standard_burst),
+            Self::Premium => quota(Duration::from_millis(limits.premium_refresh_millis), limits.premium_burst),
+        }
+    }
+}
+
+/// An issued api token, as it's kept in storage.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Token {
+    /// who the token was issued to, only used to tell tokens apart.
+    pub name: String,
+    pub tier: Tier,
+    /// first path segments the token may request, such as `get` or `secrets`. Every route is allowed if empty.
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// overrides the tier's burst size.
+    #[serde(default)]
+    pub burst: Option<u32>,
+    /// overrides the tier's milliseconds between requests.
+    #[serde(default)]
+    pub refresh_millis: Option<u64>,
+    /// unix time in seconds the token stops being accepted.
+    pub expires_at: u64,
+}
+
+impl Token {
+    /// A token expiring after `ttl_days`, or `ratelimit.token_ttl_days` if not given.
+    pub fn new(name: String, tier: Tier, routes: Vec<String>, ttl_days: Option<u64>) -> Self {
+        let expires_at = unix_secs() + ttl_days.unwrap_or(config().ratelimit.token_ttl_days) * 24 * 60 * 60;
+        Self { name, tier, routes, burst: None, refresh_millis: None, expires_at }
+    }
+
+    pub fn allows(&self, route: &str) -> bool {
+        self.routes.is_empty() || self.routes.iter().any(|allowed| allowed == route)
+    }
+
+    fn quota(&self) -> Quota {
+        let tier = self.tier.quota();
+        let period = self.refresh_millis.map_or(tier.replenish_interval(), Duration::from_millis);
+        quota(period, self.burst.unwrap_or(tier.burst_size().get()))
+    }
+}
+
+/// A token in use, with its own quota.
+pub struct ActiveToken {
+    pub token: Token,
+    limiter: DirectLimiter,
+    quota: Quota,
+}
+
+impl ActiveToken {
+    fn new(token: Token) -> Self {
+        let quota = token.quota();
+        Self { limiter: RateLimiter::direct(quota).with_middleware(), quota, token }
+    }
+}
+
+/// Who a request is limited as.
+pub enum Client {
+    Token(Arc<ActiveToken>),
+    Anonymous(IpAddr),
+}
+
+impl Client {
+    pub fn tier(&self) -> Tier {
+        match self {
+            Self::Token(active) => active.token.tier,
+            Self::Anonymous(_) => Tier::Anonymous,
+        }
+    }
+}
+
+/// Quota left after a request was let through.
+pub struct Usage {
+    pub limit: u32,
+    pub remaining: u32,
+}
+
+/// A request over its client's quota.
+pub struct Throttled {
+    pub limit: u32,
+    /// time until the client can afford the request, `None` if it costs more than their whole burst.
+    pub wait: Option<Duration>,
+}
+
+/// Issued tokens, read from storage the first time they're used, and the per ip limiter for anonymous clients.
+pub struct Tokens {
+    active: RwLock<HashMap<String, Arc<ActiveToken>, RandomHash>>,
+    /// the limiter along with the quota it was built with, replaced if the quota is reloaded.
+    anonymous: RwLock<(Quota, KeyedLimiter)>,
+}
+
+impl Tokens {
+    pub fn new() -> Self {
+        let quota = Tier::Anonymous.quota();
+        Self {
+            active: RwLock::new(HashMap::default()),
+            anonymous: RwLock::new((quota, RateLimiter::keyed(quota).with_middleware())),
+        }
+    }
+
+    /// Rebuilds the limiters whose quota changed with the config, which starts their clients over with a full burst.
+    /// Limiters that kept their quota keep their state.
+    pub fn reload(&self) {
+        let quota = Tier::Anonymous.quota();
+        let mut anonymous = self.anonymous.write().unwrap_or_else(PoisonError::into_inner);
+        if anonymous.0 != quota {
+            *anonymous = (quota, RateLimiter::keyed(quota).with_middleware());
+        }
+        for active in self.active.write().unwrap_or_else(PoisonError::into_inner).values_mut() {
+            if active.token.quota() != active.quota {
+                *active = Arc::new(ActiveToken::new(active.token.clone()));
+            }
+        }
+    }
+
+    /// Whether a token is already in memory, so `find` won't read storage for it.
+    pub fn is_loaded(&self, secret: &str) -> bool {
+        self.active.read().unwrap_or_else(PoisonError::into_inner).contains_key(secret)
+    }
+
+    /// Looks up a token by its secret.
+    ///
+    /// # Errors
+    /// Returns an error if storage fails or holds an unreadable token.
+    pub async fn find(&self, storage: &impl Storage, secret: &str) -> Result<Option<Arc<ActiveToken>>, ProcessError> {
+        let now = unix_secs();
+        let cached = self.active.read().map_err(|_| ProcessError::internal("Tokens were poisoned."))?.get(secret).cloned();
+        if let Some(active) = cached {
+            if active.token.expires_at > now {
+                return Ok(Some(active))
+            }
+            self.forget(secret)?;
+            return Ok(None)
+        }
+
+        let Some(stored) = storage.read(key(secret)).await? else { return Ok(None) };
+        let token: Token = simd_json::serde::from_slice(&mut stored.to_vec())?;
+        if token.expires_at <= now {
+            return Ok(None)
+        }
+        Ok(Some(self.activate(secret, token)?))
+    }
+
+    /// Stores a new token, returning its secret.
+    ///
+    /// # Errors
+    /// Returns an error if storage fails.
+    pub async fn issue(&self, storage: &impl Storage, token: Token) -> Result<String, ProcessError> {
+        let secret = Uuid::new_v4().simple().to_string();
+        let ttl = Duration::from_secs(token.expires_at.saturating_sub(unix_secs()).max(1).next_multiple_of(STORAGE_TTL_UNIT));
+        storage.insert(key(&secret), simd_json::to_vec(&token)?.into(), ttl).await?;
+        self.activate(&secret, token)?;
+        Ok(secret)
+    }
+
+    /// Stops accepting a token, returning `true` if it existed.
+    ///
+    /// # Errors
+    /// Returns an error if storage fails.
+    pub async fn revoke(&self, storage: &impl Storage, secret: &str) -> Result<bool, ProcessError> {
+        let active = self.forget(secret)?;
+        Ok(storage.delete(key(secret)).await? || active)
+    }
+
+    /// Takes `cost` requests from the client's quota, all or nothing.
+    ///
+    /// # Errors
+    /// Returns `Throttled` if the client can't afford the cost.
+    pub fn check(&self, client: &Client, cost: u32) -> Result<Usage, Throttled> {
+        let cost = NonZeroU32::new(cost).unwrap_or(NonZeroU32::MIN);
+        let result = match client {
+            Client::Token(active) => active.limiter.check_n(cost),
+            Client::Anonymous(ip) => self.anonymous.read().unwrap_or_else(PoisonError::into_inner).1.check_key_n(ip, cost),
+        };
+        let limit = self.limit(client);
+        match result {
+            Ok(Ok(snapshot)) => Ok(Usage { limit, remaining: snapshot.remaining_burst_capacity() }),
+            Ok(Err(not_until)) => Err(Throttled { limit, wait: Some(not_until.wait_time_from(DefaultClock::default().now())) }),
+            Err(_) => Err(Throttled { limit, wait: None }),
+        }
+    }
+
+    /// Takes up to `cost` requests from the client's quota for work found to be more expensive after it was let through.
+    /// Clients that can't afford all of it are left with nothing, rather than failing a request that's already done.
+    pub fn charge(&self, client: &Client, cost: u32) -> Option<Usage> {
+        (1..=cost).rev().find_map(|cost| self.check(client, cost).ok())
+    }
+
+    fn limit(&self, client: &Client) -> u32 {
+        match client {
+            Client::Token(active) => active.quota.burst_size().get(),
+            Client::Anonymous(_) => self.anonymous.read().unwrap_or_else(PoisonError::into_inner).0.burst_size().get(),
+        }
+    }
+
+    fn activate(&self, secret: &str, token: Token) -> Result<Arc<ActiveToken>, ProcessError> {
+        let mut active = self.active.write().map_err(|_| ProcessError::internal("Tokens were poisoned."))?;
+        // another request may have loaded it first, keep its limiter so the quota isn't reset.
+        let token = active.entry(secret.to_string())
+            .or_insert_with(|| Arc::new(ActiveToken::new(token)));
+        Ok(token.clone())
+    }
+
+    fn forget(&self, secret: &str) -> Result<bool, ProcessError> {
+        let mut active = self.active.write().map_err(|_| ProcessError::internal("Tokens were poisoned."))?;
+        Ok(active.remove(secret).is_some())
+    }
+}
+
+fn key(secret: &str) -> EncodedKey {
+    BytesKey::encode(secret.as_bytes(), TOKEN_FLAG).into()
+}
+
+fn quota(period: Duration, burst: u32) -> Quota {
+    let burst = NonZeroU32::new(burst).unwrap_or(NonZeroU32::MIN);
+    Quota::with_period(period).unwrap_or_else(|| Quota::per_second(burst)).allow_burst(burst)
+}
@@ -0,0 +1,48 @@
+use reqwest::StatusCode;
+use simd_json::{BorrowedValue, derived::{ValueObjectAccess, ValueTryAsScalar}, to_borrowed_value};
+
+use crate::error::ProcessError;
+
+/// Checks an upstream payload is one hypixel meant to send before it's cached, see `CacheKey::VALIDATOR`.
+pub struct Validator {
+    /// top level field the payload must contain, its value may still be `null`.
+    pub field: &'static str,
+}
+
+impl Validator {
+    pub const fn field(field: &'static str) -> Self {
+        Self { field }
+    }
+
+    /// # Errors
+    /// Returns `ProcessError::Hypixel` with hypixel's `cause` if the payload doesn't parse,
+    /// `success` isn't true, or `field` is missing.
+    pub fn check(&self, body: &[u8]) -> Result<(), ProcessError> {
+        let mut body = body.to_vec();
+        let json = to_borrowed_value(&mut body).map_err(|e| invalid(format!("Malformed response: {e}")))?;
+
+        if json.get("success").and_then(|success| success.try_as_bool().ok()) != Some(true) {
+            return Err(invalid(cause_of(&json).unwrap_or("Response was not successful").to_string()))
+        }
+        if json.get(self.field).is_none() {
+            return Err(invalid(format!("Response is missing {}", self.field)))
+        }
+        Ok(())
+    }
+}
+
+/// Hypixel's explanation of why a request failed, if the body has one.
+pub fn cause(body: &[u8]) -> Option<String> {
+    let mut body = body.to_vec();
+    let json = to_borrowed_value(&mut body).ok()?;
+    cause_of(&json).map(str::to_string)
+}
+
+fn cause_of<'a>(json: &'a BorrowedValue<'a>) -> Option<&'a str> {
+    json.get("cause").and_then(|cause| cause.try_as_str().ok())
+}
+
+/// upstream answered, but not with anything we can use, so we report it as a bad gateway.
+fn invalid(cause: String) -> ProcessError {
+    ProcessError::Hypixel { status: StatusCode::BAD_GATEWAY, cause }
+}
@@ -0,0 +1,63 @@
+//! The combined format access log, with the cache outcome of each request.
+
+mod common;
+
+use std::{env, fs, process};
+
+use reqwest::{Client, StatusCode};
+use tokio::time::{Duration, Instant, sleep};
+
+use common::{mock, proxy};
+
+const PLAYER: &str = "9d4b2f6a-3c5e-4f7a-b1d3-5e7f9a1b3c5d";
+
+#[tokio::test]
+async fn requests_are_logged_with_their_cache_outcome() {
+    let mock = mock().await;
+    let dir = env::temp_dir().join(format!("hypixel_api_access_log_{}", process::id()));
+    let file = dir.join("access.log");
+    let proxy = proxy(&mock, &[("ACCESS_LOG", file.to_str().unwrap())]).await;
+
+    let client = Client::new();
+    let get = |path: String| client.get(format!("{}{path}", proxy.url))
+        .header("User-Agent", "access-log-test")
+        .header("X-Forwarded-For", "203.0.113.7")
+        .send();
+
+    for _ in 0..2 {
+        let res = get(format!("/get/{PLAYER}")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        res.bytes().await.unwrap();
+    }
+    let res = get("/get/not-a-uuid".to_string()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let bad_request_len = res.bytes().await.unwrap().len();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let lines = loop {
+        let logs = fs::read_to_string(&file).unwrap_or_default();
+        let lines: Vec<String> = logs.lines().filter(|line| line.contains("access-log-test")).map(str::to_string).collect();
+        if lines.len() == 3 || Instant::now() > deadline {
+            break lines
+        }
+        sleep(Duration::from_millis(50)).await;
+    };
+    assert_eq!(lines.len(), 3, "{lines:?}");
+
+    // the client is the forwarded ip, since the proxy's peer is a trusted loopback address.
+    assert!(lines[0].starts_with("203.0.113.7 - - ["), "{}", lines[0]);
+    assert!(lines[0].contains(&format!("\"GET /get/{PLAYER} HTTP/1.1\" 200 ")), "{}", lines[0]);
+    assert!(lines[0].contains("\"-\" \"access-log-test\""), "{}", lines[0]);
+    assert!(lines[0].contains("tier=upstream flight=leader upstream=200 request_id="), "{}", lines[0]);
+
+    assert!(lines[1].contains("tier=memory flight=- upstream=- "), "{}", lines[1]);
+    let first_len = lines[0].split(' ').nth(9).unwrap();
+    assert_eq!(lines[1].split(' ').nth(9).unwrap(), first_len);
+    assert!(first_len.parse::<u64>().unwrap() > 0);
+
+    assert!(lines[2].contains(&format!("\"GET /get/not-a-uuid HTTP/1.1\" 400 {bad_request_len} ")), "{}", lines[2]);
+    assert!(lines[2].contains("tier=- flight=- upstream=- "), "{}", lines[2]);
+
+    drop(proxy);
+    let _ = fs::remove_dir_all(dir);
+}
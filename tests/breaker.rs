@@ -0,0 +1,135 @@
+//! Circuit breaker behavior while the mock hypixel server is failing.
+
+mod common;
+
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde_json::Value;
+use tokio::time::sleep;
+
+use common::{FORBIDDEN, SERVER_ERROR, SLOW, UNAVAILABLE, mock, proxy, upstream_hits};
+
+const PLAYER: &str = "0c3a8f51-2b7e-4d0f-9a61-5e2f8c7d4b19";
+const STORED: &str = "7d1e4c2a-9b3f-4e8a-b5c6-1f0a2d3e4b5c";
+
+async fn breaker(url: &str) -> Value {
+    let stats: Value = reqwest::get(format!("{url}/stats")).await.unwrap().json().await.unwrap();
+    stats["breaker"].clone()
+}
+
+#[tokio::test]
+async fn open_circuit_fails_fast_then_recovers() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("BREAKER_FAILURES", "2"), ("BREAKER_OPEN_SECONDS", "1"), ("PROFILE_CACHE_TTL_SECONDS", "0")]).await;
+
+    // stored before the circuit opens, so it can still be answered from storage while it's open.
+    let res = reqwest::get(format!("{}/get/{STORED}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    for _ in 0..2 {
+        let res = reqwest::get(format!("{}/get/{SERVER_ERROR}", proxy.url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    assert_eq!(breaker(&proxy.url).await["state"], "open");
+
+    let res = reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(res.text().await.unwrap().contains("try again later"));
+    assert_eq!(upstream_hits(&mock, "profiles", PLAYER).await, 0);
+
+    let res = reqwest::get(format!("{}/get/{STORED}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(upstream_hits(&mock, "profiles", STORED).await, 1);
+    let stats: Value = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap().json().await.unwrap();
+    assert_eq!(stats["cache"]["profile"]["db_hits"]["total"], 1);
+
+    // once the circuit has been open long enough a probe is let through, and its success closes the circuit.
+    sleep(Duration::from_millis(2100)).await;
+    let res = reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let breaker = breaker(&proxy.url).await;
+    assert_eq!(breaker["state"], "closed");
+    assert_eq!(breaker["opened"]["total"], 1);
+    assert_eq!(breaker["half_opened"]["total"], 1);
+    assert_eq!(breaker["closed"]["total"], 1);
+    assert!(breaker["rejected"]["total"].as_u64().unwrap() >= 1);
+}
+
+#[tokio::test]
+async fn open_circuit_answers_expired_values_from_storage() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("BREAKER_FAILURES", "1"), ("BREAKER_OPEN_SECONDS", "60"), ("PROFILE_CACHE_TTL_SECONDS", "0"), ("PROFILE_DB_TTL_SECONDS", "1")]).await;
+
+    let fetched = reqwest::get(format!("{}/get/{STORED}", proxy.url)).await.unwrap().bytes().await.unwrap();
+    sleep(Duration::from_millis(2100)).await;
+
+    let res = reqwest::get(format!("{}/get/{SERVER_ERROR}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(breaker(&proxy.url).await["state"], "open");
+
+    // past its ttl, but answered anyway rather than failing while hypixel is down.
+    let res = reqwest::get(format!("{}/get/{STORED}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.bytes().await.unwrap(), fetched);
+    assert_eq!(upstream_hits(&mock, "profiles", STORED).await, 1);
+    let stats: Value = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap().json().await.unwrap();
+    assert_eq!(stats["cache"]["profile"]["db_hits"]["total"], 1);
+}
+
+#[tokio::test]
+async fn retries_count_as_one_failure() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("BREAKER_FAILURES", "2")]).await;
+
+    let res = reqwest::get(format!("{}/get/{UNAVAILABLE}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(upstream_hits(&mock, "profiles", UNAVAILABLE).await, 3);
+
+    let breaker = breaker(&proxy.url).await;
+    assert_eq!(breaker["state"], "closed");
+    assert_eq!(breaker["consecutive_failures"], 1);
+}
+
+#[tokio::test]
+async fn rejected_keys_are_neither_a_success_nor_a_failure() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("API_KEYS", "first,second"), ("BREAKER_FAILURES", "3")]).await;
+
+    let res = reqwest::get(format!("{}/get/{SERVER_ERROR}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(breaker(&proxy.url).await["consecutive_failures"], 1);
+
+    // every key is rejected in turn, leaving none to try.
+    let res = reqwest::get(format!("{}/get/{FORBIDDEN}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(res.text().await.unwrap().contains("No api keys are in rotation"));
+    assert_eq!(upstream_hits(&mock, "profiles", FORBIDDEN).await, 2);
+
+    let breaker = breaker(&proxy.url).await;
+    assert_eq!(breaker["state"], "closed");
+    assert_eq!(breaker["consecutive_failures"], 1);
+}
+
+#[tokio::test]
+async fn only_probes_close_a_half_open_circuit() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("BREAKER_FAILURES", "1"), ("BREAKER_OPEN_SECONDS", "0")]).await;
+    let get = |uuid: &'static str| tokio::spawn(reqwest::get(format!("{}/get/{uuid}", proxy.url)));
+
+    // let through while closed, and still in flight once the circuit opens and half opens.
+    let closed = get(SLOW);
+    sleep(Duration::from_millis(200)).await;
+    let res = reqwest::get(format!("{}/get/{SERVER_ERROR}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let probe = get("0000de1a-0000-0000-0000-000000000001");
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(breaker(&proxy.url).await["state"], "half_open");
+
+    assert_eq!(closed.await.unwrap().unwrap().status(), StatusCode::OK);
+    assert_eq!(breaker(&proxy.url).await["state"], "half_open");
+
+    assert_eq!(probe.await.unwrap().unwrap().status(), StatusCode::OK);
+    assert_eq!(breaker(&proxy.url).await["state"], "closed");
+}
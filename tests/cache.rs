@@ -0,0 +1,55 @@
+//! The admin `/cache/{uuid}` endpoint reporting where a key is cached.
+
+mod common;
+
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use tokio::time::sleep;
+
+use common::{Server, mock, proxy};
+
+const PLAYER: &str = "9a4d2b7e-1c3f-4e8a-b6d5-0f2e1a3c5b7d";
+const UNSEEN: &str = "2f6c8e0a-4b1d-4c3e-a5f7-9d8b6a4c2e0f";
+
+async fn inspect(proxy: &Server, uuid: &str) -> Value {
+    let res = Client::new().get(format!("{}/cache/{uuid}", proxy.url)).header("Admin-Key", "admin").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    res.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn reports_hits_misses_and_expired_keys() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("ADMIN_KEY", "admin"), ("PROFILE_CACHE_TTL_SECONDS", "1"), ("PROFILE_DB_TTL_SECONDS", "1")]).await;
+
+    let res = Client::new().get(format!("{}/cache/{PLAYER}", proxy.url)).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let body = reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap().bytes().await.unwrap();
+    let hit = inspect(&proxy, PLAYER).await;
+    let profile = &hit["keys"]["profile"];
+    assert_eq!(hit["uuid"], PLAYER);
+    assert_eq!(profile["memory"]["size"], body.len());
+    assert_eq!(profile["storage"]["uncompressed_size"], body.len());
+    assert!(profile["storage"]["compressed_size"].as_u64().unwrap() > 0);
+    let written_at = profile["storage"]["written_at"].as_u64().unwrap();
+    assert_eq!(profile["storage"]["fresh_until"].as_u64().unwrap(), written_at + 1);
+    assert_eq!(profile["storage"]["stale"], false);
+    // kept past its ttl, in case it needs to be answered while the circuit is open.
+    assert_eq!(profile["storage"]["expires_at"].as_u64().unwrap(), written_at + 1 + 86400);
+    assert_eq!(profile["in_flight"], false);
+    // secrets aren't stored, and weren't requested.
+    assert_eq!(hit["keys"]["secrets"]["tiers"]["storage"], false);
+    assert_eq!(hit["keys"]["secrets"]["memory"], Value::Null);
+
+    let miss = inspect(&proxy, UNSEEN).await;
+    assert_eq!(miss["keys"]["profile"]["memory"], Value::Null);
+    assert_eq!(miss["keys"]["profile"]["storage"], Value::Null);
+
+    sleep(Duration::from_millis(2100)).await;
+    let expired = inspect(&proxy, PLAYER).await;
+    assert_eq!(expired["keys"]["profile"]["memory"], Value::Null);
+    assert_eq!(expired["keys"]["profile"]["storage"]["stale"], true);
+}
@@ -0,0 +1,45 @@
+//! Which client a request is rate limited as when it comes through a proxy.
+
+mod common;
+
+use reqwest::{Client, StatusCode};
+
+use common::{Server, mock, proxy};
+
+/// Remaining requests of whoever `forwarded_for` makes the request look like it came from.
+async fn remaining(proxy: &Server, forwarded_for: &str) -> u64 {
+    let res = Client::new().get(format!("{}/stats", proxy.url)).header("X-Forwarded-For", forwarded_for).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    res.headers()["x-ratelimit-remaining"].to_str().unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn forwarded_headers_from_untrusted_peers_are_ignored() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("TRUSTED_PROXIES", ""), ("RATELIMIT_BURST", "10"), ("RATELIMIT_REFRESH", "60")]).await;
+
+    // every request is limited as the tcp peer, whatever it claims to forward for.
+    let first = remaining(&proxy, "198.51.100.1").await;
+    assert_eq!(remaining(&proxy, "198.51.100.2").await, first - 1);
+}
+
+#[tokio::test]
+async fn the_first_untrusted_hop_is_the_client() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("TRUSTED_PROXIES", "127.0.0.1,10.0.0.0/8"), ("RATELIMIT_BURST", "10"), ("RATELIMIT_REFRESH", "60")]).await;
+
+    assert_eq!(remaining(&proxy, "198.51.100.1").await, 9);
+    // hops before the client are whatever it sent, only the trusted hops after it are walked past.
+    assert_eq!(remaining(&proxy, "203.0.113.7, 198.51.100.1, 10.1.2.3").await, 8);
+    assert_eq!(remaining(&proxy, "198.51.100.2").await, 9);
+}
+
+#[tokio::test]
+async fn ipv6_clients_are_grouped_by_prefix() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("IPV6_PREFIX", "64"), ("RATELIMIT_BURST", "10"), ("RATELIMIT_REFRESH", "60")]).await;
+
+    assert_eq!(remaining(&proxy, "2001:db8:0:1::1").await, 9);
+    assert_eq!(remaining(&proxy, "2001:db8:0:1::2").await, 8);
+    assert_eq!(remaining(&proxy, "2001:db8:0:2::1").await, 9);
+}
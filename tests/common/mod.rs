@@ -0,0 +1,109 @@
+//! Spawns the proxy and the mock hypixel server as child processes for integration tests.
+
+#![allow(dead_code)] // not every test file uses every helper.
+
+use std::{net::TcpListener, process::{Child, Command, Stdio}, time::Duration};
+
+use tokio::time::{Instant, sleep};
+
+/// uuids the mock responds to with a specific behavior, see `src/bin/mock_hypixel.rs`.
+pub const FORBIDDEN: &str = "00000403-0000-0000-0000-000000000000";
+pub const THROTTLED: &str = "00000429-0000-0000-0000-000000000000";
+pub const SERVER_ERROR: &str = "00000500-0000-0000-0000-000000000000";
+pub const FLAKY: &str = "00000502-0000-0000-0000-000000000000";
+pub const UNAVAILABLE: &str = "00000503-0000-0000-0000-000000000000";
+pub const SLOW: &str = "0000de1a-0000-0000-0000-000000000000";
+pub const UNSUCCESSFUL: &str = "0000fa15-0000-0000-0000-000000000000";
+pub const TRUNCATED: &str = "0000bad0-0000-0000-0000-000000000000";
+pub const STREAMED: &str = "0000b16f-0000-0000-0000-000000000000";
+pub const BROKEN_STREAM: &str = "0000d1ed-0000-0000-0000-000000000000";
+pub const MISSING: &str = "0000404f-0000-0000-0000-000000000000";
+
+/// A running child process, killed when dropped.
+pub struct Server {
+    pub child: Child,
+    pub url: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Starts the mock hypixel server.
+pub async fn mock() -> Server {
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_mock_hypixel"))
+        .env("MOCK_PORT", port.to_string())
+        .env("MOCK_DELAY_MILLIS", "500")
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("mock should spawn");
+
+    let server = Server { child, url: format!("http://127.0.0.1:{port}") };
+    wait_until_up(&format!("{}/mock/hits/profiles/ready", server.url)).await;
+    server
+}
+
+/// Starts the proxy in front of `upstream`, with in-memory storage and a rate limit tests won't reach.
+/// `env` is applied last so it can override any of the defaults.
+pub async fn proxy(upstream: &Server, env: &[(&str, &str)]) -> Server {
+    proxy_on(free_port(), upstream, env).await
+}
+
+/// Starts the proxy like `proxy`, on a port picked beforehand, such as one already given to its peers.
+pub async fn proxy_on(port: u16, upstream: &Server, env: &[(&str, &str)]) -> Server {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_hypixel_api"));
+    command
+        .env("API_KEY", "test")
+        .env("UPSTREAM_URL", &upstream.url)
+        .env("IP_ADDR", "127.0.0.1")
+        .env("PORT", port.to_string())
+        .env("STORAGE", "memory")
+        .env("RATELIMIT_BURST", "1000")
+        .env_remove("PEERS")
+        .env_remove("ADMIN_KEY")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    for (key, value) in env {
+        command.env(key, value);
+    }
+
+    let server = Server { child: command.spawn().expect("proxy should spawn"), url: format!("http://127.0.0.1:{port}") };
+    wait_until_ready(&format!("{}/readyz", server.url)).await;
+    server
+}
+
+/// Number of times the mock served `endpoint` (`profiles` or `player`) for `uuid`.
+pub async fn upstream_hits(mock: &Server, endpoint: &str, uuid: &str) -> u64 {
+    reqwest::get(format!("{}/mock/hits/{endpoint}/{uuid}", mock.url)).await.unwrap().text().await.unwrap().parse().unwrap()
+}
+
+/// Waits for `url` to answer successfully, the proxy serves health checks before it's ready.
+pub async fn wait_until_ready(url: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if reqwest::get(url).await.is_ok_and(|res| res.status().is_success()) {
+            return;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{url} wasn't ready in time");
+}
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+async fn wait_until_up(url: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if reqwest::get(url).await.is_ok() {
+            return;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{url} didn't come up in time");
+}
@@ -0,0 +1,265 @@
+//! Settings read from the config file, flags and environment variables, and how bad ones are reported.
+
+mod common;
+
+use std::{env, fs, path::PathBuf, process::{self, Command, Stdio}};
+
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use simd_json::{OwnedValue, prelude::ValueObjectAccess};
+use tokio::time::{Duration, Instant, sleep};
+
+use common::{Server, free_port, mock, wait_until_ready};
+
+fn config_file(test: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("hypixel_api_{test}_{}.toml", process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Starts the proxy with nothing but `args` and `env`, none of the defaults `common::proxy` sets.
+async fn proxy_with(args: &[&str], env: &[(&str, &str)], port: u16) -> Server {
+    let child = Command::new(env!("CARGO_BIN_EXE_hypixel_api"))
+        .args(args)
+        .env_clear()
+        .envs(env.iter().copied())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("proxy should spawn");
+
+    let server = Server { child, url: format!("http://127.0.0.1:{port}") };
+    wait_until_ready(&format!("{}/readyz", server.url)).await;
+    server
+}
+
+async fn burst(proxy: &Server) -> String {
+    let res = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    res.headers()["x-ratelimit-limit"].to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn flags_override_the_file_and_env_overrides_flags() {
+    let mock = mock().await;
+    let file = config_file("precedence", &format!(r#"
+        # everything the proxy needs to start, from the file alone.
+        [server]
+        port = 1 # replaced by --port
+
+        [storage]
+        kind = "memory"
+
+        [ratelimit]
+        burst = 7
+
+        [upstream]
+        url = "{}"
+        api_keys = [
+            "test",
+        ]
+    "#, mock.url));
+    let config = file.to_str().unwrap();
+
+    let port = free_port();
+    let from_file = proxy_with(&["--config", config, "--port", &port.to_string()], &[], port).await;
+    assert_eq!(burst(&from_file).await, "7");
+    drop(from_file);
+
+    let port = free_port();
+    let from_flag = proxy_with(&["--config", config, "--port", &port.to_string(), "--ratelimit.burst=6"], &[], port).await;
+    assert_eq!(burst(&from_flag).await, "6");
+    drop(from_flag);
+
+    let port = free_port();
+    let from_env = proxy_with(&["--config", config, "--ratelimit.burst=6"], &[("CONFIG_FILE", "ignored.toml"), ("PORT", &port.to_string()), ("RATELIMIT_BURST", "5")], port).await;
+    assert_eq!(burst(&from_env).await, "5");
+
+    let _ = fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn every_invalid_setting_is_reported_before_exiting() {
+    let file = config_file("invalid", r#"
+        [storage]
+        kind = "disk"
+
+        [ratelimit]
+        burst = 0
+        unknown = true
+    "#);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hypixel_api"))
+        .args(["--config", file.to_str().unwrap(), "--port", "eighty", "--nonsense", "1"])
+        .env_clear()
+        .env("PEERS", "http://127.0.0.1:8001")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
+    for expected in [
+        "ratelimit.unknown",
+        "unknown flag --nonsense",
+        r#"server.port = "eighty" from flag --server.port"#,
+        r#"storage.kind = "disk""#,
+        "unknown storage disk",
+        "ratelimit.burst should be more than 0",
+        "upstream.api_keys should contain at least one key",
+        "peers.self_url should be set",
+        "peers.secret should be set",
+    ] {
+        assert!(stderr.contains(expected), "{expected:?} missing from {stderr}");
+    }
+    let _ = fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn array_items_are_kept_whole_and_dotted_keys_work() {
+    let mock = mock().await;
+    let port = free_port();
+    let file = config_file("arrays", &format!(r#"
+        server.port = {port}
+        storage = {{ kind = "memory" }}
+
+        [upstream]
+        url = "{}"
+        api_keys = ["first-key,with-comma", "second-key-0002"]
+    "#, mock.url));
+    let proxy = proxy_with(&["--config", file.to_str().unwrap()], &[], port).await;
+
+    let stats: Value = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap().json().await.unwrap();
+    let names: Vec<_> = stats["keys"].as_array().unwrap().iter().map(|key| key["key"].as_str().unwrap()).collect();
+    assert_eq!(names, ["...omma", "...0002"]);
+
+    let _ = fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn values_of_the_wrong_type_are_reported() {
+    let file = config_file("types", r#"
+        [ratelimit]
+        burst = 1.5
+        trusted_proxies = [1, 2]
+
+        [upstream]
+        api_keys = "test"
+        url = ["http://127.0.0.1"]
+
+        [storage.nested]
+        kind = "memory"
+    "#);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hypixel_api"))
+        .args(["--config", file.to_str().unwrap()])
+        .env_clear()
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
+    for expected in [
+        "ratelimit.burst = 1.5",
+        "ratelimit.trusted_proxies = [1, 2]",
+        "expected an array of strings, found integer",
+        "upstream.url = [\"http://127.0.0.1\"]",
+        "expected a single value, found array",
+        ":10: unknown setting storage.nested",
+    ] {
+        assert!(stderr.contains(expected), "{expected:?} missing from {stderr}");
+    }
+    let _ = fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn values_that_cant_be_sent_as_headers_are_reported() {
+    let output = Command::new(env!("CARGO_BIN_EXE_hypixel_api"))
+        .env_clear()
+        .envs([("API_KEYS", "good,bad\u{7f}key"), ("PEERS", "http://127.0.0.1:8001"), ("SELF_URL", "http://127.0.0.1:8001"), ("PEER_SECRET", "bad\u{1}secret")])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
+    for expected in ["upstream.api_keys[1] should only contain valid header characters", "peers.secret should only contain valid header characters"] {
+        assert!(stderr.contains(expected), "{expected:?} missing from {stderr}");
+    }
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[tokio::test]
+async fn storage_that_cant_be_loaded_is_logged_before_exiting() {
+    // a path under a file can never be created as a directory.
+    let file = config_file("storage_path", "");
+    let output = Command::new(env!("CARGO_BIN_EXE_hypixel_api"))
+        .env_clear()
+        .envs([("API_KEY", "test"), ("PORT", &free_port().to_string()), ("STORAGE", "ltmdb"), ("DB_PATH", &file.join("db").display().to_string())])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(stdout.contains("Storage failed to load, exiting"), "{stdout}");
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("panicked"));
+    let _ = fs::remove_file(file);
+}
+
+fn reloadable(upstream: &str, port: u16, burst: u32) -> String {
+    format!(r#"
+        [server]
+        port = {port}
+
+        [storage]
+        kind = "memory"
+
+        [ratelimit]
+        burst = {burst}
+
+        [upstream]
+        url = "{upstream}"
+        api_keys = ["test"]
+
+        [admin]
+        key = "admin"
+    "#)
+}
+
+async fn reload(proxy: &Server) -> (StatusCode, OwnedValue) {
+    let res = Client::new().post(format!("{}/config/reload", proxy.url)).header("Admin-Key", "admin").send().await.unwrap();
+    let status = res.status();
+    (status, simd_json::to_owned_value(&mut res.bytes().await.unwrap().to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn the_config_is_reloaded_by_the_admin_endpoint_and_sighup() {
+    let mock = mock().await;
+    let port = free_port();
+    let file = config_file("reload", &reloadable(&mock.url, port, 7));
+    let proxy = proxy_with(&["--config", file.to_str().unwrap()], &[], port).await;
+    assert_eq!(burst(&proxy).await, "7");
+
+    // the port can't change without a restart, so it's reported and left as it was.
+    fs::write(&file, reloadable(&mock.url, free_port(), 4)).unwrap();
+    let (status, json) = reload(&proxy).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json.get("applied"), Some(&OwnedValue::from(vec!["ratelimit.burst"])));
+    assert_eq!(json.get("restart_required"), Some(&OwnedValue::from(vec!["server.port"])));
+    assert_eq!(burst(&proxy).await, "4");
+
+    fs::write(&file, reloadable(&mock.url, port, 0)).unwrap();
+    let (status, json) = reload(&proxy).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json.get("code"), Some(&OwnedValue::from("invalid_config")));
+    assert_eq!(burst(&proxy).await, "4");
+
+    fs::write(&file, reloadable(&mock.url, port, 3)).unwrap();
+    let hangup = Command::new("kill").args(["-HUP", &proxy.child.id().to_string()]).status().unwrap();
+    assert!(hangup.success());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while burst(&proxy).await != "3" {
+        assert!(Instant::now() < deadline, "SIGHUP didn't reload the config");
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    let _ = fs::remove_file(file);
+}
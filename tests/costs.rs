@@ -0,0 +1,41 @@
+//! Rate limit costs weighted by route, and by whether a request went upstream.
+
+mod common;
+
+use reqwest::StatusCode;
+
+use common::{Server, mock, proxy, upstream_hits};
+
+const PLAYER: &str = "3e8d1c2b-6f4a-4b9e-a7c5-0d2e1f3a4b5c";
+
+async fn remaining(proxy: &Server, path: &str) -> u64 {
+    let res = reqwest::get(format!("{}{path}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    res.headers()["x-ratelimit-remaining"].to_str().unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn cache_hits_are_cheaper_and_fresh_requests_cost_more() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("RATELIMIT_BURST", "20"), ("RATELIMIT_REFRESH", "60")]).await;
+
+    assert_eq!(remaining(&proxy, "/stats").await, 19);
+    // the first fetch goes upstream, costing its base and upstream cost.
+    assert_eq!(remaining(&proxy, &format!("/get/{PLAYER}")).await, 16);
+    assert_eq!(remaining(&proxy, &format!("/get/{PLAYER}")).await, 15);
+    assert_eq!(upstream_hits(&mock, "profiles", PLAYER).await, 1);
+
+    assert_eq!(remaining(&proxy, &format!("/get/{PLAYER}?fresh=true")).await, 9);
+    assert_eq!(upstream_hits(&mock, "profiles", PLAYER).await, 2);
+}
+
+#[tokio::test]
+async fn upstream_costs_drain_what_is_left() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("RATELIMIT_BURST", "2"), ("RATELIMIT_REFRESH", "60")]).await;
+
+    // the base cost is affordable, so the request is served even though its upstream cost isn't.
+    assert_eq!(remaining(&proxy, &format!("/get/{PLAYER}")).await, 0);
+    let res = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+}
@@ -0,0 +1,70 @@
+//! Json error bodies and the statuses they're returned with.
+
+mod common;
+
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+
+use common::{MISSING, THROTTLED, free_port, mock, proxy};
+
+#[tokio::test]
+async fn invalid_uuids_are_bad_requests() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    for route in ["get", "secrets"] {
+        let res = reqwest::get(format!("{}/{route}/not-a-uuid", proxy.url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let json: Value = res.json().await.unwrap();
+        assert_eq!(json["code"], "invalid_input");
+        assert_eq!(json["upstream_status"], Value::Null);
+        assert!(json["request_id"].as_str().is_some_and(|id| !id.is_empty()));
+    }
+}
+
+#[tokio::test]
+async fn request_ids_sent_by_the_client_are_kept() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    let res = Client::new().get(format!("{}/get/not-a-uuid", proxy.url)).header("X-Request-Id", "from-the-client").send().await.unwrap();
+    let json: Value = res.json().await.unwrap();
+    assert_eq!(json["request_id"], "from-the-client");
+}
+
+#[tokio::test]
+async fn missing_player_data_is_not_found() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    let res = reqwest::get(format!("{}/secrets/{MISSING}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let json: Value = res.json().await.unwrap();
+    assert_eq!(json["code"], "not_found");
+}
+
+#[tokio::test]
+async fn upstream_throttling_keeps_its_status_and_cause() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    let res = reqwest::get(format!("{}/secrets/{THROTTLED}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    let json: Value = res.json().await.unwrap();
+    assert_eq!(json["code"], "upstream_throttled");
+    assert_eq!(json["upstream_status"], 429);
+    assert_eq!(json["cause"], "Key throttle");
+}
+
+#[tokio::test]
+async fn unreachable_upstream_is_a_bad_gateway() {
+    let mock = mock().await;
+    let closed = format!("http://127.0.0.1:{}", free_port());
+    let proxy = proxy(&mock, &[("UPSTREAM_URL", &closed)]).await;
+
+    let res = reqwest::get(format!("{}/secrets/4c9e2a7b-3d1f-4b8e-a6c5-0d2f1e3a5b7c", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    let json: Value = res.json().await.unwrap();
+    assert_eq!(json["code"], "upstream_unreachable");
+    assert_eq!(json["upstream_status"], Value::Null);
+}
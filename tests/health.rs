@@ -0,0 +1,59 @@
+//! Liveness and readiness checks.
+
+mod common;
+
+use std::{env, fs, process};
+
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use common::{SERVER_ERROR, Server, mock, proxy};
+
+async fn get(proxy: &Server, path: &str) -> (StatusCode, Value) {
+    let res = reqwest::get(format!("{}{path}", proxy.url)).await.unwrap();
+    (res.status(), res.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn ready_with_ltmdb_storage() {
+    let mock = mock().await;
+    let dir = env::temp_dir().join(format!("hypixel_api_health_{}", process::id()));
+    let proxy = proxy(&mock, &[("STORAGE", "ltmdb"), ("DB_PATH", dir.to_str().unwrap())]).await;
+
+    let (status, health) = get(&proxy, "/healthz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["checks"]["server"]["ok"], true);
+
+    let (status, ready) = get(&proxy, "/readyz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ready["status"], "ready");
+    assert_eq!(ready["checks"]["storage_loaded"]["ok"], true);
+    assert_eq!(ready["checks"]["storage_writable"]["detail"], "writable");
+    assert_eq!(ready["checks"]["expiration_task"]["detail"], "running");
+    assert_eq!(ready["checks"]["breaker"]["detail"], "closed");
+
+    drop(proxy);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn not_ready_while_the_breaker_is_open() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("BREAKER_FAILURES", "1"), ("BREAKER_OPEN_SECONDS", "60")]).await;
+
+    let res = reqwest::get(format!("{}/get/{SERVER_ERROR}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let (status, ready) = get(&proxy, "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(ready["status"], "not_ready");
+    assert_eq!(ready["checks"]["breaker"]["ok"], false);
+    assert_eq!(ready["checks"]["breaker"]["detail"], "open");
+    // memory storage has nothing on disk to check.
+    assert_eq!(ready["checks"]["storage_writable"]["ok"], true);
+
+    // still alive, so it isn't restarted while it waits for hypixel to recover.
+    let (status, _) = get(&proxy, "/healthz").await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,130 @@
+//! Log levels, json lines, request ids and the rotating log file.
+
+mod common;
+
+use std::{env, fs, path::{Path, PathBuf}, process};
+
+use futures::future::join;
+use reqwest::{Client, StatusCode};
+use simd_json::{OwnedValue, prelude::{ValueAsScalar, ValueObjectAccess}};
+use tokio::time::{Duration, Instant, sleep};
+
+use common::{SLOW, mock, proxy};
+
+const PLAYER: &str = "7c2e9a4b-1d3f-4a6c-8e5b-2f4a6c8e0b1d";
+
+fn log_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("hypixel_api_{test}_{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Waits for the log writer to catch up, until the logs read so far are `done`.
+async fn read_until(path: &Path, done: impl Fn(&str) -> bool) -> String {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let logs = fs::read_to_string(path).unwrap_or_default();
+        if done(&logs) || Instant::now() > deadline {
+            return logs
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn json_lines_are_written_at_the_configured_levels() {
+    let mock = mock().await;
+    let dir = log_dir("json_logs");
+    let file = dir.join("app.log");
+    let proxy = proxy(&mock, &[
+        ("LOG_FILE", file.to_str().unwrap()),
+        ("LOG_FORMAT", "json"),
+        ("LOG_LEVEL", "warn,request_utils=info"),
+    ]).await;
+
+    let res = reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    res.bytes().await.unwrap();
+
+    let logs = read_until(&file, |logs| logs.contains("Upstream hit")).await;
+    let lines = json_lines(&logs);
+
+    let hit = lines.iter().find(|line| line["message"].as_str().unwrap().starts_with("Upstream hit")).expect("upstream hit should be logged");
+    assert_eq!(hit["level"].as_str(), Some("info"));
+    assert_eq!(hit["target"].as_str(), Some("request_utils"));
+    assert_eq!(hit["status"].as_u64(), Some(200));
+    assert!(hit["elapsed_ms"].as_f64().is_some());
+    assert!(hit["key"].as_str().unwrap().contains(PLAYER));
+    assert!(hit.get("flag").is_some());
+
+    // everything else is filtered to warnings, so the startup lines and per request timings aren't written.
+    assert!(lines.iter().all(|line| line["target"].as_str() == Some("request_utils") || line["level"].as_str() == Some("warn")));
+
+    drop(proxy);
+    let _ = fs::remove_dir_all(dir);
+}
+
+fn json_lines(logs: &str) -> Vec<OwnedValue> {
+    logs.lines().map(|line| simd_json::from_slice(&mut line.as_bytes().to_vec()).unwrap()).collect()
+}
+
+#[tokio::test]
+async fn lines_are_tagged_with_their_request_id() {
+    let mock = mock().await;
+    let dir = log_dir("request_id_logs");
+    let file = dir.join("app.log");
+    let proxy = proxy(&mock, &[("LOG_FILE", file.to_str().unwrap()), ("LOG_FORMAT", "json"), ("LOG_LEVEL", "debug")]).await;
+
+    let client = Client::new();
+    let get = |id: &'static str| client.get(format!("{}/get/{SLOW}", proxy.url)).header("X-Request-Id", id).send();
+    let (first, second) = join(get("first-request"), get("second-request")).await;
+    let (first, second) = (first.unwrap(), second.unwrap());
+    assert_eq!(first.headers()["x-request-id"], "first-request");
+    assert_eq!(second.headers()["x-request-id"], "second-request");
+
+    let logs = read_until(&file, |logs| {
+        logs.lines().filter(|line| line.contains("Time elapsed for the last request") && line.contains("-request\"")).count() == 2
+    }).await;
+    let lines = json_lines(&logs);
+    let tagged = |id: &str, message: &str| lines.iter().any(|line| {
+        line["request_id"].as_str() == Some(id) && line["message"].as_str().unwrap().starts_with(message)
+    });
+
+    // one request fetched the profile and the other waited on it, each line tagged with the request it belongs to.
+    let (leader, follower) = if tagged("first-request", "Upstream hit") { ("first-request", "second-request") } else { ("second-request", "first-request") };
+    assert!(tagged(leader, "Upstream hit"));
+    assert!(tagged(leader, "Time elapsed for the last request"));
+    assert!(tagged(follower, "Time elapsed for the last request"));
+    assert!(!tagged(follower, "Upstream hit"));
+    let followed = lines.iter().find(|line| line["request_id"].as_str() == Some(follower) && line.get("leader").is_some()).unwrap();
+    assert_eq!(followed["leader"].as_str(), Some(leader));
+
+    drop(proxy);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn the_log_file_is_rotated_by_size() {
+    let mock = mock().await;
+    let dir = log_dir("rotated_logs");
+    let file = dir.join("app.log");
+    let proxy = proxy(&mock, &[
+        ("LOG_FILE", file.to_str().unwrap()),
+        ("LOG_LEVEL", "debug"),
+        ("LOG_FILE_MAX_BYTES", "512"),
+        ("LOG_FILE_KEEP", "2"),
+    ]).await;
+
+    for _ in 0..20 {
+        reqwest::get(format!("{}/stats", proxy.url)).await.unwrap().bytes().await.unwrap();
+    }
+    read_until(&file.with_extension("log.2"), |logs| !logs.is_empty()).await;
+
+    assert!(fs::metadata(&file).unwrap().len() <= 512);
+    assert!(fs::metadata(file.with_extension("log.1")).unwrap().len() <= 512);
+    assert!(file.with_extension("log.2").exists());
+    assert!(!file.with_extension("log.3").exists());
+
+    drop(proxy);
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,120 @@
+//! The `ltmdb` admin binary, run against data directories written by hand.
+
+use std::{env, fs, path::{Path, PathBuf}, process::{self, Command, Output}, time::{SystemTime, UNIX_EPOCH}};
+
+use lz4_flex::compress_prepend_size;
+
+const HOUR_BUCKET: &str = "3600000";
+const MINUTE_BUCKET: &str = "60000";
+
+fn data_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("hypixel_api_ltmdb_{test}_{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Writes a partition file in ltmdb's format, length prefixed keys and values back to back.
+fn partition(dir: &Path, bucket: &str, created: u64, entries: &[(&[u8], &[u8])]) -> PathBuf {
+    let bucket = dir.join(bucket);
+    fs::create_dir_all(&bucket).unwrap();
+    let mut file = Vec::new();
+    for (key, value) in entries {
+        file.extend_from_slice(&(key.len() as u64).to_be_bytes());
+        file.extend_from_slice(key);
+        file.extend_from_slice(&(value.len() as u64).to_be_bytes());
+        file.extend_from_slice(value);
+    }
+    let path = bucket.join(created.to_string());
+    fs::write(&path, file).unwrap();
+    path
+}
+
+/// A directory with a value replaced by a newer partition, a compressed value and an expired partition.
+fn populated(test: &str) -> (PathBuf, u64) {
+    let dir = data_dir(test);
+    let now = now();
+    partition(&dir, HOUR_BUCKET, now - 120, &[(b"player", b"old"), (b"profile", &compress_prepend_size(b"{\"success\":true}"))]);
+    partition(&dir, HOUR_BUCKET, now - 60, &[(b"player", b"new")]);
+    partition(&dir, MINUTE_BUCKET, now - 600, &[(b"gone", b"expired")]);
+    (dir, now)
+}
+
+fn ltmdb(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ltmdb")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn values_are_listed_dumped_and_exported() {
+    let (dir, now) = populated("read");
+    let path = dir.to_str().unwrap();
+
+    let list = stdout(&ltmdb(&["list", path]));
+    assert!(list.contains("bucket 3600s: 2 partitions, 3 entries"), "{list}");
+    assert!(list.contains(&format!("partition {}: 1 entries, 27 bytes, expired", now - 600)), "{list}");
+
+    assert_eq!(stdout(&ltmdb(&["dump", path, "player", "--text"])), "new");
+    assert_eq!(stdout(&ltmdb(&["dump", path, "70726f66696c65", "--lz4"])), "{\"success\":true}");
+    assert_eq!(ltmdb(&["dump", path, "missing", "--text"]).status.code(), Some(1));
+
+    // the replaced and expired values aren't exported.
+    let export = stdout(&ltmdb(&["export", path, "--lz4"]));
+    let lines: Vec<&str> = export.lines().collect();
+    assert_eq!(lines.len(), 2, "{export}");
+    assert!(lines.iter().any(|line| line.contains(r#""key":"706c61796572""#) && line.contains(r#""value":"new""#)), "{export}");
+    assert!(lines.iter().any(|line| line.contains(r#""value":"{\"success\":true}""#)), "{export}");
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn truncated_partitions_fail_verification() {
+    let (dir, now) = populated("verify");
+    let path = dir.to_str().unwrap();
+    assert!(stdout(&ltmdb(&["verify", path])).contains("3 of 3 partitions parsed"));
+
+    let truncated = partition(&dir, HOUR_BUCKET, now - 30, &[(b"player", b"newest")]);
+    let bytes = fs::read(&truncated).unwrap();
+    fs::write(&truncated, &bytes[..bytes.len() - 2]).unwrap();
+
+    let output = ltmdb(&["verify", path]);
+    let (report, errors) = (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap());
+    assert_eq!(output.status.code(), Some(1), "{report}{errors}");
+    assert!(errors.contains("the value of the entry at byte 0 runs past the end of the file"), "{errors}");
+    assert!(!report.contains("runs past the end of the file"), "{report}");
+    assert!(report.contains("3 of 4 partitions parsed"), "{report}");
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn purge_and_compact_free_what_the_database_would_never_serve() {
+    let (dir, now) = populated("maintain");
+    let path = dir.to_str().unwrap();
+    let expired = dir.join(MINUTE_BUCKET).join((now - 600).to_string());
+    let replaced = dir.join(HOUR_BUCKET).join((now - 120).to_string());
+    let replaced_len = fs::metadata(&replaced).unwrap().len();
+
+    assert!(stdout(&ltmdb(&["purge", path, "--dry-run"])).contains("1 expired partitions"));
+    assert!(expired.exists());
+    stdout(&ltmdb(&["purge", path]));
+    assert!(!expired.exists());
+
+    let compacted = stdout(&ltmdb(&["compact", path]));
+    assert!(compacted.contains("25 bytes of replaced values"), "{compacted}");
+    assert_eq!(fs::metadata(&replaced).unwrap().len(), replaced_len - 25);
+
+    assert_eq!(stdout(&ltmdb(&["dump", path, "player", "--text"])), "new");
+    assert_eq!(stdout(&ltmdb(&["dump", path, "profile", "--text", "--lz4"])), "{\"success\":true}");
+    assert!(stdout(&ltmdb(&["verify", path])).contains("2 of 2 partitions parsed"));
+
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,74 @@
+//! The prometheus `/metrics` endpoint.
+
+mod common;
+
+use reqwest::StatusCode;
+
+use common::{Server, mock, proxy};
+
+const PLAYER: &str = "5a1f3c9e-2b7d-4e8a-9c6f-1d3b5e7a9c2f";
+
+async fn metrics(proxy: &Server) -> String {
+    let res = reqwest::get(format!("{}/metrics", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+    res.text().await.unwrap()
+}
+
+/// The value of the series starting with `series`, which should include its labels.
+fn value(metrics: &str, series: &str) -> f64 {
+    metrics.lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("{series} should be in the metrics:\n{metrics}"))
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn requests_cache_and_upstream_are_reported() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    for _ in 0..2 {
+        let res = reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        res.bytes().await.unwrap();
+    }
+    let res = reqwest::get(format!("{}/get/not-a-uuid", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let metrics = metrics(&proxy).await;
+    assert!(metrics.contains("# TYPE hypixel_api_request_duration_seconds histogram"));
+    assert_eq!(value(&metrics, r#"hypixel_api_request_duration_seconds_count{route="/get/{uuid}",status="200"}"#), 2.0);
+    assert_eq!(value(&metrics, r#"hypixel_api_request_duration_seconds_count{route="/get/{uuid}",status="400"}"#), 1.0);
+    assert_eq!(value(&metrics, r#"hypixel_api_request_duration_seconds_bucket{route="/get/{uuid}",status="200",le="+Inf"}"#), 2.0);
+    assert_eq!(value(&metrics, r#"hypixel_api_cache_misses_total{key="profile"}"#), 1.0);
+    assert_eq!(value(&metrics, r#"hypixel_api_cache_hits_total{key="profile",tier="memory"}"#), 1.0);
+    assert_eq!(value(&metrics, r#"hypixel_api_single_flight_leaders_total{key="profile"}"#), 1.0);
+    assert_eq!(value(&metrics, r#"hypixel_api_upstream_request_duration_seconds_count{status="200"}"#), 1.0);
+    assert!(value(&metrics, r#"hypixel_api_upstream_ratelimit_remaining{key="..."}"#) > 0.0);
+    assert_eq!(value(&metrics, r#"hypixel_api_breaker_state{state="closed"}"#), 1.0);
+}
+
+#[tokio::test]
+async fn ltmdb_reads_writes_and_disk_use_are_reported() {
+    let mock = mock().await;
+    let dir = std::env::temp_dir().join(format!("hypixel_api_metrics_{}", std::process::id()));
+    let proxy = proxy(&mock, &[("STORAGE", "ltmdb"), ("DB_PATH", dir.to_str().unwrap())]).await;
+
+    let res = reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    res.bytes().await.unwrap();
+
+    let metrics = metrics(&proxy).await;
+    assert_eq!(value(&metrics, r#"hypixel_api_storage_read_duration_seconds_count{result="miss"}"#), 1.0);
+    assert_eq!(value(&metrics, "hypixel_api_storage_write_duration_seconds_count"), 1.0);
+    let written = value(&metrics, "hypixel_api_storage_written_bytes_total");
+    assert!(written > 0.0);
+    assert!(value(&metrics, "hypixel_api_storage_disk_bytes") >= written);
+    // the partition the value was written to is queued to expire with it.
+    assert_eq!(value(&metrics, "hypixel_api_storage_expiration_queue_depth"), 1.0);
+
+    drop(proxy);
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,142 @@
+//! Instances sharing one cache over a consistent hash ring.
+
+mod common;
+
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+
+use common::{Server, free_port, mock, proxy_on, upstream_hits};
+
+/// Enough players that each of two peers is sure to own some.
+const PLAYERS: [&str; 16] = [
+    "1b0e4c7a-2d3f-4a5b-8c6d-7e8f9a0b1c2d", "2c1f5d8b-3e4a-4b6c-9d7e-8f9a0b1c2d3e", "3d2a6e9c-4f5b-4c7d-ae8f-9a0b1c2d3e4f", "4e3b7fad-5a6c-4d8e-bf9a-0b1c2d3e4f5a",
+    "5f4c8abe-6b7d-4e9f-8a0b-1c2d3e4f5a6b", "6a5d9bcf-7c8e-4f0a-9b1c-2d3e4f5a6b7c", "7b6eacd0-8d9f-4a1b-ac2d-3e4f5a6b7c8d", "8c7fbde1-9e0a-4b2c-bd3e-4f5a6b7c8d9e",
+    "9d80cef2-af1b-4c3d-8e4f-5a6b7c8d9eaf", "ae91dfa3-b02c-4d4e-9f5a-6b7c8d9eafb0", "bfa2e0b4-c13d-4e5f-a06b-7c8d9eafb0c1", "c0b3f1c5-d24e-4f6a-b17c-8d9eafb0c1d2",
+    "d1c402d6-e35f-4a7b-828d-9eafb0c1d2e3", "e2d513e7-f46a-4b8c-939e-afb0c1d2e3f4", "f3e624f8-a57b-4c9d-a4af-b0c1d2e3f4a5", "04f735a9-b68c-4dae-b5b0-c1d2e3f4a5b6",
+];
+
+/// Starts `count` peers on one ring, all in front of `upstream`.
+async fn ring(upstream: &Server, count: usize) -> Vec<Server> {
+    ring_with_secrets(upstream, &vec!["secret"; count]).await
+}
+
+/// Starts a peer per secret on one ring, each sending and expecting its own `PEER_SECRET`.
+async fn ring_with_secrets(upstream: &Server, secrets: &[&str]) -> Vec<Server> {
+    let ports: Vec<u16> = secrets.iter().map(|_| free_port()).collect();
+    let urls: Vec<String> = ports.iter().map(|port| format!("http://127.0.0.1:{port}")).collect();
+    let peers = urls.join(",");
+
+    let mut servers = Vec::new();
+    for ((port, url), secret) in ports.iter().zip(&urls).zip(secrets) {
+        let env = [("PEERS", peers.as_str()), ("SELF_URL", url.as_str()), ("PEER_SECRET", secret), ("ADMIN_KEY", "admin")];
+        servers.push(proxy_on(*port, upstream, &env).await);
+    }
+    servers
+}
+
+async fn stored(server: &Server, uuid: &str) -> bool {
+    let res = Client::new().get(format!("{}/cache/{uuid}", server.url)).header("Admin-Key", "admin").send().await.unwrap();
+    !res.json::<Value>().await.unwrap()["keys"]["profile"]["storage"].is_null()
+}
+
+async fn peer_hits(server: &Server) -> u64 {
+    let stats: Value = reqwest::get(format!("{}/stats", server.url)).await.unwrap().json().await.unwrap();
+    stats["cache"]["profile"]["peer_hits"]["total"].as_u64().unwrap()
+}
+
+#[tokio::test]
+async fn keys_are_fetched_through_their_owner() {
+    let mock = mock().await;
+    let servers = ring(&mock, 2).await;
+    let (local, remote) = (&servers[0], &servers[1]);
+
+    for uuid in PLAYERS {
+        let res = reqwest::get(format!("{}/get/{uuid}", local.url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    // keys the other peer owns were fetched and stored by it, and only kept in memory here.
+    let mut owned_by_remote = Vec::new();
+    for uuid in PLAYERS {
+        assert_eq!(upstream_hits(&mock, "profiles", uuid).await, 1);
+        let (here, there) = (stored(local, uuid).await, stored(remote, uuid).await);
+        assert!(here != there, "{uuid} should be stored by exactly one peer");
+        if there {
+            owned_by_remote.push(uuid);
+        }
+    }
+    assert!(!owned_by_remote.is_empty() && owned_by_remote.len() < PLAYERS.len());
+    assert_eq!(peer_hits(local).await, owned_by_remote.len() as u64);
+
+    // the owner answers from its own cache.
+    let res = reqwest::get(format!("{}/get/{}", remote.url, owned_by_remote[0])).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(upstream_hits(&mock, "profiles", owned_by_remote[0]).await, 1);
+}
+
+#[tokio::test]
+async fn peer_requests_need_the_secret() {
+    let mock = mock().await;
+    let servers = ring(&mock, 2).await;
+    let client = Client::new();
+    let uuid = PLAYERS[0];
+
+    for secret in [None, Some("wrong")] {
+        let mut req = client.get(format!("{}/peer/0/{uuid}", servers[0].url));
+        if let Some(secret) = secret {
+            req = req.header("Peer-Secret", secret);
+        }
+        let res = req.send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // refused callers are still rate limited like any other client.
+        assert!(res.headers().contains_key("x-ratelimit-remaining"));
+    }
+    assert_eq!(upstream_hits(&mock, "profiles", uuid).await, 0);
+
+    let res = client.get(format!("{}/peer/0/{uuid}", servers[0].url)).header("Peer-Secret", "secret").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!res.headers().contains_key("x-ratelimit-remaining"));
+    assert_eq!(upstream_hits(&mock, "profiles", uuid).await, 1);
+}
+
+#[tokio::test]
+async fn falls_back_to_upstream_when_the_owner_is_down() {
+    let mock = mock().await;
+    let mut servers = ring(&mock, 2).await;
+    let remote = servers.pop().unwrap();
+    let local = &servers[0];
+
+    let mut owned_by_remote = None;
+    for uuid in PLAYERS {
+        reqwest::get(format!("{}/get/{uuid}", remote.url)).await.unwrap();
+        if stored(&remote, uuid).await {
+            owned_by_remote = Some(uuid);
+            break
+        }
+    }
+    let uuid = owned_by_remote.expect("the remote peer should own one of the players");
+    drop(remote);
+
+    let res = reqwest::get(format!("{}/get/{uuid}", local.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(upstream_hits(&mock, "profiles", uuid).await, 2);
+    assert_eq!(peer_hits(local).await, 0);
+    assert!(stored(local, uuid).await);
+}
+
+#[tokio::test]
+async fn falls_back_to_upstream_when_the_owner_refuses() {
+    let mock = mock().await;
+    let servers = ring_with_secrets(&mock, &["secret", "mismatched"]).await;
+    let (local, remote) = (&servers[0], &servers[1]);
+
+    // the remote peer owns some of these, but refuses our secret, so they're fetched here rather than failing.
+    for uuid in PLAYERS {
+        let res = reqwest::get(format!("{}/get/{uuid}", local.url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(upstream_hits(&mock, "profiles", uuid).await, 1);
+        assert!(stored(local, uuid).await);
+        assert!(!stored(remote, uuid).await);
+    }
+    assert_eq!(peer_hits(local).await, 0);
+}
@@ -0,0 +1,58 @@
+//! Draining in flight requests and closing storage on `SIGTERM`.
+
+mod common;
+
+use std::{env, fs, process::{self, Command}};
+
+use reqwest::StatusCode;
+use tokio::time::{Duration, Instant, sleep};
+
+use common::{SLOW, mock, proxy, upstream_hits};
+
+#[tokio::test]
+async fn sigterm_finishes_in_flight_requests_and_keeps_stored_values() {
+    let mock = mock().await;
+    let dir = env::temp_dir().join(format!("hypixel_api_shutdown_{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let (db, log) = (dir.join("db"), dir.join("app.log"));
+    let env = [("STORAGE", "ltmdb"), ("DB_PATH", db.to_str().unwrap()), ("LOG_FILE", log.to_str().unwrap())];
+
+    let mut first = proxy(&mock, &env).await;
+    let in_flight = tokio::spawn(reqwest::get(format!("{}/get/{SLOW}", first.url)));
+    // the mock takes 500ms to answer, so the request is still in flight once the mock has seen it.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while upstream_hits(&mock, "profiles", SLOW).await == 0 {
+        assert!(Instant::now() < deadline, "the request never reached the mock");
+        sleep(Duration::from_millis(10)).await;
+    }
+    let term = Command::new("kill").args(["-TERM", &first.child.id().to_string()]).status().unwrap();
+    assert!(term.success());
+
+    let res = in_flight.await.unwrap().unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    res.bytes().await.unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = first.child.try_wait().unwrap() {
+            break status
+        }
+        assert!(Instant::now() < deadline, "the proxy didn't exit after SIGTERM");
+        sleep(Duration::from_millis(50)).await;
+    };
+    assert!(status.success());
+
+    let logs = fs::read_to_string(&log).unwrap();
+    assert!(logs.contains("Got SIGTERM"), "{logs}");
+    assert!(logs.contains("Shut down in"), "{logs}");
+    assert!(logs.contains("partitions synced"), "{logs}");
+
+    // the value written while draining is read back from the database rather than fetched again.
+    let second = proxy(&mock, &env).await;
+    let res = reqwest::get(format!("{}/get/{SLOW}", second.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(upstream_hits(&mock, "profiles", SLOW).await, 1);
+
+    drop(second);
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,38 @@
+//! Per layer cache accounting reported in `/stats`.
+
+mod common;
+
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde_json::Value;
+use tokio::time::sleep;
+
+use common::{mock, proxy, upstream_hits};
+
+const PLAYER: &str = "3e8b1c5a-6d2f-4a7e-9c1b-8f0d2e4a6b3c";
+
+#[tokio::test]
+async fn each_layer_is_counted() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("PROFILE_CACHE_TTL_SECONDS", "1")]).await;
+    let get = || async { reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap().status() };
+
+    // fetched upstream, then answered from memory until it expires there, then from storage.
+    assert_eq!(get().await, StatusCode::OK);
+    assert_eq!(get().await, StatusCode::OK);
+    sleep(Duration::from_millis(1100)).await;
+    assert_eq!(get().await, StatusCode::OK);
+    assert_eq!(upstream_hits(&mock, "profiles", PLAYER).await, 1);
+
+    let stats: Value = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap().json().await.unwrap();
+    let profile = &stats["cache"]["profile"];
+    assert_eq!(profile["upstream_fetches"]["total"], 1);
+    assert_eq!(profile["memory_hits"]["total"], 1);
+    assert_eq!(profile["db_hits"]["total"], 1);
+    assert_eq!(profile["peer_hits"]["total"], 0);
+    // memory hits are answered before joining a single flight group.
+    assert_eq!(profile["single_flight_leaders"]["total"], 2);
+    assert_eq!(profile["errors"]["total"], 0);
+    assert_eq!(stats["upstream_calls"]["total"], 1);
+}
@@ -0,0 +1,78 @@
+//! The storage tier each key type uses, for every `storage.kind`.
+
+mod common;
+
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use tokio::time::sleep;
+
+use common::{Server, mock, proxy, upstream_hits};
+
+const PLAYER: &str = "6d3a9f1e-2b4c-4d7e-8a5f-1c0b2e3d4f6a";
+
+async fn get(proxy: &Server, route: &str) {
+    let res = reqwest::get(format!("{}/{route}/{PLAYER}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+async fn storage(proxy: &Server, key: &str) -> Value {
+    let res = Client::new().get(format!("{}/cache/{PLAYER}", proxy.url)).header("Admin-Key", "admin").send().await.unwrap();
+    res.json::<Value>().await.unwrap()["keys"][key]["storage"].clone()
+}
+
+async fn stored(proxy: &Server, key: &str) -> bool {
+    !storage(proxy, key).await.is_null()
+}
+
+/// Requests a profile and secrets, then again once they've left the memory cache.
+async fn twice(proxy: &Server) {
+    get(proxy, "get").await;
+    get(proxy, "secrets").await;
+    sleep(Duration::from_millis(1100)).await;
+    get(proxy, "get").await;
+    get(proxy, "secrets").await;
+}
+
+#[tokio::test]
+async fn memory_storage_keeps_profiles_only() {
+    let mock = mock().await;
+    let env = [("STORAGE", "memory"), ("ADMIN_KEY", "admin"), ("PROFILE_CACHE_TTL_SECONDS", "1"), ("SECRETS_TTL_SECONDS", "1")];
+    let proxy = proxy(&mock, &env).await;
+
+    twice(&proxy).await;
+    assert!(stored(&proxy, "profile").await);
+    assert!(!stored(&proxy, "secrets").await);
+    assert_eq!(upstream_hits(&mock, "profiles", PLAYER).await, 1);
+    assert_eq!(upstream_hits(&mock, "player", PLAYER).await, 2);
+}
+
+#[tokio::test]
+async fn no_storage_keeps_nothing() {
+    let mock = mock().await;
+    let env = [("STORAGE", "none"), ("ADMIN_KEY", "admin"), ("PROFILE_CACHE_TTL_SECONDS", "1"), ("SECRETS_TTL_SECONDS", "1")];
+    let proxy = proxy(&mock, &env).await;
+
+    twice(&proxy).await;
+    assert!(!stored(&proxy, "profile").await);
+    assert_eq!(upstream_hits(&mock, "profiles", PLAYER).await, 2);
+    assert_eq!(upstream_hits(&mock, "player", PLAYER).await, 2);
+}
+
+#[tokio::test]
+async fn stored_profiles_expire() {
+    let mock = mock().await;
+    let env = [("STORAGE", "memory"), ("ADMIN_KEY", "admin"), ("PROFILE_CACHE_TTL_SECONDS", "0"), ("PROFILE_DB_TTL_SECONDS", "1")];
+    let proxy = proxy(&mock, &env).await;
+
+    get(&proxy, "get").await;
+    get(&proxy, "get").await;
+    assert_eq!(upstream_hits(&mock, "profiles", PLAYER).await, 1);
+
+    sleep(Duration::from_millis(2100)).await;
+    assert_eq!(storage(&proxy, "profile").await["stale"], true);
+    get(&proxy, "get").await;
+    assert_eq!(upstream_hits(&mock, "profiles", PLAYER).await, 2);
+    assert_eq!(storage(&proxy, "profile").await["stale"], false);
+}
@@ -0,0 +1,49 @@
+//! Large upstream bodies streamed to clients while they're cached.
+
+mod common;
+
+use std::time::Duration;
+
+use futures::future::join_all;
+use reqwest::StatusCode;
+use serde_json::Value;
+use tokio::time::sleep;
+
+use common::{BROKEN_STREAM, STREAMED, mock, proxy, upstream_hits};
+
+#[tokio::test]
+async fn streamed_bodies_are_shared_and_cached() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    let requests = (0..5).map(|_| async {
+        let res = reqwest::get(format!("{}/get/{STREAMED}", proxy.url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        res.bytes().await.unwrap()
+    });
+    let bodies = join_all(requests).await;
+    assert!(bodies.windows(2).all(|pair| pair[0] == pair[1]));
+
+    let json: Value = serde_json::from_slice(&bodies[0]).unwrap();
+    assert_eq!(json["profiles"].as_array().unwrap().len(), 16);
+    assert_eq!(upstream_hits(&mock, "profiles", STREAMED).await, 1);
+
+    // once the stream completes the body is served from memory.
+    let cached = reqwest::get(format!("{}/get/{STREAMED}", proxy.url)).await.unwrap().bytes().await.unwrap();
+    assert_eq!(cached, bodies[0]);
+    assert_eq!(upstream_hits(&mock, "profiles", STREAMED).await, 1);
+}
+
+#[tokio::test]
+async fn broken_streams_are_not_cached() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    for _ in 0..2 {
+        // the status has already been sent when the body breaks, so the client only sees the body cut short.
+        let res = reqwest::get(format!("{}/get/{BROKEN_STREAM}", proxy.url)).await.unwrap();
+        assert!(res.bytes().await.is_err());
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(upstream_hits(&mock, "profiles", BROKEN_STREAM).await, 2);
+}
@@ -0,0 +1,140 @@
+//! Per client rate limits, by ip address or issued api token.
+
+mod common;
+
+use std::{env, fs, process};
+
+use reqwest::{Client, Response, StatusCode};
+use serde_json::{Value, json};
+
+use common::{Server, mock, proxy};
+
+const PLAYER: &str = "5b2c9e1d-7a4f-4c3b-8e6d-2f1a0b9c8d7e";
+
+async fn issue(proxy: &Server, name: &str, ttl_days: u64) -> String {
+    let res = Client::new().post(format!("{}/tokens", proxy.url))
+        .header("Admin-Key", "admin")
+        .json(&json!({ "name": name, "tier": "standard", "ttl_days": ttl_days }))
+        .send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    res.json::<Value>().await.unwrap()["token"].as_str().unwrap().to_string()
+}
+
+async fn status(proxy: &Server, token: &str) -> StatusCode {
+    Client::new().get(format!("{}/stats", proxy.url)).header("X-Api-Token", token).send().await.unwrap().status()
+}
+
+fn header<'a>(res: &'a Response, name: &str) -> &'a str {
+    res.headers().get(name).unwrap().to_str().unwrap()
+}
+
+#[tokio::test]
+async fn anonymous_clients_are_limited_per_ip() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("RATELIMIT_BURST", "2"), ("RATELIMIT_REFRESH", "60")]).await;
+
+    let res = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(header(&res, "x-ratelimit-tier"), "anonymous");
+    assert_eq!(header(&res, "x-ratelimit-limit"), "2");
+    assert_eq!(header(&res, "x-ratelimit-remaining"), "1");
+
+    reqwest::get(format!("{}/stats", proxy.url)).await.unwrap();
+    let res = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&res, "x-ratelimit-remaining"), "0");
+    assert!(header(&res, "retry-after").parse::<u64>().unwrap() > 0);
+    let json: Value = res.json().await.unwrap();
+    assert_eq!(json["code"], "rate_limited");
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_limited_like_anonymous_clients() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("RATELIMIT_BURST", "2"), ("RATELIMIT_REFRESH", "60")]).await;
+
+    assert_eq!(status(&proxy, "made-up").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(&proxy, "made-up-too").await, StatusCode::UNAUTHORIZED);
+    let res = Client::new().get(format!("{}/stats", proxy.url)).header("X-Api-Token", "made-up").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&res, "x-ratelimit-tier"), "anonymous");
+    assert!(header(&res, "retry-after").parse::<u64>().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn tokens_have_their_own_quota_and_routes() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("ADMIN_KEY", "admin"), ("RATELIMIT_BURST", "4"), ("RATELIMIT_REFRESH", "60")]).await;
+    let client = Client::new();
+
+    let res = client.post(format!("{}/tokens", proxy.url))
+        .header("Admin-Key", "admin")
+        .json(&json!({ "name": "tests", "tier": "standard", "routes": ["get"], "burst": 4, "refresh_millis": 60000 }))
+        .send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let token = res.json::<Value>().await.unwrap()["token"].as_str().unwrap().to_string();
+
+    let res = client.get(format!("{}/get/{PLAYER}", proxy.url)).header("X-Api-Token", &token).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(header(&res, "x-ratelimit-tier"), "standard");
+    assert_eq!(header(&res, "x-ratelimit-limit"), "4");
+    // the first request goes upstream, costing more than the cached ones after it.
+    assert_eq!(header(&res, "x-ratelimit-remaining"), "1");
+
+    let res = client.get(format!("{}/secrets/{PLAYER}", proxy.url)).header("X-Api-Token", &token).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // the token's quota is separate from the anonymous one it was issued through.
+    client.get(format!("{}/get/{PLAYER}", proxy.url)).header("X-Api-Token", &token).send().await.unwrap();
+    let res = client.get(format!("{}/get/{PLAYER}", proxy.url)).header("X-Api-Token", &token).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    let res = reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client.delete(format!("{}/tokens", proxy.url)).header("Admin-Key", "admin").json(&json!({ "token": token })).send().await.unwrap();
+    assert_eq!(res.json::<Value>().await.unwrap()["revoked"], true);
+    let res = client.get(format!("{}/get/{PLAYER}", proxy.url)).header("X-Api-Token", &token).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.json::<Value>().await.unwrap()["code"], "unauthorized");
+}
+
+#[tokio::test]
+async fn revoked_tokens_stay_revoked_after_a_restart() {
+    let mock = mock().await;
+    let dir = env::temp_dir().join(format!("hypixel_api_revoke_{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let db = dir.join("db");
+    let env = [("STORAGE", "ltmdb"), ("DB_PATH", db.to_str().unwrap()), ("ADMIN_KEY", "admin")];
+
+    let first = proxy(&mock, &env).await;
+    let (kept, revoked) = (issue(&first, "kept", 30).await, issue(&first, "revoked", 30).await);
+    let res = Client::new().delete(format!("{}/tokens", first.url)).header("Admin-Key", "admin").json(&json!({ "token": revoked })).send().await.unwrap();
+    assert_eq!(res.json::<Value>().await.unwrap()["revoked"], true);
+    drop(first);
+
+    let second = proxy(&mock, &env).await;
+    assert_eq!(status(&second, &kept).await, StatusCode::OK);
+    assert_eq!(status(&second, &revoked).await, StatusCode::UNAUTHORIZED);
+
+    drop(second);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn tokens_share_storage_buckets() {
+    let mock = mock().await;
+    let dir = env::temp_dir().join(format!("hypixel_api_token_buckets_{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let env = [("STORAGE", "ltmdb"), ("DB_PATH", dir.to_str().unwrap()), ("ADMIN_KEY", "admin")];
+    let proxy = proxy(&mock, &env).await;
+
+    for (name, ttl_days) in [("first", 1), ("second", 7), ("third", 29)] {
+        issue(&proxy, name, ttl_days).await;
+    }
+    // stored for 30 days each, their own `expires_at` decides when they stop being accepted.
+    let buckets: Vec<String> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+    assert_eq!(buckets, ["2592000000"]);
+
+    drop(proxy);
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,172 @@
+//! Route, cache and single flight behavior against the mock hypixel server.
+
+mod common;
+
+use std::time::Duration;
+
+use futures::future::join_all;
+use reqwest::StatusCode;
+use serde_json::Value;
+use tokio::time::sleep;
+
+use common::{FLAKY, SERVER_ERROR, SLOW, THROTTLED, TRUNCATED, UNAVAILABLE, UNSUCCESSFUL, mock, proxy, upstream_hits};
+
+const PLAYER: &str = "f7c77d99-9f15-4a66-a87d-c4a51ef30d19";
+
+#[tokio::test]
+async fn profiles_are_served_from_cache() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    let first = reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap();
+    assert_eq!(first.status(), StatusCode::OK);
+    let first: Value = first.json().await.unwrap();
+    assert_eq!(first["profiles"][0]["profile_id"], PLAYER);
+
+    let second: Value = reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap().json().await.unwrap();
+    assert_eq!(first, second);
+    assert_eq!(upstream_hits(&mock, "profiles", PLAYER).await, 1);
+}
+
+#[tokio::test]
+async fn secrets_are_extracted_from_player() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    let res = reqwest::get(format!("{}/secrets/{PLAYER}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.text().await.unwrap(), "42");
+}
+
+#[tokio::test]
+async fn concurrent_requests_share_one_upstream_fetch() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    let requests = (0..10).map(|_| reqwest::get(format!("{}/get/{SLOW}", proxy.url)));
+    for res in join_all(requests).await {
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+    }
+    assert_eq!(upstream_hits(&mock, "profiles", SLOW).await, 1);
+
+    let stats: Value = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap().json().await.unwrap();
+    let profile = &stats["cache"]["profile"];
+    assert_eq!(profile["upstream_fetches"]["total"], 1);
+    assert_eq!(profile["single_flight_followers"]["total"].as_u64().unwrap() + profile["memory_hits"]["total"].as_u64().unwrap(), 9);
+}
+
+#[tokio::test]
+async fn upstream_errors_are_passed_through_and_not_cached() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    for _ in 0..2 {
+        let res = reqwest::get(format!("{}/get/{SERVER_ERROR}", proxy.url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    assert_eq!(upstream_hits(&mock, "profiles", SERVER_ERROR).await, 2);
+
+}
+
+#[tokio::test]
+async fn transient_errors_are_retried() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    let res = reqwest::get(format!("{}/get/{FLAKY}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(upstream_hits(&mock, "profiles", FLAKY).await, 2);
+
+    let res = reqwest::get(format!("{}/get/{UNAVAILABLE}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(upstream_hits(&mock, "profiles", UNAVAILABLE).await, 3);
+}
+
+#[tokio::test]
+async fn retry_after_past_the_budget_is_not_waited_on() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    // the mock asks to retry once its rate limit window resets, which is usually further off than the retry budget.
+    let res = reqwest::get(format!("{}/secrets/{THROTTLED}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(res.text().await.unwrap().contains("Key throttle"));
+    assert!(upstream_hits(&mock, "player", THROTTLED).await <= 2);
+}
+
+#[tokio::test]
+async fn rate_limit_headers_are_reported_in_stats() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap();
+
+    let stats: Value = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap().json().await.unwrap();
+    assert!(stats["RateLimit-Remaining"].as_u64().unwrap() > 0);
+    assert!(stats["RateLimit-Reset"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn rate_limit_history_and_forecast_are_reported() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    for uuid in ["11111111-0000-4000-8000-000000000000", "22222222-0000-4000-8000-000000000000"] {
+        reqwest::get(format!("{}/get/{uuid}", proxy.url)).await.unwrap();
+    }
+    // the forecast needs reports at least a second apart to find a rate.
+    sleep(Duration::from_millis(1100)).await;
+    reqwest::get(format!("{}/get/{PLAYER}", proxy.url)).await.unwrap();
+
+    let stats: Value = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap().json().await.unwrap();
+    assert_eq!(stats["upstream_calls"]["total"], 3);
+    assert_eq!(stats["upstream_calls"]["last_minute"], 3);
+
+    let history = stats["keys"][0]["history"].as_array().unwrap();
+    assert_eq!(history.len(), 3);
+    assert!(history.windows(2).all(|pair| pair[0]["remaining"].as_u64() >= pair[1]["remaining"].as_u64()));
+
+    let forecast = &stats["forecast"];
+    assert_eq!(forecast["remaining"], stats["RateLimit-Remaining"]);
+    assert!(forecast["used_per_minute"].as_f64().unwrap() > 0.0);
+    assert!(forecast["exhausts_before_reset"].is_boolean());
+}
+
+#[tokio::test]
+async fn revoked_keys_are_taken_out_of_rotation() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[("API_KEYS", "revoked,first-valid-key,second-valid-key")]).await;
+
+    for uuid in [PLAYER, SLOW] {
+        let res = reqwest::get(format!("{}/get/{uuid}", proxy.url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    let stats: Value = reqwest::get(format!("{}/stats", proxy.url)).await.unwrap().json().await.unwrap();
+    let keys = stats["keys"].as_array().unwrap();
+    assert_eq!(keys[0]["in_rotation"], false);
+    // unused keys are preferred, so the two requests should have been spread across both valid keys.
+    assert!(keys[1]["RateLimit-Remaining"].is_u64());
+    assert!(keys[2]["RateLimit-Remaining"].is_u64());
+    assert_eq!(keys[2]["key"], "...-key");
+}
+
+#[tokio::test]
+async fn invalid_payloads_are_not_cached() {
+    let mock = mock().await;
+    let proxy = proxy(&mock, &[]).await;
+
+    for _ in 0..2 {
+        let res = reqwest::get(format!("{}/get/{UNSUCCESSFUL}", proxy.url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        assert!(res.text().await.unwrap().contains("Mock failure"));
+    }
+    assert_eq!(upstream_hits(&mock, "profiles", UNSUCCESSFUL).await, 2);
+
+    let res = reqwest::get(format!("{}/secrets/{UNSUCCESSFUL}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+
+    let res = reqwest::get(format!("{}/get/{TRUNCATED}", proxy.url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    assert!(res.text().await.unwrap().contains("Malformed response"));
+}